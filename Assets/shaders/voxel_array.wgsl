@@ -0,0 +1,43 @@
+#import bevy_pbr::mesh_functions::{get_model_matrix, mesh_position_local_to_clip}
+#import bevy_pbr::mesh_view_bindings::globals
+
+@group(1) @binding(0) var array_texture: texture_2d_array<f32>;
+@group(1) @binding(1) var array_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) colour: vec4<f32>,
+    @location(3) layer: u32,
+    //frame count, seconds per frame, layers between frames
+    @location(4) animation: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) colour: vec4<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(get_model_matrix(vertex.instance_index), vec4<f32>(vertex.position, 1.0));
+    out.uv = vertex.uv;
+    out.colour = vertex.colour;
+    //the frames of an animated texture are the layers after its first one
+    var frame = 0u;
+    if vertex.animation.y > 0.0 {
+        frame = u32(globals.time / vertex.animation.y) % max(u32(vertex.animation.x), 1u);
+    }
+    out.layer = vertex.layer + frame * u32(vertex.animation.z);
+    return out;
+}
+
+//unlit like the emissive atlas material
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(array_texture, array_sampler, in.uv, in.layer) * in.colour;
+}
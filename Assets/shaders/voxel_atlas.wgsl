@@ -0,0 +1,40 @@
+#import bevy_pbr::mesh_functions::{get_model_matrix, mesh_position_local_to_clip}
+#import bevy_pbr::mesh_view_bindings::globals
+
+@group(1) @binding(0) var atlas_texture: texture_2d<f32>;
+@group(1) @binding(1) var atlas_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) colour: vec4<f32>,
+    //frame count, seconds per frame, frame height in uv
+    @location(3) animation: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) colour: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(get_model_matrix(vertex.instance_index), vec4<f32>(vertex.position, 1.0));
+    //the uvs point at the first frame of an animated strip, the others follow below it
+    var frame = 0u;
+    if vertex.animation.y > 0.0 {
+        frame = u32(globals.time / vertex.animation.y) % max(u32(vertex.animation.x), 1u);
+    }
+    out.uv = vertex.uv + vec2<f32>(0.0, f32(frame) * vertex.animation.z);
+    out.colour = vertex.colour;
+    return out;
+}
+
+//unlit like the array material
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(atlas_texture, atlas_sampler, in.uv) * in.colour;
+}
@@ -0,0 +1,84 @@
+use crate::database::settings::*;
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, Seedable};
+
+//temperature noise, the same seed always gives the same climate
+#[derive(Clone)]
+pub struct Climate
+{
+    noise: Fbm<Perlin>,
+}
+
+impl Default for Climate
+{
+    fn default() -> Self
+    {
+        Climate
+        {
+            noise: Fbm::<Perlin>::default().set_seed(CLIMATE_SEED).set_octaves(3),
+        }
+    }
+}
+
+impl Climate
+{
+    //rough temperature around -1 to 1 at a world position, it gets colder with altitude on high ground
+    pub fn temperature(&self, position: IVec3) -> f64
+    {
+        let base = self.noise.get([position.x as f64 * 0.001, position.z as f64 * 0.001]);
+        base - (position.y - SEA_LEVEL - 90).max(0) as f64 * 0.02
+    }
+
+    //snow settles and water freezes below this
+    pub fn is_cold(&self, position: IVec3) -> bool
+    {
+        self.temperature(position) < -0.2
+    }
+}
+
+//rough biome of a column, worked out from the generated surface and the climate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Biome
+{
+    #[default]
+    Ocean,
+    Beach,
+    Plains,
+    Mountains,
+    Snowy,
+}
+
+impl Biome
+{
+    //surface_height is the top of the terrain without fluids, underwater is set when water covers it
+    pub fn classify(climate: &Climate, surface: IVec3, underwater: bool) -> Biome
+    {
+        if underwater
+        {
+            return Biome::Ocean;
+        }
+        if climate.is_cold(surface)
+        {
+            return Biome::Snowy;
+        }
+        match surface.y - SEA_LEVEL
+        {
+            height if height <= 2 => Biome::Beach,
+            height if height >= 80 => Biome::Mountains,
+            _ => Biome::Plains,
+        }
+    }
+
+    //colour used on biome maps
+    pub fn colour(self) -> [u8; 3]
+    {
+        match self
+        {
+            Biome::Ocean => [40, 70, 180],
+            Biome::Beach => [220, 210, 150],
+            Biome::Plains => [90, 170, 70],
+            Biome::Mountains => [130, 120, 110],
+            Biome::Snowy => [240, 245, 250],
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::database::chunk::voxel_index;
+use crate::database::edit::*;
+use crate::database::events::*;
+use crate::database::sector::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+//the most voxels a shaped operation may cover, the same bound schematics have
+pub const MAX_BULK_VOLUME: usize = 256 * 256 * 256;
+
+//a shape of voxels to change in one go. positions are in voxels relative to the sector origin
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkOperation
+{
+    //sets a list of voxels, like a batch of single edits
+    Set
+    {
+        voxels: Vec<(IVec3, Voxel)>,
+    },
+    //replaces everything inside the sphere with air
+    CarveSphere
+    {
+        center: IVec3,
+        radius: f32,
+    },
+    //sets every voxel in the box, min and max are inclusive
+    FillBox
+    {
+        min: IVec3,
+        max: IVec3,
+        voxel: Voxel,
+    },
+    //swaps every voxel of one type in the box for another voxel
+    Replace
+    {
+        min: IVec3,
+        max: IVec3,
+        from: VoxelType,
+        to: Voxel,
+    },
+    //rays from the center lose power to every voxel they pass, voxels are destroyed until a ray runs out
+    Explosion
+    {
+        center: Vec3,
+        power: f32,
+    },
+}
+
+//request to run a bulk operation against the loaded chunks
+#[derive(Event, Debug, Clone)]
+pub struct BulkEdit
+{
+    pub sector: [i32; 3],
+    pub operation: BulkOperation,
+}
+
+//sent after a bulk operation was applied, carries every voxel it changed
+//apply_voxel_edits also sends one for each sector's undoable single edits of a frame
+#[derive(Event, Debug, Clone)]
+pub struct BulkEditApplied
+{
+    pub diff: VoxelDiff,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelDelta
+{
+    pub position: IVec3,
+    pub old: Voxel,
+    pub new: Voxel,
+}
+
+//every voxel an edit changed with its old and new state, enough to undo the edit or send it over the network
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoxelDiff
+{
+    pub sector: [i32; 3],
+    pub changes: Vec<VoxelDelta>,
+}
+
+impl VoxelDiff
+{
+    //diff that turns the new voxels back into the old ones
+    pub fn inverse(&self) -> VoxelDiff
+    {
+        VoxelDiff
+        {
+            sector: self.sector,
+            changes: self.changes.iter().rev().map(|delta| VoxelDelta { position: delta.position, old: delta.new.clone(), new: delta.old.clone() }).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.changes.is_empty()
+    }
+}
+
+//how much explosion power a voxel soaks up, fluids stop explosions entirely
+pub fn blast_resistance(voxel_type: VoxelType) -> f32
+{
+    match voxel_type
+    {
+        VoxelType::Air => 0.0,
+        VoxelType::Snow => 0.1,
+        VoxelType::Glass | VoxelType::Ice => 0.3,
+        VoxelType::Netherack => 0.4,
+        VoxelType::Dirt | VoxelType::Grass | VoxelType::JungleGrass | VoxelType::SavannahGrass | VoxelType::SwampGrass
+        | VoxelType::Sand | VoxelType::RedSand | VoxelType::Ash | VoxelType::Salt | VoxelType::SnowBlock => 0.5,
+        VoxelType::Pine | VoxelType::Forest | VoxelType::Coral => 2.0,
+        VoxelType::Stone | VoxelType::Brick | VoxelType::StoneBrick | VoxelType::Sulfur => 6.0,
+        VoxelType::Copper | VoxelType::Aluminum | VoxelType::Arsenic | VoxelType::Actinium | VoxelType::Antimony => 10.0,
+        VoxelType::Water | VoxelType::Lava => 100.0,
+        _ => 3.0,
+    }
+}
+
+fn invalid(message: &str) -> Error
+{
+    Error::new(ErrorKind::InvalidInput, message.to_string())
+}
+
+//an error if the inclusive box holds more than MAX_BULK_VOLUME voxels, worked out in i64 so it cannot overflow
+fn check_region(min: IVec3, max: IVec3) -> std::io::Result<()>
+{
+    let size = (0..3).map(|axis| (min[axis] as i64 - max[axis] as i64).abs() + 1);
+    let volume = size.fold(1i64, |volume, length| volume.saturating_mul(length));
+    if volume > MAX_BULK_VOLUME as i64
+    {
+        return Err(invalid("bulk edit region is too big"));
+    }
+    Ok(())
+}
+
+//inclusive box around a center reaching extent voxels out, an error if the extent is not positive or the box too big
+fn region_around(center: IVec3, extent: f32) -> std::io::Result<(IVec3, IVec3)>
+{
+    if !(extent > 0.0 && extent.is_finite())
+    {
+        return Err(invalid("bulk edit size has to be positive"));
+    }
+    if (extent.ceil() * 2.0 + 1.0).powi(3) > MAX_BULK_VOLUME as f32
+    {
+        return Err(invalid("bulk edit region is too big"));
+    }
+    let extent = IVec3::splat(extent.ceil() as i32);
+    Ok((center.saturating_sub(extent), center.saturating_add(extent)))
+}
+
+//works out the new voxel for every position the operation touches, positions in unloaded chunks are left out
+//an error if the shape is empty or covers more than MAX_BULK_VOLUME voxels
+pub fn plan_bulk_operation(world: &impl VoxelAccess, operation: &BulkOperation) -> std::io::Result<Vec<(IVec3, Voxel)>>
+{
+    let mut changes = Vec::new();
+    match operation
+    {
+        BulkOperation::Set { voxels } =>
+        {
+            for (position, voxel) in voxels
+            {
+                if world.voxel(*position).is_some_and(|old| old != voxel)
+                {
+                    changes.push((*position, voxel.clone()));
+                }
+            }
+        }
+        BulkOperation::CarveSphere { center, radius } =>
+        {
+            let (min, max) = region_around(*center, *radius)?;
+            for position in box_positions(min, max)
+            {
+                let inside = (position - *center).as_vec3().length_squared() <= radius * radius;
+                if inside && world.voxel(position).is_some_and(|voxel| voxel.voxel_type != VoxelType::Air)
+                {
+                    changes.push((position, Voxel::default()));
+                }
+            }
+        }
+        BulkOperation::FillBox { min, max, voxel } =>
+        {
+            check_region(*min, *max)?;
+            for position in box_positions(*min, *max)
+            {
+                if world.voxel(position).is_some_and(|old| old != voxel)
+                {
+                    changes.push((position, voxel.clone()));
+                }
+            }
+        }
+        BulkOperation::Replace { min, max, from, to } =>
+        {
+            check_region(*min, *max)?;
+            for position in box_positions(*min, *max)
+            {
+                if world.voxel(position).is_some_and(|old| old.voxel_type == *from && old != to)
+                {
+                    changes.push((position, to.clone()));
+                }
+            }
+        }
+        BulkOperation::Explosion { center, power } =>
+        {
+            //rays go out through every cell on the surface of a 16 cube like minecraft, without the random power so results are repeatable
+            const RAYS: i32 = 16;
+            const STEP: f32 = 0.3;
+            //every step costs at least STEP * STEP power, so no ray gets further than power / STEP
+            region_around(center.floor().as_ivec3(), power / STEP)?;
+            let mut destroyed = HashSet::new();
+            for x in 0..RAYS
+            {
+                for y in 0..RAYS
+                {
+                    for z in 0..RAYS
+                    {
+                        let on_surface = [x, y, z].iter().any(|axis| *axis == 0 || *axis == RAYS - 1);
+                        if !on_surface
+                        {
+                            continue;
+                        }
+                        let direction = (Vec3::new(x as f32, y as f32, z as f32) / (RAYS - 1) as f32 * 2.0 - Vec3::ONE).normalize();
+                        let mut intensity = *power;
+                        let mut point = *center;
+                        while intensity > 0.0
+                        {
+                            let position = point.floor().as_ivec3();
+                            let Some(voxel) = world.voxel(position) else { break };
+                            intensity -= (blast_resistance(voxel.voxel_type) + STEP) * STEP;
+                            if intensity > 0.0 && voxel.voxel_type != VoxelType::Air && !voxel.is_fluid()
+                            {
+                                destroyed.insert(position);
+                            }
+                            point += direction * STEP;
+                        }
+                    }
+                }
+            }
+            let mut destroyed: Vec<IVec3> = destroyed.into_iter().collect();
+            destroyed.sort_by_key(|position| position.to_array());
+            changes.extend(destroyed.into_iter().map(|position| (position, Voxel::default())));
+        }
+    }
+    Ok(changes)
+}
+
+//every position in an inclusive box
+fn box_positions(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3>
+{
+    let (low, high) = (min.min(max), min.max(max));
+    (low.x..=high.x).flat_map(move |x| (low.y..=high.y).flat_map(move |y| (low.z..=high.z).map(move |z| IVec3::new(x, y, z))))
+}
+
+//writes many voxels at once. each chunk is copied at most once and marked dirty once no matter how many voxels in it change
+pub fn apply_voxel_changes(loaded_sectors: &mut CurrentlyLoaded, dirty_chunks: &mut DirtyChunks, sector: [i32; 3], changes: Vec<(IVec3, Voxel)>) -> VoxelDiff
+{
+    let mut diff = VoxelDiff { sector, changes: Vec::with_capacity(changes.len()) };
+    let Some(loaded_sector) = loaded_sectors.sectors.get_mut(&sector) else { return diff };
+    let mut by_chunk: HashMap<[i32; 3], Vec<(IVec3, Voxel)>> = HashMap::new();
+    for (position, voxel) in changes
+    {
+        by_chunk.entry(voxel_to_chunk(position).0).or_default().push((position, voxel));
+    }
+    for (chunk, voxels) in by_chunk
+    {
+        let Some(Some(chunk_data)) = loaded_sector.chunks.get_mut(&chunk) else { continue };
+        let chunk_data = Arc::make_mut(chunk_data);
+        for (position, voxel) in voxels
+        {
+            let local = voxel_to_chunk(position).1;
+            let old = std::mem::replace(&mut chunk_data.block_data[voxel_index(local[0], local[1], local[2])], voxel.clone());
+            mark_dirty_with_neighbours(sector, position, dirty_chunks);
+            diff.changes.push(VoxelDelta { position, old, new: voxel });
+        }
+    }
+    diff
+}
+
+//runs after apply_voxel_edits so single edits from the same frame are already in place
+pub fn apply_bulk_edits
+(
+    mut bulk_edits: EventReader<BulkEdit>,
+    mut loaded_sectors: ResMut<CurrentlyLoaded>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut voxel_changed: EventWriter<VoxelChanged>,
+    mut bulk_applied: EventWriter<BulkEditApplied>,
+)
+{
+    for bulk_edit in bulk_edits.read()
+    {
+        let Some(loaded_sector) = loaded_sectors.sectors.get(&bulk_edit.sector) else { continue };
+        let changes = match plan_bulk_operation(loaded_sector, &bulk_edit.operation)
+        {
+            Ok(changes) => changes,
+            Err(error) =>
+            {
+                warn!("bulk edit skipped: {}", error);
+                continue;
+            }
+        };
+        let diff = apply_voxel_changes(&mut loaded_sectors, &mut dirty_chunks, bulk_edit.sector, changes);
+        if diff.is_empty()
+        {
+            continue;
+        }
+        for delta in &diff.changes
+        {
+            let (chunk, local) = voxel_to_chunk(delta.position);
+            voxel_changed.send(VoxelChanged { sector: diff.sector, chunk, voxel: local, old: delta.old.clone(), new: delta.new.clone() });
+        }
+        bulk_applied.send(BulkEditApplied { diff });
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::settings::CHUNK_SIZE;
+
+    fn block(voxel_type: VoxelType) -> Voxel
+    {
+        Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    //chunks -1..=1 on every axis, filled with one voxel type below y 0 and the given voxels placed on top
+    fn loaded(ground: VoxelType, voxels: &[(IVec3, Voxel)]) -> CurrentlyLoaded
+    {
+        let mut world = test_world([-1, -1, -1], [1, 1, 1], voxels);
+        for (chunk_id, chunk) in world.iter_mut()
+        {
+            if chunk_id[1] < 0
+            {
+                chunk.block_data.fill(block(ground));
+            }
+        }
+        let chunks = world.into_iter().map(|(chunk_id, chunk)| (chunk_id, Some(Arc::new(chunk)))).collect();
+        let mut loaded = CurrentlyLoaded::default();
+        loaded.sectors.insert([0, 0, 0], SectorsRendering { chunks });
+        loaded
+    }
+
+    fn run(loaded: &mut CurrentlyLoaded, dirty_chunks: &mut DirtyChunks, operation: BulkOperation) -> VoxelDiff
+    {
+        let changes = plan_bulk_operation(&loaded.sectors[&[0, 0, 0]], &operation).unwrap();
+        apply_voxel_changes(loaded, dirty_chunks, [0, 0, 0], changes)
+    }
+
+    fn voxel(loaded: &CurrentlyLoaded, position: IVec3) -> &Voxel
+    {
+        loaded.sectors[&[0, 0, 0]].voxel(position).unwrap()
+    }
+
+    #[test]
+    fn marks_every_touched_chunk_dirty_once()
+    {
+        let mut loaded = loaded(VoxelType::Stone, &[]);
+        let mut dirty_chunks = DirtyChunks::default();
+        //inside chunk 0,0,0 away from its faces, thousands of voxels but one remesh
+        let diff = run(&mut loaded, &mut dirty_chunks, BulkOperation::FillBox { min: IVec3::splat(4), max: IVec3::splat(20), voxel: block(VoxelType::Brick) });
+        assert_eq!(diff.changes.len(), 17 * 17 * 17);
+        assert_eq!(dirty_chunks.chunks, HashSet::from([([0, 0, 0], [0, 0, 0])]));
+        //a box across the x border touches both chunks, the voxels on the shared face also wake the chunk across it
+        let mut dirty_chunks = DirtyChunks::default();
+        run(&mut loaded, &mut dirty_chunks, BulkOperation::FillBox { min: IVec3::new(28, 4, 4), max: IVec3::new(35, 8, 8), voxel: block(VoxelType::Dirt) });
+        assert_eq!(dirty_chunks.chunks, HashSet::from([([0, 0, 0], [0, 0, 0]), ([0, 0, 0], [1, 0, 0])]));
+    }
+
+    #[test]
+    fn diff_holds_old_and_new_voxels_and_inverts()
+    {
+        let mut loaded = loaded(VoxelType::Stone, &[(IVec3::new(2, 0, 2), block(VoxelType::Dirt))]);
+        let mut dirty_chunks = DirtyChunks::default();
+        let diff = run(&mut loaded, &mut dirty_chunks, BulkOperation::Replace { min: IVec3::new(0, -2, 0), max: IVec3::new(3, 0, 3), from: VoxelType::Stone, to: block(VoxelType::Sand) });
+        assert_eq!(diff.changes.len(), 4 * 2 * 4);
+        assert!(diff.changes.iter().all(|delta| delta.old == block(VoxelType::Stone) && delta.new == block(VoxelType::Sand)));
+        assert_eq!(voxel(&loaded, IVec3::new(2, 0, 2)).voxel_type, VoxelType::Dirt);
+        //nothing left to replace, so nothing changes
+        assert!(run(&mut loaded, &mut dirty_chunks, BulkOperation::Replace { min: IVec3::new(0, -2, 0), max: IVec3::new(3, 0, 3), from: VoxelType::Stone, to: block(VoxelType::Sand) }).is_empty());
+
+        let inverse = diff.inverse().changes.into_iter().map(|delta| (delta.position, delta.new)).collect();
+        apply_voxel_changes(&mut loaded, &mut dirty_chunks, [0, 0, 0], inverse);
+        assert_eq!(voxel(&loaded, IVec3::new(1, -1, 1)).voxel_type, VoxelType::Stone);
+    }
+
+    #[test]
+    fn carves_a_sphere()
+    {
+        let mut loaded = loaded(VoxelType::Stone, &[]);
+        let mut dirty_chunks = DirtyChunks::default();
+        let diff = run(&mut loaded, &mut dirty_chunks, BulkOperation::CarveSphere { center: IVec3::new(0, -8, 0), radius: 3.0 });
+        assert!(diff.changes.iter().all(|delta| delta.position.distance_squared(IVec3::new(0, -8, 0)) <= 9 && delta.new == Voxel::default()));
+        assert_eq!(voxel(&loaded, IVec3::new(0, -5, 0)).voxel_type, VoxelType::Air);
+        assert_eq!(voxel(&loaded, IVec3::new(0, -4, 0)).voxel_type, VoxelType::Stone);
+        assert_eq!(voxel(&loaded, IVec3::new(2, -6, 2)).voxel_type, VoxelType::Stone);
+    }
+
+    #[test]
+    fn refuses_empty_and_oversized_shapes()
+    {
+        let loaded = loaded(VoxelType::Stone, &[]);
+        let world = &loaded.sectors[&[0, 0, 0]];
+        for radius in [-3.0, 0.0, f32::NAN, f32::INFINITY, 1000.0]
+        {
+            assert!(plan_bulk_operation(world, &BulkOperation::CarveSphere { center: IVec3::ZERO, radius }).is_err());
+        }
+        let huge = BulkOperation::FillBox { min: IVec3::splat(i32::MIN), max: IVec3::splat(i32::MAX), voxel: Voxel::default() };
+        assert!(plan_bulk_operation(world, &huge).is_err());
+        let huge = BulkOperation::Replace { min: IVec3::ZERO, max: IVec3::splat(300), from: VoxelType::Stone, to: Voxel::default() };
+        assert!(plan_bulk_operation(world, &huge).is_err());
+        for power in [-1.0, 0.0, f32::NAN, 1000.0]
+        {
+            assert!(plan_bulk_operation(world, &BulkOperation::Explosion { center: Vec3::ZERO, power }).is_err());
+        }
+        let largest = BulkOperation::FillBox { min: IVec3::ZERO, max: IVec3::splat(255), voxel: Voxel::default() };
+        assert!(plan_bulk_operation(world, &largest).is_ok());
+    }
+
+    //voxels an explosion of some power destroys in ground of one type
+    fn crater(ground: VoxelType, power: f32) -> usize
+    {
+        let loaded = loaded(ground, &[]);
+        plan_bulk_operation(&loaded.sectors[&[0, 0, 0]], &BulkOperation::Explosion { center: Vec3::new(0.5, -CHUNK_SIZE as f32 / 2.0, 0.5), power }).unwrap().len()
+    }
+
+    #[test]
+    fn blast_resistance_depends_on_the_voxel_type()
+    {
+        let (snow, dirt, stone, copper) = (crater(VoxelType::Snow, 4.0), crater(VoxelType::Dirt, 4.0), crater(VoxelType::Stone, 4.0), crater(VoxelType::Copper, 4.0));
+        assert!(snow > dirt && dirt > stone && stone > copper, "{} {} {} {}", snow, dirt, stone, copper);
+        assert!(crater(VoxelType::Stone, 8.0) > stone);
+        //fluids soak up everything and are never destroyed
+        assert_eq!(crater(VoxelType::Water, 4.0), 0);
+    }
+
+    #[test]
+    fn explosions_are_repeatable()
+    {
+        let loaded = loaded(VoxelType::Dirt, &[]);
+        let operation = BulkOperation::Explosion { center: Vec3::new(3.2, -2.7, 1.1), power: 4.0 };
+        let world = &loaded.sectors[&[0, 0, 0]];
+        assert_eq!(plan_bulk_operation(world, &operation).unwrap(), plan_bulk_operation(world, &operation).unwrap());
+    }
+}
@@ -3,7 +3,7 @@ use crate::database::texture::*;
 use crate::database::structs::AppState;
 use crate::database::voxel::*;
 use crate::database::example_chunk::STONE_CHUNK;
-use crate::database::settings::{CHUNK_SIZE, DENSITY_MOD, SEA_LEVEL};
+use crate::database::settings::{CHUNK_SIZE, DENSITY_MOD, SEA_LEVEL, WORLD_SEED};
 use rand::prelude::*;
 use std::sync::Arc;
 use std::thread::current;
@@ -20,12 +20,16 @@ use bevy::{
 };
 use splines::{Interpolation, Key, Spline};
 use futures_lite::{future, StreamExt};
-use bevy::render::mesh::PrimitiveTopology;
-use bevy::render::mesh;
 use bevy::utils::HashMap;
 use bevy::utils::label::DynEq;
 use noise::{NoiseFn, Perlin, Seedable, Fbm, MultiFractal};
 use crate::database::sector::*;
+use crate::database::events::*;
+use crate::database::edit::{DirtyChunks, VoxelAccess};
+use crate::database::mesher::*;
+use crate::database::model::*;
+use crate::database::texture_array::VoxelArrayMaterial;
+use crate::database::fluid::fluid_corner_heights;
 
 
 
@@ -46,370 +50,153 @@ pub struct ChunkEntitys
 #[derive(Component)]
 pub struct GenerateChunk(pub Task<Chunk>);
 
-#[derive(Default, Debug, Clone)]
-pub struct RenderData
+//sector and chunk a chunk entity belongs to
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkCoords
 {
-    vertices: Vec<[f32; 3]>,
-    indices: Vec<u32>,
-    uvs: Vec<[f32; 2]>,
-    chunk_position: [i32; 3],
-    chunk_data: Chunk,
-    neighbours: HashMap<[i8; 3], Arc<Option<RenderData>>>,
+    pub sector: [i32; 3],
+    pub chunk: [i32; 3],
 }
 
 #[derive(Component)]
-pub struct ComputeChunk(Task<RenderData>);
-
-#[derive(Component)]
-pub struct UpdateChunk(Task<RenderData>);
+pub struct UpdateChunk(pub Task<ChunkMesh>);
 
 #[derive(Component)]
-pub struct SpawnChunk(Task<RenderData>);
+pub struct SpawnChunk(pub Task<ChunkMesh>);
 
+//immutable snapshot of a chunk and the 26 chunks around it, handed to meshing tasks instead of the whole loaded world
+#[derive(Default, Debug, Clone)]
+pub struct ChunkNeighbourhood
+{
+    //indexed by (x+1)*9 + (y+1)*3 + (z+1) for offsets -1..=1, index 13 is the center chunk
+    chunks: [Option<Arc<Chunk>>; 27],
+}
 
-pub fn chunk_to_render
-(
-    chunk: Chunk,
-    loaded_chunks: CurrentlyLoaded,
-) -> RenderData
+impl ChunkNeighbourhood
 {
-    let mut render_data = RenderData::default();
-    //check all 6 neighbouring chunks to see if there are any chunks currently loaded that can be added.
-    //get sector
-    if let Some(sector) = loaded_chunks.sectors.get(&[0,0,0])
+    //None if the center chunk has not finished generating
+    pub fn from_loaded(sector: &SectorsRendering, position: [i32; 3]) -> Option<ChunkNeighbourhood>
     {
-        //get chunk [x+1,y,z]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE+1,chunk.pos.1/CHUNK_SIZE,chunk.pos.2/CHUNK_SIZE];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
-        {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
-            {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
-            }
-        }
-        //get chunk [x-1,y,z]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE-1,chunk.pos.1/CHUNK_SIZE,chunk.pos.2/CHUNK_SIZE];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
-        {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
-            {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
-            }
-        }
-        //get chunk [x,y+1,z]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE,chunk.pos.1/CHUNK_SIZE+1,chunk.pos.2/CHUNK_SIZE];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
-        {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
-            {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
-            }
-        }
-        //get chunk [x,y-1,z]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE,chunk.pos.1/CHUNK_SIZE-1,chunk.pos.2/CHUNK_SIZE];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
-        {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
-            {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
-            }
-        }
-        //get chunk [x,y,z+1]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE,chunk.pos.1/CHUNK_SIZE,chunk.pos.2/CHUNK_SIZE+1];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
+        let mut neighbourhood = ChunkNeighbourhood::default();
+        for x in -1..=1
         {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
+            for y in -1..=1
             {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
+                for z in -1..=1
+                {
+                    if let Some(Some(chunk)) = sector.chunks.get(&[position[0]+x, position[1]+y, position[2]+z])
+                    {
+                        neighbourhood.chunks[((x+1)*9 + (y+1)*3 + (z+1)) as usize] = Some(chunk.clone());
+                    }
+                }
             }
         }
-        //get chunk [x,y,z-1]
-        let mut key = &[chunk.pos.0/CHUNK_SIZE,chunk.pos.1/CHUNK_SIZE,chunk.pos.2/CHUNK_SIZE-1];
-        if !render_data.neighbours.contains_key(&[key.clone()[0] as i8,key.clone()[1] as i8,key.clone()[2] as i8])
+        neighbourhood.chunks[13].is_some().then_some(neighbourhood)
+    }
+
+    //just the center chunk, faces against the missing neighbours are not drawn
+    pub fn single(chunk: Arc<Chunk>) -> ChunkNeighbourhood
+    {
+        let mut neighbourhood = ChunkNeighbourhood::default();
+        neighbourhood.chunks[13] = Some(chunk);
+        neighbourhood
+    }
+
+    pub fn center(&self) -> &Arc<Chunk>
+    {
+        self.chunks[13].as_ref().unwrap()
+    }
+
+}
+
+impl VoxelAccess for ChunkNeighbourhood
+{
+    //voxel at a position local to the center chunk, may be up to one chunk outside of it. None if that chunk is not loaded
+    fn voxel(&self, position: IVec3) -> Option<&Voxel>
+    {
+        let chunk_x = position.x.div_euclid(CHUNK_SIZE);
+        let chunk_y = position.y.div_euclid(CHUNK_SIZE);
+        let chunk_z = position.z.div_euclid(CHUNK_SIZE);
+        if chunk_x.abs() > 1 || chunk_y.abs() > 1 || chunk_z.abs() > 1
         {
-            if let Some(neighbour) = sector.chunks.get(&key.clone())
-            {
-                render_data.neighbours.insert([key[0] as i8,key[1] as i8,key[2] as i8], neighbour.clone());
-            }
+            return None;
         }
+        let chunk = self.chunks[((chunk_x+1)*9 + (chunk_y+1)*3 + (chunk_z+1)) as usize].as_ref()?;
+        chunk.block_data.get(voxel_index(position.x.rem_euclid(CHUNK_SIZE), position.y.rem_euclid(CHUNK_SIZE), position.z.rem_euclid(CHUNK_SIZE)))
     }
-    //render data stuff... moved to another thread for the purpose of locking threads until they have all neighbours available to check
-    return render_data;
 }
 
+//index into Chunk::block_data for a position local to the chunk
+pub fn voxel_index(x: i32, y: i32, z: i32) -> usize
+{
+    (x * CHUNK_SIZE * CHUNK_SIZE + y * CHUNK_SIZE + z) as usize
+}
+
+//the block model of every voxel the filter lets through. faces with a cullface are only drawn against neighbours that
+//are not solid, so voxels left out by the filter still hide the faces next to them
 pub fn render_update
 (
-    mut render_data: RenderData,
-    texture_info: TextureInfo,
-    loaded_chunks: CurrentlyLoaded,
-) -> RenderData
+    neighbourhood: &ChunkNeighbourhood,
+    texture_info: &TextureInfo,
+    block_models: &BlockModels,
+    filter: &VoxelFilter,
+) -> ChunkMesh
 {
-    let chunk_size_squared = CHUNK_SIZE * CHUNK_SIZE;
+    let chunk_data = neighbourhood.center().clone();
+    let chunk_origin = Vec3::new(chunk_data.pos.0 as f32, chunk_data.pos.1 as f32, chunk_data.pos.2 as f32);
+    let mut render_data = ChunkMesh::default();
 
-    for(index, voxel) in render_data.chunk_data.block_data.iter().enumerate()
+    for (index, voxel) in chunk_data.block_data.iter().enumerate()
     {
-        let rotation = VOXEL_ROTATIONS[voxel.voxel_rotation as usize].clone();
-        let z = index as f32 % CHUNK_SIZE as f32;
-        let y = ((index as f32 - z) / CHUNK_SIZE as f32) % CHUNK_SIZE as f32;
-        let x = (index as f32 - z - CHUNK_SIZE as f32 * y) / chunk_size_squared as f32;
-        //sides
         //voxel 0 is air so we dont want to draw it
-        if voxel.voxel_type != VoxelType::Air
+        if voxel.voxel_type == VoxelType::Air || !filter.contains(voxel.voxel_type)
         {
-            //p for side iter
-            let mut faces = FACES.clone();
-            for i in 0..faces.len()
+            continue;
+        }
+        let index = index as i32;
+        let position = IVec3::new(index / (CHUNK_SIZE * CHUNK_SIZE), index / CHUNK_SIZE % CHUNK_SIZE, index % CHUNK_SIZE);
+        //neighbouring voxels outside of the chunk are looked up in the neighbour snapshot
+        let shape = stair_shape(neighbourhood, position, voxel);
+        let orientation = voxel.orientation(shape);
+        let neighbour = |face: ModelFace| neighbourhood.voxel(position + orientation.direction(face.normal()));
+        //fluid surfaces slope between the levels of the voxels around them
+        let top = if voxel.is_fluid() { fluid_corner_heights(neighbourhood, position, voxel.voxel_type) } else { [[1.0; 2]; 2] };
+        let uv_rect = texture_info.uv_rect(voxel.voxel_type);
+        for quad in &block_models.for_voxel(voxel, shape).quads
+        {
+            if quad.connect.is_some_and(|face| !neighbour(face).is_some_and(|neighbour| connects(voxel, neighbour)))
             {
-                if rotation.switch
-                {
-                    faces[i] = [faces[i][2] * rotation.values[0], faces[i][1], faces[i][0] * rotation.values[1]];
-                }else
-                {
-                    faces[i] = [faces[i][0] * rotation.values[0], faces[i][1], faces[i][2] * rotation.values[1]];
-                }
+                continue;
             }
-            for p in 0 .. 6
+            //faces against chunks that are not loaded yet are not drawn, the chunk gets remeshed once they load
+            if quad.cullface.is_some_and(|face| neighbour(face).is_none_or(|neighbour| neighbour.solid))
             {
-                let neighbour_index = ((x + faces[p][0]) * chunk_size_squared as f32) + ((y + faces[p][1]) * CHUNK_SIZE as f32) + (z + faces[p][2]);
-                //makes sure that neighbouring voxels are inside of the bounds of the chunk as to avoid errors
-                if (x + faces[p][0]) >= 0. && (x + faces[p][0]) < CHUNK_SIZE as f32 && (y + faces[p][1]) >= 0. && (y + faces[p][1]) < CHUNK_SIZE as f32 && (z + faces[p][2]) >= 0. && (z + faces[p][2]) < CHUNK_SIZE as f32 {
-                    //if the p side does not have a voxel then draw the face.
-                    if render_data.chunk_data.block_data[neighbour_index as usize].solid == false {
-                        if voxel.voxel_variant == VoxelVariant::Block
-                        {
-                            //i represents the corners of the triangle. 3 per triangle. 2 triangles per face
-                            for i in 0 .. 6 {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(BLOCK_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(BLOCK_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                            }
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.min.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.min.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.min.y]);
-                        }
-                        if voxel.voxel_variant == VoxelVariant::Slab
-                        {
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            for i in 0 .. 6 {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((SLAB_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(SLAB_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((SLAB_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((SLAB_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(SLAB_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((SLAB_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                                render_data.uvs.push([uv_rect.min.x+(uv_rect.max.x-uv_rect.min.x)*SLAB_UVS[p][i].x,uv_rect.min.y+(uv_rect.max.y-uv_rect.min.y)*SLAB_UVS[p][i].y]);
-                            }
-                        }
-                        if voxel.voxel_variant == VoxelVariant::Stair
-                        {
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            for i in 0 .. STAIR_TRIS[p].len() {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((STAIR_VERTS[STAIR_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(STAIR_VERTS[STAIR_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((STAIR_VERTS[STAIR_TRIS[p][i]][0]-0.5)*rotation.values[1] + 0.5 + z as f32) + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((STAIR_VERTS[STAIR_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(STAIR_VERTS[STAIR_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((STAIR_VERTS[STAIR_TRIS[p][i]][2]-0.5)*rotation.values[1] + 0.5 + z as f32) + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                                render_data.uvs.push([uv_rect.min.x+(uv_rect.max.x-uv_rect.min.x)*STAIR_UVS[p][i].x,uv_rect.min.y+(uv_rect.max.y-uv_rect.min.y)*STAIR_UVS[p][i].y]);
-                            }
-                        }
-                    }
-                }else{
-                    //it is along the border. depending on the face check direction check the chunk neighbour if exists for each face direction
-                    let mut display_face = true;
-                    //get sector
-                    if let Some(sector) = loaded_chunks.sectors.get(&[0,0,0])
-                    {
-                        if (x + faces[p][0]) < 0.
-                        {
-                            //chunk [x-1,y,z]
-                            //voxel [CHUNK_SIZE-1,y,z]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE-1,render_data.chunk_data.pos.1/CHUNK_SIZE,render_data.chunk_data.pos.2/CHUNK_SIZE];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(((CHUNK_SIZE-1)*CHUNK_SIZE*CHUNK_SIZE) as f32+y*CHUNK_SIZE as f32+z) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if (x + faces[p][0]) >= CHUNK_SIZE as f32
-                        {
-                            //chunk [x+1,y,z]
-                            //voxel [0,y,z]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE+1,render_data.chunk_data.pos.1/CHUNK_SIZE,render_data.chunk_data.pos.2/CHUNK_SIZE];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(y*CHUNK_SIZE as f32+z) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if (y + faces[p][1]) < 0.
-                        {
-                            //chunk [x,y-1,z]
-                            //voxel [x,CHUNK_SIZE-1,z]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE,render_data.chunk_data.pos.1/CHUNK_SIZE-1,render_data.chunk_data.pos.2/CHUNK_SIZE];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(x*chunk_size_squared as f32+((CHUNK_SIZE-1)*CHUNK_SIZE) as f32+z) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if (y + faces[p][1]) >= CHUNK_SIZE as f32
-                        {
-                            //chunk [x,y+1,z]
-                            //voxel [x,0,z]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE,render_data.chunk_data.pos.1/CHUNK_SIZE+1,render_data.chunk_data.pos.2/CHUNK_SIZE];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(x*chunk_size_squared as f32+z) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if (z + faces[p][2]) < 0.
-                        {
-                            //chunk [x,y,z-1]
-                            //voxel [x,y,CHUNK_SIZE-1]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE,render_data.chunk_data.pos.1/CHUNK_SIZE,render_data.chunk_data.pos.2/CHUNK_SIZE-1];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(x*chunk_size_squared as f32+y*CHUNK_SIZE as f32+(CHUNK_SIZE-1) as f32) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        if (z + faces[p][2]) >= CHUNK_SIZE as f32
-                        {
-                            //chunk [x,y,z+1]
-                            //voxel [x,y,0]
-                            let key = &[render_data.chunk_data.pos.0/CHUNK_SIZE,render_data.chunk_data.pos.1/CHUNK_SIZE,render_data.chunk_data.pos.2/CHUNK_SIZE+1];
-                            if let Some(neighbouring_chunk_arc) = sector.chunks.get(key)
-                            {
-                                if neighbouring_chunk_arc.is_some()
-                                {
-                                    if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                                    {
-                                        if neighbouring_chunk.chunk_data.block_data[(x*chunk_size_squared as f32+y*CHUNK_SIZE as f32) as usize].solid == false
-                                        {
-                                            display_face = false;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if !display_face
-                    {
-                        if voxel.voxel_variant == VoxelVariant::Block
-                        {
-                            for i in 0 .. 6 {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(BLOCK_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(BLOCK_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((BLOCK_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                            }
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.min.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.max.y]);
-                            render_data.uvs.push([uv_rect.max.x,uv_rect.min.y]);
-                            render_data.uvs.push([uv_rect.min.x,uv_rect.min.y]);
-                        }
-                        if voxel.voxel_variant == VoxelVariant::Slab
-                        {
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            for i in 0 .. 6 {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((SLAB_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(SLAB_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((SLAB_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((SLAB_VERTS[RECTANGLE_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(SLAB_VERTS[RECTANGLE_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((SLAB_VERTS[RECTANGLE_TRIS[p][i]][2]-0.5)*rotation.values[1] + z as f32) + 0.5 + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                                render_data.uvs.push([uv_rect.min.x+(uv_rect.max.x-uv_rect.min.x)*SLAB_UVS[p][i].x,uv_rect.min.y+(uv_rect.max.y-uv_rect.min.y)*SLAB_UVS[p][i].y]);
-                            }
-                        }
-                        if voxel.voxel_variant == VoxelVariant::Stair
-                        {
-                            let uv_rect = block_to_tex(voxel.voxel_type, texture_info.map.clone(), texture_info.rects.clone(),texture_info.size);
-                            for i in 0 .. STAIR_TRIS[p].len() {
-                                if rotation.switch
-                                {
-                                    render_data.vertices.push([((STAIR_VERTS[STAIR_TRIS[p][i]][2]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(STAIR_VERTS[STAIR_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((STAIR_VERTS[STAIR_TRIS[p][i]][0]-0.5)*rotation.values[1] + 0.5 + z as f32) + render_data.chunk_data.pos.2 as f32]);
-                                }else
-                                {
-                                    render_data.vertices.push([((STAIR_VERTS[STAIR_TRIS[p][i]][0]-0.5)*rotation.values[0] + 0.5 + x as f32) + render_data.chunk_data.pos.0 as f32,(STAIR_VERTS[STAIR_TRIS[p][i]][1] + y as f32) + render_data.chunk_data.pos.1 as f32,((STAIR_VERTS[STAIR_TRIS[p][i]][2]-0.5)*rotation.values[1] + 0.5 + z as f32) + render_data.chunk_data.pos.2 as f32]);
-                                }
-                                render_data.indices.push((render_data.vertices.len()-1) as u32);
-                                render_data.uvs.push([uv_rect.min.x+(uv_rect.max.x-uv_rect.min.x)*STAIR_UVS[p][i].x,uv_rect.min.y+(uv_rect.max.y-uv_rect.min.y)*STAIR_UVS[p][i].y]);
-                            }
-                        }
-                    }
-                }
+                continue;
+            }
+            let corners = quad.corners.map(|corner|
+            {
+                let height = top[0][0] * (1.0 - corner.x) * (1.0 - corner.z) + top[1][0] * corner.x * (1.0 - corner.z) + top[0][1] * (1.0 - corner.x) * corner.z + top[1][1] * corner.x * corner.z;
+                let local = orientation.point(Vec3::new(corner.x, corner.y * height, corner.z));
+                (chunk_origin + position.as_vec3() + local).to_array()
+            });
+            let uvs = quad.uvs.map(|uv| [uv_rect.min.x + (uv_rect.max.x - uv_rect.min.x) * uv.x, uv_rect.min.y + (uv_rect.max.y - uv_rect.min.y) * uv.y]);
+            let order = if orientation.is_mirrored() { [0, 2, 1, 0, 3, 2] } else { [0, 1, 2, 0, 2, 3] };
+            for corner in order
+            {
+                render_data.push_vertex(voxel.voxel_type, corners[corner], uvs[corner]);
             }
         }
     }
-    return render_data;
+    render_data
 }
 
 pub fn generate_chunk(chunk_position: [i32; 3]) -> Vec<Voxel>
+{
+    generate_chunk_with_seed(chunk_position, WORLD_SEED)
+}
+
+//the same seed always gives the same chunk, small random details use an rng seeded from the seed and chunk position
+pub fn generate_chunk_with_seed(chunk_position: [i32; 3], seed: u32) -> Vec<Voxel>
 {
     let sp1 = Key::new(-1.0, 50.0, Interpolation::Linear);
     let sp2 = Key::new(0.3, 100.0, Interpolation::default());
@@ -419,70 +206,69 @@ pub fn generate_chunk(chunk_position: [i32; 3]) -> Vec<Voxel>
     let sp6 = Key::new(1.0, 153.0, Interpolation::default());
     let spline = Spline::from_vec(vec![sp1, sp2, sp3, sp4, sp5]);
 
-    let fbm = Fbm::<Perlin>::default().set_seed(1).set_octaves(4);
+    let fbm = Fbm::<Perlin>::default().set_seed(seed).set_octaves(4);
     let mut data = Vec::with_capacity(CHUNK_SIZE.pow(3) as usize);
 
     let x_offset = chunk_position[0] * CHUNK_SIZE;
     let y_offset = chunk_position[1] * CHUNK_SIZE;
     let z_offset = chunk_position[2] * CHUNK_SIZE;
 
-    let mut vals = Vec::with_capacity(CHUNK_SIZE as usize);
-    let mut rng = rand::thread_rng();
-    for x in 0..CHUNK_SIZE {
-        vals.clear();
-        for z in 0..CHUNK_SIZE {
-            vals.push(fbm.get([(x + x_offset) as f64 * 0.002, (z + z_offset) as f64 * 0.002]));
+    let chunk_seed = (seed as u64) ^ (chunk_position[0] as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (chunk_position[1] as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F) ^ (chunk_position[2] as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    let mut rng = StdRng::seed_from_u64(chunk_seed);
+
+    //terrain height of the columns from -2 to CHUNK_SIZE + 1, the stairs of the columns around the chunk need theirs too
+    let sample_width = CHUNK_SIZE + 4;
+    let mut samples = Vec::with_capacity((sample_width * sample_width) as usize);
+    for x in -2..CHUNK_SIZE + 2
+    {
+        for z in -2..CHUNK_SIZE + 2
+        {
+            samples.push(spline.clamped_sample(fbm.get([(x + x_offset) as f64 * 0.002, (z + z_offset) as f64 * 0.002])).unwrap());
+        }
+    }
+    let sample = |x: i32, z: i32| samples[((x + 2) * sample_width + z + 2) as usize];
+    //height and surface stair of the columns from -1 to CHUNK_SIZE
+    let column_width = CHUNK_SIZE + 2;
+    let mut columns = Vec::with_capacity((column_width * column_width) as usize);
+    for x in -1..CHUNK_SIZE + 1
+    {
+        for z in -1..CHUNK_SIZE + 1
+        {
+            let height = (sample(x, z) * 10.0).round() / 10.0;
+            let around = [sample(x + 1, z), sample(x - 1, z), sample(x, z + 1), sample(x, z - 1)].map(f64::round);
+            columns.push((height, surface_stair(height, around)));
+        }
+    }
+    let column = |x: i32, z: i32| columns[((x + 1) * column_width + z + 1) as usize];
+    //the air in front of an inner corner gets a stair facing the corner's other side, so the two turn into an inner and
+    //an outer corner and diagonal slopes become rows of corners instead of lone stairs
+    let corner_fill = |x: i32, y: i32, z: i32| [VoxelRotation::Forward, VoxelRotation::Backward, VoxelRotation::Left, VoxelRotation::Right].into_iter().find_map(|facing|
+    {
+        let direction = facing.direction();
+        match column(x + direction.x, z + direction.z)
+        {
+            (height, Some((stair, Some(side)))) if stair == facing && (y as f64 - height).abs() < 0.2 => Some(side),
+            _ => None,
         }
+    });
+
+    for x in 0..CHUNK_SIZE {
         for y in 0..CHUNK_SIZE {
             let y_val = y + y_offset;
             for z in 0..CHUNK_SIZE {
                 let mut voxel = Voxel::default();
-                let height:f64 = (spline.clamped_sample(vals[z as usize]).unwrap()*10.0).round()/10.0;
+                let (height, stair) = column(x, z);
                 if (y_val as f64 - height).abs() < 0.2 {
-                    let x1 = spline.clamped_sample(fbm.get([(x + x_offset + 1) as f64 * 0.002, (z + z_offset) as f64 * 0.002])).unwrap().round();
-                    let x2 = spline.clamped_sample(fbm.get([(x + x_offset - 1) as f64 * 0.002, (z + z_offset) as f64 * 0.002])).unwrap().round();
-                    let z1 = spline.clamped_sample(fbm.get([(x + x_offset) as f64 * 0.002, (z + z_offset + 1) as f64 * 0.002])).unwrap().round();
-                    let z2 = spline.clamped_sample(fbm.get([(x + x_offset) as f64 * 0.002, (z + z_offset - 1) as f64 * 0.002])).unwrap().round();
-                    let max = x1.max(x2).max(z1).max(z2);
-                    let x1max:bool = max == x1;
-                    let x2max:bool = max == x2;
-                    let z1max:bool = max == z1;
-                    let z2max:bool = max == z2;
-                    if z1max
-                    {
-                        voxel.voxel_variant = VoxelVariant::Stair;
-                        voxel.voxel_rotation = VoxelRotation::Forward;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
-                    }else if z2max
-                    {
-                        voxel.voxel_variant = VoxelVariant::Stair;
-                        voxel.voxel_rotation = VoxelRotation::Backward;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
-                    }else if x1max
-                    {
-                        voxel.voxel_variant = VoxelVariant::Stair;
-                        voxel.voxel_rotation = VoxelRotation::Left;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
-                    }else if x2max
-                    {
-                        voxel.voxel_variant = VoxelVariant::Stair;
-                        voxel.voxel_rotation = VoxelRotation::Right;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
-                    }
-                    if (x1max&&x2max)||(x1max&&z1max)||(x1max&&z2max)||(x2max&&z1max)||(x2max&&z2max)||(z1max&&z2max)
+                    voxel.voxel_type = VoxelType::Grass;
+                    voxel.solid = false;
+                    match stair
                     {
-                        voxel.voxel_variant = VoxelVariant::Slab;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
-                    }
-                    if (max - height).abs() < 0.2  {
-                        voxel.voxel_variant = VoxelVariant::Slab;
-                        voxel.voxel_type = VoxelType::Grass;
-                        voxel.solid = false;
+                        Some((facing, _)) =>
+                        {
+                            voxel.voxel_variant = VoxelVariant::Stair;
+                            voxel.voxel_rotation = facing;
+                        }
+                        None => voxel.voxel_variant = VoxelVariant::Slab,
                     }
                 }else if (y_val as f64) < height
                 {
@@ -496,6 +282,11 @@ pub fn generate_chunk(chunk_position: [i32; 3]) -> Vec<Voxel>
                         {
                         voxel.voxel_type = VoxelType::Grass;
                     }
+                }else if let Some(side) = corner_fill(x, y_val, z)
+                {
+                    voxel.voxel_variant = VoxelVariant::Stair;
+                    voxel.voxel_rotation = side;
+                    voxel.voxel_type = VoxelType::Grass;
                 }
                 if y_val <= SEA_LEVEL
                 {
@@ -504,6 +295,7 @@ pub fn generate_chunk(chunk_position: [i32; 3]) -> Vec<Voxel>
                     {
                         voxel.solid = true;
                         voxel.voxel_type = VoxelType::Water;
+                        voxel.fluid = FluidState::SOURCE;
                     }else
                     {
                         voxel.voxel_type = VoxelType::Sand;
@@ -527,20 +319,46 @@ pub fn generate_chunk(chunk_position: [i32; 3]) -> Vec<Voxel>
     data
 }
 
+//the stair smoothing the surface voxel of a column, from the rounded heights of the columns at +x, -x, +z and -z. it
+//rises towards the highest of them, None for a slab on flat ground or in a valley between two opposite sides
+//in a corner where the ground also rises on a side next to the facing that side comes along
+fn surface_stair(height: f64, around: [f64; 4]) -> Option<(VoxelRotation, Option<VoxelRotation>)>
+{
+    let max = around.into_iter().fold(f64::MIN, f64::max);
+    let [x1max, x2max, z1max, z2max] = around.map(|side| side == max);
+    if (x1max && x2max) || (z1max && z2max) || (max - height).abs() < 0.2
+    {
+        return None;
+    }
+    let across = if x1max { Some(VoxelRotation::Left) } else if x2max { Some(VoxelRotation::Right) } else { None };
+    if z1max
+    {
+        return Some((VoxelRotation::Forward, across));
+    }
+    if z2max
+    {
+        return Some((VoxelRotation::Backward, across));
+    }
+    Some((if x1max { VoxelRotation::Left } else { VoxelRotation::Right }, None))
+}
+
 
 pub fn chunk_handler
 (
     mut commands: Commands,
-    mut compute_chunks: Query<(Entity, &mut ComputeChunk)>,
-    mut update_chunks: Query<(Entity, &mut UpdateChunk)>,
-    mut spawn_chunks: Query<(Entity, &mut SpawnChunk)>,
-    mut generate_chunks: Query<(Entity, &mut GenerateChunk)>,
+    mut update_chunks: Query<(Entity, &ChunkCoords, &mut UpdateChunk)>,
+    mut spawn_chunks: Query<(Entity, &ChunkCoords, &mut SpawnChunk)>,
+    mut generate_chunks: Query<(Entity, &ChunkCoords, &mut GenerateChunk)>,
+    universe: Res<Universe>,
     mut loaded_sectors: ResMut<CurrentlyLoaded>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    texture_atlas_data: Res<TextureAtlasBuilt>,
-    mut chunk_entitys: ResMut<ChunkEntitys>,
+    mut atlas_materials: ResMut<Assets<VoxelAtlasMaterial>>,
+    mut array_materials: ResMut<Assets<VoxelArrayMaterial>>,
+    meshing: ChunkMeshing,
+    chunk_entitys: Res<ChunkEntitys>,
+    mut chunk_generated: EventWriter<ChunkGenerated>,
+    mut chunk_meshed: EventWriter<ChunkMeshed>,
     query: Query<Entity>
 )
 {
@@ -548,173 +366,81 @@ pub fn chunk_handler
     let thread_pool = AsyncComputeTaskPool::get();
 
     //generate chunks
-    //accepts chunk data and turns into render data via thread
-    for (mut entity, mut task) in &mut generate_chunks
+    //accepts chunk data, stores it as loaded and meshes it along with its neighbours via thread
+    for (entity, coords, mut task) in &mut generate_chunks
     {
         if let Some(chunk_data) = future::block_on(future::poll_once(&mut task.0)) {
-            let texture_atlas_data = texture_atlas_data.clone();
-            let loaded_sectors_copy = loaded_sectors.clone();
-            let position = [chunk_data.pos.0.clone()/CHUNK_SIZE,chunk_data.pos.1.clone()/CHUNK_SIZE,chunk_data.pos.2.clone()/CHUNK_SIZE];
-            let chunk_task:Task<RenderData> =  thread_pool.spawn(async move
-                {
-                    let texture_info = TextureInfo
-                    {
-                        map: texture_atlas_data.texture_map.to_vec(),
-                        rects: texture_atlas_data.texture_rects.to_vec(),
-                        size: texture_atlas_data.texture_size,
-                    };
-                    let mut render_data = RenderData::default();
-                    render_data = chunk_to_render(chunk_data.clone(), loaded_sectors_copy);
-                    RenderData
-                    {
-                        vertices: render_data.vertices,
-                        indices: render_data.indices,
-                        uvs: render_data.uvs,
-                        chunk_position: position.clone(),
-                        chunk_data,
-                        neighbours: render_data.neighbours,
-                    }
-                });
-            let mut name: String = position
-                .iter()
-                .map(|&n| n.to_string())  // Convert each integer to a String
-                .collect::<Vec<_>>()       // Collect into a vector of strings
-                .join(",");
-            name = format!("[{}]", name);
+            let position = coords.chunk;
             commands.entity(entity).remove::<GenerateChunk>();
-            commands.entity(entity).remove::<Name>();
-            commands.entity(entity).insert((ComputeChunk(chunk_task),Name::new(name))).id();
-        }
-    }
-
-
-
-    //compute chunks
-    //accepts render data and re-renders all neighbour chunks
-    for (mut entity, mut task) in &mut compute_chunks
-    {
-        if let Some(chunk_data) = future::block_on(future::poll_once(&mut task.0)) {
-            //update all neighbour chunks when chunk loads
-            //set loaded_sector stuff
-            if let Some(mut loaded_sector) = loaded_sectors.sectors.get_mut(&[0,0,0])
+            let Some(loaded_sector) = loaded_sectors.sectors.get_mut(&coords.sector) else { continue };
+            //chunk was unloaded while it was generating
+            let Some(chunk) = loaded_sector.chunks.get_mut(&position) else { continue };
+            //an undo can save the chunk while it is still generating, the saved copy wins
+            let chunk_data = universe.saved_chunk(coords.sector, position).cloned().unwrap_or(chunk_data);
+            *chunk = Some(Arc::new(chunk_data));
+            chunk_generated.send(ChunkGenerated { sector: coords.sector, chunk: position });
+
+            //mesh the new chunk and re-render all neighbour chunks so their border faces are updated
+            //each task only gets the chunks around the one it meshes, shared through arcs
+            for offset in meshing.affected_neighbours(coords.sector)
             {
-                if let Some(mut chunk) = loaded_sector.chunks.get_mut(&chunk_data.chunk_position)
+                let neighbour_position = [position[0]+offset[0],position[1]+offset[1],position[2]+offset[2]];
+                let Some(neighbourhood) = ChunkNeighbourhood::from_loaded(loaded_sector, neighbour_position) else { continue };
+                let (chunk_task, lod) = meshing.spawn_task(thread_pool, coords.sector, neighbour_position, neighbourhood);
+                if offset == [0,0,0]
                 {
-                    *chunk = Arc::new(Some(chunk_data.clone()));
-                }
-            }
-            //get sector [0,0,0]
-            if let Some(mut loaded_sector) = loaded_sectors.sectors.get(&[0,0,0])
-            {
-                //get all neighbouring chunks
-                for (position,chunk) in &chunk_data.neighbours
+                    commands.entity(entity).insert((SpawnChunk(chunk_task), lod));
+                }else if let Some(neighbour_entity) = chunk_entitys.entitys.get(&neighbour_position)
                 {
-                    //get the render data of the neighbouring chunk in form of arc
-                    if let Some(mut neighbouring_chunk_arc) = loaded_sector.chunks.get(&[position[0] as i32,position[1] as i32,position[2] as i32])
-                    {
-                        //get the render data out of the arc
-                        if let Some(neighbouring_chunk) = &**neighbouring_chunk_arc
-                        {
-                            let texture_atlas_data_copy = texture_atlas_data.clone();
-                            let loaded_sectors_copy = loaded_sectors.clone();
-                            let neighbouring_chunk_copy = neighbouring_chunk.clone();
-                            let position = [neighbouring_chunk_copy.chunk_data.pos.0.clone()/CHUNK_SIZE,neighbouring_chunk_copy.chunk_data.pos.1.clone()/CHUNK_SIZE,neighbouring_chunk_copy.chunk_data.pos.2.clone()/CHUNK_SIZE];
-                            let chunk_task:Task<RenderData> =  thread_pool.spawn(async move
-                                {
-                                    let texture_info = TextureInfo
-                                    {
-                                        map: texture_atlas_data_copy.texture_map.to_vec(),
-                                        rects: texture_atlas_data_copy.texture_rects.to_vec(),
-                                        size: texture_atlas_data_copy.texture_size,
-                                    };
-                                    let mut render_data = RenderData::default();
-                                    render_data = render_update(neighbouring_chunk_copy.clone(), texture_info,loaded_sectors_copy);
-                                    RenderData
-                                    {
-                                        vertices: render_data.vertices,
-                                        indices: render_data.indices,
-                                        uvs: render_data.uvs,
-                                        chunk_position: neighbouring_chunk_copy.chunk_position,
-                                        chunk_data: neighbouring_chunk_copy.chunk_data,
-                                        neighbours: render_data.neighbours,
-                                    }
-
-                                });
-                            let mut name: String = position
-                                .iter()
-                                .map(|&n| n.to_string())  // Convert each integer to a String
-                                .collect::<Vec<_>>()       // Collect into a vector of strings
-                                .join(",");
-                            name = format!("[{}]", name);
-                            if let Some(entity) = chunk_entitys.entitys.get(&position)
-                            {
-                                commands.entity(*entity).insert((UpdateChunk(chunk_task)));
-                            }
-                        }
-                    }
+                    commands.entity(*neighbour_entity).insert((UpdateChunk(chunk_task), lod));
                 }
             }
+        }
+    }
 
-            let position = [chunk_data.chunk_data.pos.0.clone()/CHUNK_SIZE,chunk_data.chunk_data.pos.1.clone()/CHUNK_SIZE,chunk_data.chunk_data.pos.2.clone()/CHUNK_SIZE];
-            let texture_atlas_data_copy = texture_atlas_data.clone();
-            let loaded_sectors_copy = loaded_sectors.clone();
-            let chunk_task:Task<RenderData> =  thread_pool.spawn(async move
-                {
-                    let texture_info = TextureInfo
-                    {
-                        map: texture_atlas_data_copy.texture_map.to_vec(),
-                        rects: texture_atlas_data_copy.texture_rects.to_vec(),
-                        size: texture_atlas_data_copy.texture_size,
-                    };
-                    let mut render_data = RenderData::default();
-                    render_data = render_update(chunk_data.clone(), texture_info,loaded_sectors_copy);
-                    RenderData
-                    {
-                        vertices: render_data.vertices,
-                        indices: render_data.indices,
-                        uvs: render_data.uvs,
-                        chunk_position: position.clone(),
-                        chunk_data: chunk_data.chunk_data,
-                        neighbours: render_data.neighbours,
-                    }
-                });
-
-            let mut name: String = position
-                .iter()
-                .map(|&n| n.to_string())  // Convert each integer to a String
-                .collect::<Vec<_>>()       // Collect into a vector of strings
-                .join(",");
-            name = format!("[{}]", name);
-            commands.entity(entity).remove::<ComputeChunk>();
-            commands.entity(entity).remove::<Name>();
-            commands.entity(entity).insert((SpawnChunk(chunk_task),Name::new(name))).id();
+    //edited chunks
+    //every chunk touched by voxel edits since the last frame is remeshed once
+    for (sector, position) in std::mem::take(&mut dirty_chunks.chunks)
+    {
+        let Some(loaded_sector) = loaded_sectors.sectors.get(&sector) else { continue };
+        let Some(neighbourhood) = ChunkNeighbourhood::from_loaded(loaded_sector, position) else { continue };
+        if let Some(entity) = chunk_entitys.entitys.get(&position)
+        {
+            let (chunk_task, lod) = meshing.spawn_task(thread_pool, sector, position, neighbourhood);
+            commands.entity(*entity).insert((UpdateChunk(chunk_task), lod));
         }
     }
 
     //spawn chunks
     //creates the mesh from render data
-    for (mut entity, mut task) in &mut spawn_chunks
+    for (entity, coords, mut task) in &mut spawn_chunks
     {
-        if let Some(chunk_data) = future::block_on(future::poll_once(&mut task.0)) {
-            let mut chunk_new_mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, chunk_data.vertices.clone());
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; chunk_data.indices.len()]);
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, chunk_data.uvs.clone());
-            chunk_new_mesh.set_indices(Some(mesh::Indices::U32(chunk_data.indices.clone())));
-            commands.entity(entity).insert((PbrBundle {
-                mesh: meshes.add(chunk_new_mesh),
-                material: materials.add(StandardMaterial{
-                    emissive: Color::WHITE,
-                    emissive_texture: Option::from(texture_atlas_data.clone().tex),
-                    double_sided: false,
-                    alpha_mode: AlphaMode::Opaque,
-
-                    ..default()
-                }),
-                transform: Transform::from_xyz(0.0, 0.0, 0.0),
-                ..default()
-            }));
+        if let Some(chunk_mesh) = future::block_on(future::poll_once(&mut task.0)) {
+            let chunk_new_mesh = chunk_mesh.into_mesh();
+            //the texture array material when the array was built, the meshes carry a layer per vertex then. both animate in their shaders
+            match meshing.texture_atlas_data.array.clone()
+            {
+                Some(array_texture) =>
+                {
+                    commands.entity(entity).insert(MaterialMeshBundle {
+                        mesh: meshes.add(chunk_new_mesh),
+                        material: array_materials.add(VoxelArrayMaterial { array_texture }),
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..default()
+                    });
+                }
+                None =>
+                {
+                    commands.entity(entity).insert(MaterialMeshBundle {
+                        mesh: meshes.add(chunk_new_mesh),
+                        material: atlas_materials.add(VoxelAtlasMaterial { atlas_texture: meshing.texture_atlas_data.tex.clone() }),
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..default()
+                    });
+                }
+            }
             commands.entity(entity).remove::<SpawnChunk>();
+            chunk_meshed.send(ChunkMeshed { sector: coords.sector, chunk: coords.chunk, remesh: false });
         }
     }
 
@@ -723,19 +449,74 @@ pub fn chunk_handler
 
 
 
-    for (mut entity, mut task) in &mut update_chunks.iter_mut()
+    for (entity, coords, mut task) in &mut update_chunks.iter_mut()
     {
-        if let Some(chunk_data) = future::block_on(future::poll_once(&mut task.0)) {
-            let mut chunk_new_mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, chunk_data.vertices.clone());
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; chunk_data.indices.len()]);
-            chunk_new_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, chunk_data.uvs.clone());
-            chunk_new_mesh.set_indices(Some(mesh::Indices::U32(chunk_data.indices.clone())));
+        if let Some(chunk_mesh) = future::block_on(future::poll_once(&mut task.0)) {
+            let chunk_new_mesh = chunk_mesh.into_mesh();
             commands.entity(entity).remove::<Handle<Mesh>>();
             commands.entity(entity).insert(meshes.add(chunk_new_mesh));
             commands.entity(entity).remove::<UpdateChunk>();
+            chunk_meshed.send(ChunkMeshed { sector: coords.sector, chunk: coords.chunk, remesh: true });
         }
     }
 
 }
 
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn surface_stairs_rise_towards_the_highest_side()
+    {
+        //+x, -x, +z, -z
+        assert_eq!(surface_stair(100.0, [100.0; 4]), None);
+        assert_eq!(surface_stair(100.0, [101.0, 100.0, 100.0, 100.0]), Some((VoxelRotation::Left, None)));
+        assert_eq!(surface_stair(100.0, [100.0, 101.0, 100.0, 100.0]), Some((VoxelRotation::Right, None)));
+        assert_eq!(surface_stair(100.0, [100.0, 100.0, 101.0, 99.0]), Some((VoxelRotation::Forward, None)));
+        assert_eq!(surface_stair(100.0, [100.0, 99.0, 100.0, 101.0]), Some((VoxelRotation::Backward, None)));
+        //corners bring the other rising side along
+        assert_eq!(surface_stair(100.0, [101.0, 100.0, 101.0, 100.0]), Some((VoxelRotation::Forward, Some(VoxelRotation::Left))));
+        assert_eq!(surface_stair(100.0, [100.0, 101.0, 100.0, 101.0]), Some((VoxelRotation::Backward, Some(VoxelRotation::Right))));
+        //valleys between opposite sides are slabs
+        assert_eq!(surface_stair(100.0, [101.0, 101.0, 100.0, 100.0]), None);
+        assert_eq!(surface_stair(100.0, [101.0, 100.0, 101.0, 101.0]), None);
+    }
+
+    #[test]
+    fn terrain_stairs_turn_corners()
+    {
+        let mut world = std::collections::HashMap::new();
+        for x in 0..4
+        {
+            for y in 3..5
+            {
+                for z in 0..4
+                {
+                    world.insert([x, y, z], Chunk { block_data: generate_chunk_with_seed([x, y, z], 1), pos: (x * CHUNK_SIZE, y * CHUNK_SIZE, z * CHUNK_SIZE) });
+                }
+            }
+        }
+        let mut shapes: HashMap<StairShape, usize> = HashMap::new();
+        for chunk in world.values()
+        {
+            for (index, voxel) in chunk.block_data.iter().enumerate()
+            {
+                if voxel.voxel_variant != VoxelVariant::Stair
+                {
+                    continue;
+                }
+                let index = index as i32;
+                let position = IVec3::new(chunk.pos.0, chunk.pos.1, chunk.pos.2) + IVec3::new(index / (CHUNK_SIZE * CHUNK_SIZE), index / CHUNK_SIZE % CHUNK_SIZE, index % CHUNK_SIZE);
+                *shapes.entry(stair_shape(&world, position, voxel)).or_default() += 1;
+            }
+        }
+        for shape in [StairShape::InnerLeft, StairShape::InnerRight, StairShape::OuterLeft, StairShape::OuterRight]
+        {
+            assert!(shapes.get(&shape).is_some_and(|count| *count > 0), "no {shape:?} in {shapes:?}");
+        }
+        //the same seed makes the same terrain
+        assert_eq!(generate_chunk_with_seed([1, 3, 2], 1), world[&[1, 3, 2]].block_data);
+    }
+}
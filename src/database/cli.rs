@@ -0,0 +1,106 @@
+use crate::database::export::*;
+use crate::database::generator::configured_generator;
+use crate::database::maps::*;
+use crate::database::model::configured_block_models;
+use crate::database::sector::*;
+use crate::database::settings::{LoadShape, VERTICAL_RENDER_DISTANCE};
+use bevy::prelude::*;
+
+const USAGE: &str = "usage:\n  voxel_engine                                          start the game\n  voxel_engine export <min chunk x,y,z> <max chunk x,y,z> <out.glb|out.gltf|out.obj>\n  voxel_engine maps <seed> <min x,z> <max x,z> <out dir>          colour, height and biome png of a generated area\n  voxel_engine bench-loading [radius ...]              time a chunk loader crossing a chunk boundary, radius 8 16 32 by default";
+
+fn parse_numbers(argument: &str) -> Option<Vec<i32>>
+{
+    argument.split(',').map(|value| value.trim().parse::<i32>().ok()).collect()
+}
+
+fn parse_chunk(argument: &str) -> Result<IVec3, String>
+{
+    match parse_numbers(argument).as_deref()
+    {
+        Some([x, y, z]) => Ok(IVec3::new(*x, *y, *z)),
+        _ => Err(format!("{} is not a chunk position like 0,2,-1", argument)),
+    }
+}
+
+fn parse_column(argument: &str) -> Result<IVec2, String>
+{
+    match parse_numbers(argument).as_deref()
+    {
+        Some([x, z]) => Ok(IVec2::new(*x, *z)),
+        _ => Err(format!("{} is not a column position like -256,128", argument)),
+    }
+}
+
+fn export(arguments: &[String]) -> Result<(), String>
+{
+    let [min, max, path] = arguments else { return Err(USAGE.to_string()) };
+    let (min, max) = (parse_chunk(min)?, parse_chunk(max)?);
+    let atlas = HeadlessAtlas::load("Assets/textures/blocks", "Assets/TexMem", "Assets/BlockMem").map_err(|error| format!("could not build the texture atlas: {}", error))?;
+    //the same generator the game loads chunks with, so a heightmap world exports as it is played
+    let generator = configured_generator();
+    let mesh = mesh_region(min, max, |chunk_position| generator.0.generate(chunk_position), &atlas, &configured_block_models());
+    write_mesh(path, &mesh, &atlas).map_err(|error| format!("could not write {}: {}", path, error))?;
+    println!("wrote {} triangles to {}", mesh.indices.len() / 3, path);
+    Ok(())
+}
+
+fn maps(arguments: &[String]) -> Result<(), String>
+{
+    let [seed, min, max, directory] = arguments else { return Err(USAGE.to_string()) };
+    let seed = seed.parse::<u32>().map_err(|_| format!("{} is not a seed", seed))?;
+    let (min, max) = (parse_column(min)?, parse_column(max)?);
+    let atlas = HeadlessAtlas::load("Assets/textures/blocks", "Assets/TexMem", "Assets/BlockMem").map_err(|error| format!("could not build the texture atlas: {}", error))?;
+    let maps = render_world_maps(seed, min, max, &atlas.average_colours());
+    let directory = std::path::Path::new(directory);
+    std::fs::create_dir_all(directory).map_err(|error| format!("could not create {}: {}", directory.display(), error))?;
+    for (name, result) in [
+        ("colour.png", maps.colour.save(directory.join("colour.png"))),
+        ("height.png", maps.height.save(directory.join("height.png"))),
+        ("biome.png", maps.biome.save(directory.join("biome.png"))),
+    ]
+    {
+        result.map_err(|error| format!("could not write {}: {}", name, error))?;
+    }
+    println!("wrote {}x{} maps to {}", maps.colour.width(), maps.colour.height(), directory.display());
+    Ok(())
+}
+
+fn bench_loading(arguments: &[String]) -> Result<(), String>
+{
+    let radii = match arguments
+    {
+        [] => vec![8, 16, 32],
+        _ => arguments.iter().map(|radius| radius.parse::<i32>().ok().filter(|radius| *radius > 0).ok_or(format!("{} is not a radius", radius))).collect::<Result<Vec<i32>, String>>()?,
+    };
+    for radius in radii
+    {
+        let loader = ChunkLoader { shape: LoadShape::Cylinder, radius, vertical_radius: VERTICAL_RENDER_DISTANCE };
+        //the old full scan takes about a minute at radius 32
+        let result = benchmark_boundary_crossing(loader, radius <= 16);
+        let full_scan = result.full_scan.map_or("not run".to_string(), |duration| format!("{:.2?}", duration));
+        println!("radius {}: {} chunks loaded, crossing took {:.2?}, old full scan {}", radius, result.loaded, result.crossing, full_scan);
+    }
+    Ok(())
+}
+
+//runs a headless subcommand if one was given, without opening a window or touching the gpu
+//returns false when the game should start normally
+pub fn run_from_args() -> bool
+{
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = arguments.first() else { return false };
+    let result = match command.as_str()
+    {
+        "export" => export(&arguments[1..]),
+        "maps" => maps(&arguments[1..]),
+        "bench-loading" => bench_loading(&arguments[1..]),
+        "help" | "--help" | "-h" => Err(USAGE.to_string()),
+        other => Err(format!("unknown command {}\n{}", other, USAGE)),
+    };
+    if let Err(error) = result
+    {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    true
+}
@@ -0,0 +1,159 @@
+use crate::database::bulk::*;
+use crate::database::chunk::*;
+use crate::database::events::*;
+use crate::database::sector::*;
+use crate::database::settings::CHUNK_SIZE;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+//request to replace a single voxel. position is in voxels relative to the sector origin
+#[derive(Event, Debug, Clone)]
+pub struct SetVoxel
+{
+    pub sector: [i32; 3],
+    pub position: IVec3,
+    pub voxel: Voxel,
+    //editor changes become undo steps, one per frame and sector. simulation such as fluids and falling blocks sends false
+    pub undoable: bool,
+}
+
+//chunks whose voxel data changed since they were last meshed, chunk_handler remeshes each of them once
+#[derive(Default, Resource)]
+pub struct DirtyChunks
+{
+    pub chunks: HashSet<([i32; 3], [i32; 3])>,
+}
+
+//splits a voxel position relative to the sector origin into its chunk and the position inside that chunk
+pub fn voxel_to_chunk(position: IVec3) -> ([i32; 3], [i32; 3])
+{
+    (
+        [position.x.div_euclid(CHUNK_SIZE), position.y.div_euclid(CHUNK_SIZE), position.z.div_euclid(CHUNK_SIZE)],
+        [position.x.rem_euclid(CHUNK_SIZE), position.y.rem_euclid(CHUNK_SIZE), position.z.rem_euclid(CHUNK_SIZE)],
+    )
+}
+
+//read access to voxels by position relative to a sector origin
+//implemented for loaded sectors so world queries like collision can also run against plain generated chunks
+pub trait VoxelAccess
+{
+    //None if the chunk holding the position is not available
+    fn voxel(&self, position: IVec3) -> Option<&Voxel>;
+}
+
+impl VoxelAccess for SectorsRendering
+{
+    fn voxel(&self, position: IVec3) -> Option<&Voxel>
+    {
+        let (chunk, local) = voxel_to_chunk(position);
+        let chunk_data = self.chunks.get(&chunk)?.as_ref()?;
+        chunk_data.block_data.get(voxel_index(local[0], local[1], local[2]))
+    }
+}
+
+impl VoxelAccess for HashMap<[i32; 3], Chunk>
+{
+    fn voxel(&self, position: IVec3) -> Option<&Voxel>
+    {
+        let (chunk, local) = voxel_to_chunk(position);
+        self.get(&chunk)?.block_data.get(voxel_index(local[0], local[1], local[2]))
+    }
+}
+
+//air chunks from min to max chunk inclusive with the given voxels placed, a small world for tests to query
+#[cfg(test)]
+pub fn test_world(min: [i32; 3], max: [i32; 3], voxels: &[(IVec3, Voxel)]) -> HashMap<[i32; 3], Chunk>
+{
+    let mut world = HashMap::new();
+    for x in min[0]..=max[0]
+    {
+        for y in min[1]..=max[1]
+        {
+            for z in min[2]..=max[2]
+            {
+                let block_data = vec![Voxel::default(); (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize];
+                world.insert([x, y, z], Chunk { block_data, pos: (x * CHUNK_SIZE, y * CHUNK_SIZE, z * CHUNK_SIZE) });
+            }
+        }
+    }
+    for (position, voxel) in voxels
+    {
+        let (chunk, local) = voxel_to_chunk(*position);
+        if let Some(chunk) = world.get_mut(&chunk)
+        {
+            chunk.block_data[voxel_index(local[0], local[1], local[2])] = voxel.clone();
+        }
+    }
+    world
+}
+
+//replaces a voxel in a loaded chunk and marks the chunk dirty, along with any neighbour sharing the changed face
+//returns the voxel that was replaced, None if the chunk is not loaded
+pub fn set_voxel(loaded_sectors: &mut CurrentlyLoaded, dirty_chunks: &mut DirtyChunks, sector: [i32; 3], position: IVec3, voxel: Voxel) -> Option<Voxel>
+{
+    let (chunk, local) = voxel_to_chunk(position);
+    let chunk_data = loaded_sectors.sectors.get_mut(&sector)?.chunks.get_mut(&chunk)?.as_mut()?;
+    //meshing tasks may still hold the old data, make_mut copies it in that case
+    let old = std::mem::replace(&mut Arc::make_mut(chunk_data).block_data[voxel_index(local[0], local[1], local[2])], voxel);
+    mark_dirty_with_neighbours(sector, position, dirty_chunks);
+    Some(old)
+}
+
+//marks the chunk holding a changed voxel dirty, along with any neighbour sharing the face the voxel is on
+pub fn mark_dirty_with_neighbours(sector: [i32; 3], position: IVec3, dirty_chunks: &mut DirtyChunks)
+{
+    let (chunk, local) = voxel_to_chunk(position);
+    dirty_chunks.chunks.insert((sector, chunk));
+    for axis in 0..3
+    {
+        let mut offset = [0, 0, 0];
+        offset[axis] = match local[axis]
+        {
+            0 => -1,
+            edge if edge == CHUNK_SIZE - 1 => 1,
+            _ => continue,
+        };
+        dirty_chunks.chunks.insert((sector, [chunk[0] + offset[0], chunk[1] + offset[1], chunk[2] + offset[2]]));
+    }
+}
+
+pub fn apply_voxel_edits
+(
+    mut edits: EventReader<SetVoxel>,
+    mut loaded_sectors: ResMut<CurrentlyLoaded>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut voxel_changed: EventWriter<VoxelChanged>,
+    mut edits_applied: EventWriter<BulkEditApplied>,
+)
+{
+    let mut undoable: HashMap<[i32; 3], VoxelDiff> = HashMap::new();
+    for edit in edits.read()
+    {
+        if let Some(old) = set_voxel(&mut loaded_sectors, &mut dirty_chunks, edit.sector, edit.position, edit.voxel.clone())
+        {
+            let (chunk, local) = voxel_to_chunk(edit.position);
+            if edit.undoable
+            {
+                let diff = undoable.entry(edit.sector).or_insert_with(|| VoxelDiff { sector: edit.sector, changes: Vec::new() });
+                diff.changes.push(VoxelDelta { position: edit.position, old: old.clone(), new: edit.voxel.clone() });
+            }
+            voxel_changed.send(VoxelChanged
+            {
+                sector: edit.sector,
+                chunk,
+                voxel: local,
+                old,
+                new: edit.voxel.clone(),
+            });
+        }
+    }
+    let mut undoable: Vec<VoxelDiff> = undoable.into_values().collect();
+    undoable.sort_by_key(|diff| diff.sector);
+    for diff in undoable
+    {
+        edits_applied.send(BulkEditApplied { diff });
+    }
+}
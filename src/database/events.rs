@@ -0,0 +1,39 @@
+use bevy::prelude::*;
+use crate::database::voxel::*;
+
+//sent by chunk_handler once a chunk's voxel data has been generated and stored as loaded
+#[derive(Event, Debug, Clone)]
+pub struct ChunkGenerated
+{
+    pub sector: [i32; 3],
+    pub chunk: [i32; 3],
+}
+
+//sent by chunk_handler when a chunk's mesh is added, remesh is true when it replaced an older mesh
+#[derive(Event, Debug, Clone)]
+pub struct ChunkMeshed
+{
+    pub sector: [i32; 3],
+    pub chunk: [i32; 3],
+    pub remesh: bool,
+}
+
+//sent by unload_chunks after a chunk was removed from the loaded chunks and its entity despawned
+#[derive(Event, Debug, Clone)]
+pub struct ChunkUnloaded
+{
+    pub sector: [i32; 3],
+    pub chunk: [i32; 3],
+}
+
+//sent for every voxel that was edited in a loaded chunk
+#[derive(Event, Debug, Clone)]
+pub struct VoxelChanged
+{
+    pub sector: [i32; 3],
+    pub chunk: [i32; 3],
+    //position inside the chunk
+    pub voxel: [i32; 3],
+    pub old: Voxel,
+    pub new: Voxel,
+}
@@ -0,0 +1,454 @@
+use crate::database::chunk::*;
+use crate::database::mesher::{ChunkMesh, VoxelFilter};
+use crate::database::model::BlockModels;
+use crate::database::sector::SectorsRendering;
+use crate::database::settings::{TextureMode, CHUNK_SIZE};
+use crate::database::texture::{frame_times, TextureAnimation, TextureInfo};
+use crate::database::voxel::*;
+use base64::Engine;
+use bevy::prelude::*;
+use image::{ImageOutputFormat, RgbaImage};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+//block texture atlas built without the asset server or a gpu, packed the same way texture_map and rects are used in game
+pub struct HeadlessAtlas
+{
+    pub image: RgbaImage,
+    pub info: TextureInfo,
+}
+
+impl HeadlessAtlas
+{
+    //loads every png in texture_dir and maps them to voxel types by the names in tex_mem, like build_texture_atlas
+    //textures animated in block_mem are meshed with their first frame
+    pub fn load(texture_dir: impl AsRef<Path>, tex_mem: impl AsRef<Path>, block_mem: impl AsRef<Path>) -> std::io::Result<HeadlessAtlas>
+    {
+        let mut paths: Vec<_> = fs::read_dir(texture_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+            .collect();
+        paths.sort();
+        let mut textures = Vec::with_capacity(paths.len());
+        for path in &paths
+        {
+            let texture = image::open(path).map_err(|error| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), error)))?;
+            textures.push(texture.to_rgba8());
+        }
+        //shelf packing, tallest textures first so every row wastes little space
+        let mut order: Vec<usize> = (0..textures.len()).collect();
+        order.sort_by_key(|index| std::cmp::Reverse(textures[*index].height()));
+        let area: u32 = textures.iter().map(|texture| texture.width() * texture.height()).sum();
+        let widest = textures.iter().map(|texture| texture.width()).max().unwrap_or(1);
+        let width = ((area as f32).sqrt().ceil() as u32).max(widest).next_power_of_two();
+        let mut rects = vec![Rect::default(); textures.len()];
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for index in &order
+        {
+            let texture = &textures[*index];
+            if x + texture.width() > width
+            {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            rects[*index] = Rect::new(x as f32, y as f32, (x + texture.width()) as f32, (y + texture.height()) as f32);
+            x += texture.width();
+            row_height = row_height.max(texture.height());
+        }
+        let height = (y + row_height).max(1);
+        let mut atlas = RgbaImage::new(width, height);
+        for (texture, rect) in textures.iter().zip(&rects)
+        {
+            image::imageops::replace(&mut atlas, texture, rect.min.x as i64, rect.min.y as i64);
+        }
+        let mut map = vec![0; textures.len() + 1];
+        for (voxel_type, name) in fs::read_to_string(tex_mem)?.lines().enumerate()
+        {
+            let texture = paths.iter().position(|path| path.file_stem().is_some_and(|stem| stem.to_string_lossy() == name));
+            if let (Some(texture), Some(entry)) = (texture, map.get_mut(voxel_type))
+            {
+                *entry = texture;
+            }
+        }
+        let frame_times = fs::read_to_string(block_mem).map(|block_mem| frame_times(&block_mem)).unwrap_or_default();
+        let animations = paths.iter().zip(&textures).map(|(path, texture)|
+        {
+            let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+            frame_times.get(&name).and_then(|frame_time| TextureAnimation::from_strip(&name, texture.width(), texture.height(), *frame_time).ok()).unwrap_or_default()
+        }).collect();
+        Ok(HeadlessAtlas
+        {
+            image: atlas,
+            info: TextureInfo { map, rects, size: Vec2::new(width as f32, height as f32), mode: TextureMode::Atlas, animations, layers: Vec::new() },
+        })
+    }
+
+    //average colour of every voxel type's texture, indexed by voxel type. see-through pixels count less
+    pub fn average_colours(&self) -> Vec<[u8; 3]>
+    {
+        VoxelType::ALL.iter().map(|voxel_type|
+        {
+            let Some(rect) = self.info.map.get(*voxel_type as usize).and_then(|texture| self.info.rects.get(*texture)) else { return [0, 0, 0] };
+            let mut sum = Vec3::ZERO;
+            let mut weight = 0.0;
+            for y in rect.min.y as u32..rect.max.y as u32
+            {
+                for x in rect.min.x as u32..rect.max.x as u32
+                {
+                    let pixel = self.image.get_pixel(x, y).0;
+                    let alpha = pixel[3] as f32 / 255.0;
+                    sum += Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) * alpha;
+                    weight += alpha;
+                }
+            }
+            let colour = if weight > 0.0 { sum / weight } else { Vec3::ZERO };
+            [colour.x as u8, colour.y as u8, colour.z as u8]
+        }).collect()
+    }
+
+    pub fn png_bytes(&self) -> std::io::Result<Vec<u8>>
+    {
+        let mut bytes = Vec::new();
+        self.image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png).map_err(|error| Error::other(error.to_string()))?;
+        Ok(bytes)
+    }
+}
+
+//triangle mesh of a region ready to be written out, positions are world positions relative to the sector origin
+#[derive(Default, Debug, Clone)]
+pub struct ExportMesh
+{
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl ExportMesh
+{
+    //adds a finished chunk mesh
+    pub fn append(&mut self, chunk_mesh: ChunkMesh)
+    {
+        let offset = self.positions.len() as u32;
+        self.positions.extend(chunk_mesh.positions);
+        self.normals.extend(chunk_mesh.normals);
+        self.uvs.extend(chunk_mesh.uvs);
+        self.indices.extend(chunk_mesh.indices.into_iter().map(|index| index + offset));
+    }
+
+    pub fn bounds(&self) -> (Vec3, Vec3)
+    {
+        self.positions.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), position| (min.min(Vec3::from(*position)), max.max(Vec3::from(*position))))
+    }
+}
+
+//generates the chunks between min_chunk and max_chunk (inclusive) and meshes them with render_update
+//a ring of chunks around the region is generated too so faces between the region and its surroundings are culled like in game
+pub fn mesh_region(min_chunk: IVec3, max_chunk: IVec3, generator: impl Fn([i32; 3]) -> Vec<Voxel>, atlas: &HeadlessAtlas, block_models: &BlockModels) -> ExportMesh
+{
+    let (low, high) = (min_chunk.min(max_chunk), min_chunk.max(max_chunk));
+    let mut sector = SectorsRendering::default();
+    for x in low.x - 1..=high.x + 1
+    {
+        for y in low.y - 1..=high.y + 1
+        {
+            for z in low.z - 1..=high.z + 1
+            {
+                let chunk = Chunk { block_data: generator([x, y, z]), pos: (x * CHUNK_SIZE, y * CHUNK_SIZE, z * CHUNK_SIZE) };
+                sector.chunks.insert([x, y, z], Some(Arc::new(chunk)));
+            }
+        }
+    }
+    let mut mesh = ExportMesh::default();
+    for x in low.x..=high.x
+    {
+        for y in low.y..=high.y
+        {
+            for z in low.z..=high.z
+            {
+                let Some(neighbourhood) = ChunkNeighbourhood::from_loaded(&sector, [x, y, z]) else { continue };
+                let texture_info = TextureInfo { map: atlas.info.map.clone(), rects: atlas.info.rects.clone(), size: atlas.info.size, mode: TextureMode::Atlas, animations: atlas.info.animations.clone(), layers: Vec::new() };
+                mesh.append(render_update(&neighbourhood, &texture_info, block_models, &VoxelFilter::All).finish());
+            }
+        }
+    }
+    mesh
+}
+
+fn push_floats(buffer: &mut Vec<u8>, values: impl Iterator<Item = f32>)
+{
+    for value in values
+    {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn pad(buffer: &mut Vec<u8>, byte: u8)
+{
+    while !buffer.len().is_multiple_of(4)
+    {
+        buffer.push(byte);
+    }
+}
+
+//glTF json and the binary buffer it points into. the atlas is either inside the buffer (glb) or a data uri (gltf)
+//an empty mesh is an error, glTF needs a POSITION accessor with real bounds and at least one vertex
+fn gltf_parts(mesh: &ExportMesh, atlas_png: &[u8], embed_image_in_buffer: bool) -> std::io::Result<(String, Vec<u8>)>
+{
+    if mesh.positions.is_empty() || mesh.indices.is_empty()
+    {
+        return Err(Error::new(ErrorKind::InvalidInput, "nothing to export, the region has no visible faces"));
+    }
+    let mut buffer = Vec::new();
+    let mut views = Vec::new();
+    let mut view = |buffer: &mut Vec<u8>, start: usize, target: Option<u32>|
+    {
+        let target = target.map_or(String::new(), |target| format!(",\"target\":{}", target));
+        views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}{}}}", start, buffer.len() - start, target));
+        pad(buffer, 0);
+    };
+    push_floats(&mut buffer, mesh.positions.iter().flatten().copied());
+    view(&mut buffer, 0, Some(34962));
+    let start = buffer.len();
+    push_floats(&mut buffer, mesh.normals.iter().flatten().copied());
+    view(&mut buffer, start, Some(34962));
+    let start = buffer.len();
+    push_floats(&mut buffer, mesh.uvs.iter().flatten().copied());
+    view(&mut buffer, start, Some(34962));
+    let start = buffer.len();
+    for index in &mesh.indices
+    {
+        buffer.extend_from_slice(&index.to_le_bytes());
+    }
+    view(&mut buffer, start, Some(34963));
+    let image = if embed_image_in_buffer
+    {
+        let start = buffer.len();
+        buffer.extend_from_slice(atlas_png);
+        view(&mut buffer, start, None);
+        "{\"bufferView\":4,\"mimeType\":\"image/png\"}".to_string()
+    }
+    else
+    {
+        format!("{{\"uri\":\"data:image/png;base64,{}\"}}", base64::engine::general_purpose::STANDARD.encode(atlas_png))
+    };
+    let (min, max) = mesh.bounds();
+    let vertex_count = mesh.positions.len();
+    let json = format!(
+        concat!(
+            "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"voxel_engine\"}},\"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],",
+            "\"meshes\":[{{\"primitives\":[{{\"attributes\":{{\"POSITION\":0,\"NORMAL\":1,\"TEXCOORD_0\":2}},\"indices\":3,\"material\":0}}]}}],",
+            "\"materials\":[{{\"pbrMetallicRoughness\":{{\"baseColorTexture\":{{\"index\":0}},\"metallicFactor\":0.0,\"roughnessFactor\":1.0}},\"alphaMode\":\"MASK\"}}],",
+            "\"textures\":[{{\"sampler\":0,\"source\":0}}],\"samplers\":[{{\"magFilter\":9728,\"minFilter\":9728}}],\"images\":[{}],",
+            "\"accessors\":[",
+            "{{\"bufferView\":0,\"componentType\":5126,\"count\":{},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]}},",
+            "{{\"bufferView\":1,\"componentType\":5126,\"count\":{},\"type\":\"VEC3\"}},",
+            "{{\"bufferView\":2,\"componentType\":5126,\"count\":{},\"type\":\"VEC2\"}},",
+            "{{\"bufferView\":3,\"componentType\":5125,\"count\":{},\"type\":\"SCALAR\"}}],",
+            "\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}{}}}]}}"
+        ),
+        image,
+        vertex_count, min.x, min.y, min.z, max.x, max.y, max.z,
+        vertex_count,
+        vertex_count,
+        mesh.indices.len(),
+        views.join(","),
+        buffer.len(),
+        if embed_image_in_buffer { String::new() } else { format!(",\"uri\":\"data:application/octet-stream;base64,{}\"", base64::engine::general_purpose::STANDARD.encode(&buffer)) },
+    );
+    Ok((json, buffer))
+}
+
+//single self contained .gltf, buffer and atlas are embedded as base64
+pub fn write_gltf(path: impl AsRef<Path>, mesh: &ExportMesh, atlas: &HeadlessAtlas) -> std::io::Result<()>
+{
+    let (json, _) = gltf_parts(mesh, &atlas.png_bytes()?, false)?;
+    fs::write(path, json)
+}
+
+//binary glTF with the atlas png inside the binary chunk
+pub fn write_glb(path: impl AsRef<Path>, mesh: &ExportMesh, atlas: &HeadlessAtlas) -> std::io::Result<()>
+{
+    let (json, buffer) = gltf_parts(mesh, &atlas.png_bytes()?, true)?;
+    let mut json = json.into_bytes();
+    pad(&mut json, b' ');
+    let mut glb = Vec::with_capacity(28 + json.len() + buffer.len());
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&((28 + json.len() + buffer.len()) as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&buffer);
+    fs::write(path, glb)
+}
+
+//wavefront obj with a .mtl and the atlas png written next to it under the same file stem
+pub fn write_obj(path: impl AsRef<Path>, mesh: &ExportMesh, atlas: &HeadlessAtlas) -> std::io::Result<()>
+{
+    let path = path.as_ref();
+    let stem = path.file_stem().map_or("export".to_string(), |stem| stem.to_string_lossy().to_string());
+    atlas.image.save(path.with_extension("png")).map_err(|error| Error::other(error.to_string()))?;
+    fs::write(path.with_extension("mtl"), format!("newmtl atlas\nKd 1 1 1\nmap_Kd {}.png\n", stem))?;
+    let mut obj = format!("mtllib {}.mtl\nusemtl atlas\n", stem);
+    for position in &mesh.positions
+    {
+        obj += &format!("v {} {} {}\n", position[0], position[1], position[2]);
+    }
+    for normal in &mesh.normals
+    {
+        obj += &format!("vn {} {} {}\n", normal[0], normal[1], normal[2]);
+    }
+    //obj texture coordinates start at the bottom of the image
+    for uv in &mesh.uvs
+    {
+        obj += &format!("vt {} {}\n", uv[0], 1.0 - uv[1]);
+    }
+    for triangle in mesh.indices.chunks_exact(3)
+    {
+        let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+        obj += &format!("f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}\n");
+    }
+    fs::write(path, obj)
+}
+
+//picks the format from the file extension: glb, gltf or obj
+pub fn write_mesh(path: impl AsRef<Path>, mesh: &ExportMesh, atlas: &HeadlessAtlas) -> std::io::Result<()>
+{
+    let path = path.as_ref();
+    match path.extension().and_then(|extension| extension.to_str())
+    {
+        Some("glb") => write_glb(path, mesh, atlas),
+        Some("gltf") => write_gltf(path, mesh, atlas),
+        Some("obj") => write_obj(path, mesh, atlas),
+        _ => Err(Error::new(ErrorKind::InvalidInput, "export path has to end in .glb, .gltf or .obj")),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::model::configured_block_models;
+    use crate::database::settings::CHUNK_SIZE;
+
+    fn atlas() -> HeadlessAtlas
+    {
+        HeadlessAtlas::load("Assets/textures/blocks", "Assets/TexMem", "Assets/BlockMem").unwrap()
+    }
+
+    //one stone block in chunk 0,0,0 and air everywhere else
+    fn single_block(chunk_position: [i32; 3]) -> Vec<Voxel>
+    {
+        let mut voxels = vec![Voxel::default(); (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize];
+        if chunk_position == [0, 0, 0]
+        {
+            voxels[0] = Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::default());
+        }
+        voxels
+    }
+
+    fn out_path(name: &str) -> std::path::PathBuf
+    {
+        let directory = std::env::temp_dir().join(format!("voxel_engine_export_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory.join(name)
+    }
+
+    fn json(text: &str) -> serde_json::Value
+    {
+        serde_json::from_str(text).unwrap()
+    }
+
+    //checks the accessors against the mesh, the position bounds have to be the unit cube of the block
+    fn check_gltf(gltf: &serde_json::Value, mesh: &ExportMesh, buffer_length: usize)
+    {
+        let accessors = gltf["accessors"].as_array().unwrap();
+        assert_eq!(accessors[0]["count"], mesh.positions.len());
+        let bound = |name: &str| -> Vec<f64> { accessors[0][name].as_array().unwrap().iter().map(|value| value.as_f64().unwrap()).collect() };
+        assert_eq!(bound("min"), vec![0.0; 3]);
+        assert_eq!(bound("max"), vec![1.0; 3]);
+        assert_eq!(accessors[3]["count"], mesh.indices.len());
+        assert_eq!(gltf["buffers"][0]["byteLength"], buffer_length);
+        for view in gltf["bufferViews"].as_array().unwrap()
+        {
+            assert!(view["byteOffset"].as_u64().unwrap() + view["byteLength"].as_u64().unwrap() <= buffer_length as u64);
+        }
+    }
+
+    #[test]
+    fn meshes_the_region_only()
+    {
+        let mesh = mesh_region(IVec3::ZERO, IVec3::ZERO, single_block, &atlas(), &configured_block_models());
+        assert_eq!(mesh.indices.len(), 6 * 6);
+        assert_eq!(mesh.positions.len(), mesh.normals.len());
+        assert_eq!(mesh.positions.len(), mesh.uvs.len());
+        assert_eq!(mesh.bounds(), (Vec3::ZERO, Vec3::ONE));
+        let elsewhere = mesh_region(IVec3::ONE, IVec3::ONE, single_block, &atlas(), &configured_block_models());
+        assert!(elsewhere.indices.is_empty());
+    }
+
+    #[test]
+    fn writes_gltf_and_glb()
+    {
+        let atlas = atlas();
+        let mesh = mesh_region(IVec3::ZERO, IVec3::ZERO, single_block, &atlas, &configured_block_models());
+
+        let path = out_path("block.gltf");
+        write_mesh(&path, &mesh, &atlas).unwrap();
+        let gltf = json(&fs::read_to_string(&path).unwrap());
+        let uri = gltf["buffers"][0]["uri"].as_str().unwrap();
+        let buffer = base64::engine::general_purpose::STANDARD.decode(uri.trim_start_matches("data:application/octet-stream;base64,")).unwrap();
+        check_gltf(&gltf, &mesh, buffer.len());
+
+        let path = out_path("block.glb");
+        write_mesh(&path, &mesh, &atlas).unwrap();
+        let glb = fs::read(&path).unwrap();
+        let word = |at: usize| u32::from_le_bytes(glb[at..at + 4].try_into().unwrap()) as usize;
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8), glb.len());
+        let json_length = word(12);
+        assert_eq!(&glb[16..20], b"JSON");
+        assert!(json_length.is_multiple_of(4));
+        let gltf = json(std::str::from_utf8(&glb[20..20 + json_length]).unwrap());
+        let binary_length = word(20 + json_length);
+        assert_eq!(&glb[24 + json_length..28 + json_length], b"BIN\0");
+        assert_eq!(28 + json_length + binary_length, glb.len());
+        check_gltf(&gltf, &mesh, binary_length);
+        assert_eq!(gltf["images"][0]["bufferView"], 4);
+    }
+
+    #[test]
+    fn writes_obj_with_material_and_atlas()
+    {
+        let atlas = atlas();
+        let mesh = mesh_region(IVec3::ZERO, IVec3::ZERO, single_block, &atlas, &configured_block_models());
+        let path = out_path("block.obj");
+        write_mesh(&path, &mesh, &atlas).unwrap();
+        let obj = fs::read_to_string(&path).unwrap();
+        let count = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+        assert_eq!(count("v "), mesh.positions.len());
+        assert_eq!(count("vn "), mesh.normals.len());
+        assert_eq!(count("vt "), mesh.uvs.len());
+        assert_eq!(count("f "), mesh.indices.len() / 3);
+        assert!(obj.starts_with("mtllib block.mtl\n"));
+        assert!(fs::read_to_string(path.with_extension("mtl")).unwrap().contains("map_Kd block.png"));
+        assert!(image::open(path.with_extension("png")).is_ok());
+    }
+
+    #[test]
+    fn refuses_empty_gltf()
+    {
+        let atlas = atlas();
+        let mesh = ExportMesh::default();
+        assert!(write_mesh(out_path("empty.gltf"), &mesh, &atlas).is_err());
+        assert!(write_mesh(out_path("empty.glb"), &mesh, &atlas).is_err());
+        assert!(write_mesh(out_path("block.fbx"), &mesh, &atlas).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use crate::database::edit::*;
+use crate::database::scheduler::BlockUpdates;
+use crate::database::physics::collision_boxes;
+use crate::database::sector::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+//cellular automaton for water and lava. only positions next to a recent voxel change are simulated,
+//so only chunks with moving fluid do any work
+#[derive(Resource)]
+pub struct FluidSimulation
+{
+    //seconds between fluid ticks
+    pub water_tick: f32,
+    pub lava_tick: f32,
+    //how much the level drops per voxel of sideways spread
+    pub water_falloff: u8,
+    pub lava_falloff: u8,
+    water_timer: f32,
+    lava_timer: f32,
+    //positions relative to their sector origin waiting for the next tick of each fluid
+    water_pending: HashSet<([i32; 3], IVec3)>,
+    lava_pending: HashSet<([i32; 3], IVec3)>,
+}
+
+impl Default for FluidSimulation
+{
+    fn default() -> Self
+    {
+        FluidSimulation
+        {
+            water_tick: 0.25,
+            lava_tick: 1.5,
+            water_falloff: 1,
+            lava_falloff: 2,
+            water_timer: 0.0,
+            lava_timer: 0.0,
+            water_pending: HashSet::new(),
+            lava_pending: HashSet::new(),
+        }
+    }
+}
+
+impl FluidSimulation
+{
+    //queues a position for the next tick of both fluids
+    pub fn schedule(&mut self, sector: [i32; 3], position: IVec3)
+    {
+        self.water_pending.insert((sector, position));
+        self.lava_pending.insert((sector, position));
+    }
+}
+
+//voxels fluid can flow into
+fn replaceable(voxel: &Voxel) -> bool
+{
+    voxel.voxel_type == VoxelType::Air || (voxel.is_fluid() && !voxel.fluid.source)
+}
+
+//fluid in a voxel spreads sideways only when it cannot fall, resting on something solid or on a still body of fluid
+fn supported(world: &impl VoxelAccess, position: IVec3) -> bool
+{
+    world.voxel(position + IVec3::NEG_Y).is_none_or(|below| (below.is_fluid() && below.fluid.source) || !collision_boxes(below).is_empty())
+}
+
+fn fluid_voxel(fluid: VoxelType, state: FluidState) -> Voxel
+{
+    let mut voxel = Voxel::new(fluid, VoxelVariant::Block, VoxelRotation::Forward);
+    voxel.fluid = state;
+    //partly filled voxels do not hide the faces next to them
+    voxel.solid = state.level == 7;
+    voxel
+}
+
+//surface height of the fluid in a voxel from 0 to 1, None if the voxel holds none of that fluid
+pub fn fluid_height(world: &impl VoxelAccess, position: IVec3, fluid: VoxelType) -> Option<f32>
+{
+    let voxel = world.voxel(position).filter(|voxel| voxel.voxel_type == fluid)?;
+    if world.voxel(position + IVec3::Y).is_some_and(|above| above.voxel_type == fluid)
+    {
+        return Some(1.0);
+    }
+    Some((voxel.fluid.level as f32 + 1.0) / 9.0)
+}
+
+//height of the four top corners of a fluid voxel, indexed [x][z]
+//each corner averages the fluid voxels sharing it so neighbouring surfaces meet
+pub fn fluid_corner_heights(world: &impl VoxelAccess, position: IVec3, fluid: VoxelType) -> [[f32; 2]; 2]
+{
+    let mut corners = [[1.0; 2]; 2];
+    for (corner_x, column) in corners.iter_mut().enumerate()
+    {
+        for (corner_z, corner) in column.iter_mut().enumerate()
+        {
+            let heights: Vec<f32> = [(-1, -1), (-1, 0), (0, -1), (0, 0)].iter()
+                .filter_map(|(x, z)| fluid_height(world, position + IVec3::new(corner_x as i32 + x, 0, corner_z as i32 + z), fluid))
+                .collect();
+            if !heights.is_empty() && !heights.contains(&1.0)
+            {
+                *corner = heights.iter().sum::<f32>() / heights.len() as f32;
+            }
+        }
+    }
+    corners
+}
+
+//level a voxel gets from the fluid beside it, None if nothing flows in
+fn spread_state(world: &impl VoxelAccess, position: IVec3, fluid: VoxelType, falloff: u8) -> Option<FluidState>
+{
+    let is_fluid = |voxel: &Voxel| voxel.voxel_type == fluid;
+    let mut next: Option<FluidState> = None;
+    let mut sources = 0;
+    for offset in HORIZONTAL
+    {
+        let neighbour_position = position + offset;
+        let Some(neighbour) = world.voxel(neighbour_position) else { continue };
+        if !is_fluid(neighbour) || !supported(world, neighbour_position)
+        {
+            continue;
+        }
+        if neighbour.fluid.source
+        {
+            sources += 1;
+        }
+        let Some(level) = neighbour.fluid.level.checked_sub(falloff) else { continue };
+        if next.is_none_or(|next| level > next.level)
+        {
+            //flows away from the neighbour it came from
+            let flow = match offset.to_array()
+            {
+                [1, _, _] => FlowDirection::NegX,
+                [-1, _, _] => FlowDirection::PosX,
+                [_, _, 1] => FlowDirection::NegZ,
+                _ => FlowDirection::PosZ,
+            };
+            next = Some(FluidState { level, source: false, flow });
+        }
+    }
+    //water between two sources on solid ground becomes a source itself
+    if fluid == VoxelType::Water && sources >= 2 && world.voxel(position + IVec3::NEG_Y).is_some_and(|below| !collision_boxes(below).is_empty() || (is_fluid(below) && below.fluid.source))
+    {
+        next = Some(FluidState::SOURCE);
+    }
+    next
+}
+
+//what a voxel turns into on the next tick of a fluid, None if it stays the same
+pub fn next_fluid_state(world: &impl VoxelAccess, position: IVec3, fluid: VoxelType, falloff: u8) -> Option<Voxel>
+{
+    let current = world.voxel(position)?;
+    let other = if fluid == VoxelType::Water { VoxelType::Lava } else { VoxelType::Water };
+
+    //lava touching water hardens, sources into stone and flowing lava into ash
+    if current.voxel_type == VoxelType::Lava && fluid == VoxelType::Lava
+    {
+        let touches_water = [IVec3::Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z].iter()
+            .any(|offset| world.voxel(position + *offset).is_some_and(|neighbour| neighbour.voxel_type == VoxelType::Water));
+        if touches_water
+        {
+            let hardened = if current.fluid.source { VoxelType::Stone } else { VoxelType::Ash };
+            return Some(Voxel::new(hardened, VoxelVariant::Block, VoxelRotation::Forward));
+        }
+    }
+    if current.voxel_type == other || !replaceable(current) || (current.voxel_type == fluid && current.fluid.source)
+    {
+        return None;
+    }
+
+    //fluid above falls in as a full voxel
+    let falling = world.voxel(position + IVec3::Y).is_some_and(|above| above.voxel_type == fluid);
+    let next = if falling { Some(FluidState { level: 7, source: false, flow: FlowDirection::Down }) } else { spread_state(world, position, fluid, falloff) };
+
+    match next
+    {
+        Some(state) if current.voxel_type == fluid && current.fluid == state => None,
+        Some(state) => Some(fluid_voxel(fluid, state)),
+        //flowing fluid with nothing feeding it drains away
+        None if current.voxel_type == fluid => Some(Voxel::default()),
+        None => None,
+    }
+}
+
+//every voxel change wakes the fluid around it
+pub fn schedule_fluid_updates
+(
+    mut simulation: ResMut<FluidSimulation>,
+    block_updates: Res<BlockUpdates>,
+)
+{
+    for (sector, position) in &block_updates.positions
+    {
+        simulation.schedule(*sector, *position);
+    }
+}
+
+pub fn simulate_fluids
+(
+    time: Res<Time>,
+    mut simulation: ResMut<FluidSimulation>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    mut edits: EventWriter<SetVoxel>,
+)
+{
+    let simulation = &mut *simulation;
+    simulation.water_timer += time.delta_seconds();
+    simulation.lava_timer += time.delta_seconds();
+    for fluid in [VoxelType::Water, VoxelType::Lava]
+    {
+        let (timer, tick, falloff) = match fluid
+        {
+            VoxelType::Water => (&mut simulation.water_timer, simulation.water_tick, simulation.water_falloff),
+            _ => (&mut simulation.lava_timer, simulation.lava_tick, simulation.lava_falloff),
+        };
+        if *timer < tick
+        {
+            continue;
+        }
+        *timer -= tick;
+        let pending = match fluid
+        {
+            VoxelType::Water => std::mem::take(&mut simulation.water_pending),
+            _ => std::mem::take(&mut simulation.lava_pending),
+        };
+        //every new state is worked out from the same snapshot before any of them are applied
+        let mut changes: HashMap<([i32; 3], IVec3), Voxel> = HashMap::new();
+        for (sector, position) in pending
+        {
+            //positions in chunks that are not loaded are dropped, they wake again when something changes next to them
+            let Some(loaded_sector) = loaded_sectors.sectors.get(&sector) else { continue };
+            if let Some(voxel) = next_fluid_state(loaded_sector, position, fluid, falloff)
+            {
+                changes.insert((sector, position), voxel);
+            }
+        }
+        //applied through the normal edit path, the resulting VoxelChanged events schedule the next tick
+        for ((sector, position), voxel) in changes
+        {
+            edits.send(SetVoxel { sector, position, voxel, undoable: false });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::chunk::{voxel_index, Chunk};
+    use crate::database::edit::test_world;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn stone() -> Voxel
+    {
+        Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    //stone floor at y 0 under chunks 0,0,0 and 1,0,0 with the given voxels on top
+    fn floor_world(voxels: &[(IVec3, Voxel)]) -> std::collections::HashMap<[i32; 3], Chunk>
+    {
+        let mut placed: Vec<(IVec3, Voxel)> = Vec::new();
+        for x in 0..64
+        {
+            for z in 0..16
+            {
+                placed.push((IVec3::new(x, 0, z), stone()));
+            }
+        }
+        placed.extend_from_slice(voxels);
+        test_world([0, 0, 0], [1, 0, 0], &placed)
+    }
+
+    fn set(world: &mut std::collections::HashMap<[i32; 3], Chunk>, position: IVec3, voxel: Voxel)
+    {
+        let (chunk, local) = voxel_to_chunk(position);
+        world.get_mut(&chunk).unwrap().block_data[voxel_index(local[0], local[1], local[2])] = voxel;
+    }
+
+    //ticks one fluid over the layer above the floor until nothing changes, every tick works from one snapshot
+    fn settle(world: &mut std::collections::HashMap<[i32; 3], Chunk>, fluid: VoxelType, falloff: u8)
+    {
+        for _ in 0..32
+        {
+            let mut changes = Vec::new();
+            for x in 0..64
+            {
+                for z in 0..16
+                {
+                    let position = IVec3::new(x, 1, z);
+                    if let Some(voxel) = next_fluid_state(world, position, fluid, falloff)
+                    {
+                        changes.push((position, voxel));
+                    }
+                }
+            }
+            if changes.is_empty()
+            {
+                return;
+            }
+            for (position, voxel) in changes
+            {
+                set(world, position, voxel);
+            }
+        }
+        panic!("fluid did not settle");
+    }
+
+    fn source(fluid: VoxelType) -> Voxel
+    {
+        fluid_voxel(fluid, FluidState::SOURCE)
+    }
+
+    fn level(world: &std::collections::HashMap<[i32; 3], Chunk>, position: IVec3, fluid: VoxelType) -> Option<u8>
+    {
+        world.voxel(position).filter(|voxel| voxel.voxel_type == fluid).map(|voxel| voxel.fluid.level)
+    }
+
+    #[test]
+    fn water_spreads_with_falloff()
+    {
+        let mut world = floor_world(&[(IVec3::new(20, 1, 8), source(VoxelType::Water))]);
+        settle(&mut world, VoxelType::Water, 1);
+        for distance in 1..=7
+        {
+            assert_eq!(level(&world, IVec3::new(20 + distance, 1, 8), VoxelType::Water), Some(7 - distance as u8));
+            assert_eq!(level(&world, IVec3::new(20, 1, 8 - distance), VoxelType::Water), Some(7 - distance as u8));
+        }
+        assert_eq!(level(&world, IVec3::new(28, 1, 8), VoxelType::Water), None);
+        //diamond shaped, one step in each direction costs a level
+        assert_eq!(level(&world, IVec3::new(23, 1, 11), VoxelType::Water), Some(1));
+        //the flow points away from the source
+        assert_eq!(world.voxel(IVec3::new(21, 1, 8)).unwrap().fluid.flow, FlowDirection::PosX);
+    }
+
+    #[test]
+    fn water_drains_without_a_source()
+    {
+        let mut world = floor_world(&[(IVec3::new(20, 1, 8), source(VoxelType::Water))]);
+        settle(&mut world, VoxelType::Water, 1);
+        set(&mut world, IVec3::new(20, 1, 8), Voxel::default());
+        settle(&mut world, VoxelType::Water, 1);
+        assert!((0..64).all(|x| level(&world, IVec3::new(x, 1, 8), VoxelType::Water).is_none()));
+    }
+
+    #[test]
+    fn two_sources_make_a_third()
+    {
+        let mut world = floor_world(&[(IVec3::new(20, 1, 8), source(VoxelType::Water)), (IVec3::new(22, 1, 8), source(VoxelType::Water))]);
+        settle(&mut world, VoxelType::Water, 1);
+        assert!(world.voxel(IVec3::new(21, 1, 8)).unwrap().fluid.source);
+        //only one source next to it
+        assert!(!world.voxel(IVec3::new(23, 1, 8)).unwrap().fluid.source);
+    }
+
+    #[test]
+    fn lava_spreads_less_far_than_water()
+    {
+        let simulation = FluidSimulation::default();
+        let mut world = floor_world(&[(IVec3::new(20, 1, 8), source(VoxelType::Lava))]);
+        settle(&mut world, VoxelType::Lava, simulation.lava_falloff);
+        assert_eq!(level(&world, IVec3::new(23, 1, 8), VoxelType::Lava), Some(1));
+        assert_eq!(level(&world, IVec3::new(24, 1, 8), VoxelType::Lava), None);
+    }
+
+    #[test]
+    fn lava_ticks_slower_than_water()
+    {
+        let water = IVec3::new(20, 1, 8);
+        let lava = IVec3::new(20, 1, 4);
+        let mut sector = SectorsRendering::default();
+        for (chunk_id, chunk) in floor_world(&[(water, source(VoxelType::Water)), (lava, source(VoxelType::Lava))])
+        {
+            sector.chunks.insert(chunk_id, Some(Arc::new(chunk)));
+        }
+        let mut loaded = CurrentlyLoaded::default();
+        loaded.sectors.insert([0, 0, 0], sector);
+        let mut simulation = FluidSimulation::default();
+        simulation.schedule([0, 0, 0], water + IVec3::X);
+        simulation.schedule([0, 0, 0], lava + IVec3::X);
+        let water_tick = simulation.water_tick;
+
+        let mut app = App::new();
+        app.insert_resource(Time::<()>::default())
+            .insert_resource(loaded)
+            .insert_resource(simulation)
+            .add_event::<SetVoxel>()
+            .add_systems(Update, simulate_fluids);
+        let edited = |app: &mut App| -> Vec<VoxelType>
+        {
+            app.world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(water_tick));
+            app.update();
+            let events = app.world.resource::<Events<SetVoxel>>();
+            events.get_reader().read(events).map(|edit| edit.voxel.voxel_type).collect()
+        };
+        //the first water tick moves only water, lava waits for its own longer tick
+        assert_eq!(edited(&mut app), vec![VoxelType::Water]);
+        let ticks = (FluidSimulation::default().lava_tick / water_tick).ceil() as usize;
+        let lava_moved = (1..ticks).any(|_| edited(&mut app).contains(&VoxelType::Lava));
+        assert!(lava_moved);
+    }
+
+    #[test]
+    fn lava_touching_water_hardens()
+    {
+        let mut flowing = source(VoxelType::Lava);
+        flowing.fluid = FluidState { level: 5, source: false, flow: FlowDirection::PosX };
+        let world = floor_world(&[
+            (IVec3::new(20, 1, 8), source(VoxelType::Lava)),
+            (IVec3::new(21, 1, 8), source(VoxelType::Water)),
+            (IVec3::new(30, 1, 8), flowing),
+            (IVec3::new(30, 1, 9), source(VoxelType::Water)),
+        ]);
+        assert_eq!(next_fluid_state(&world, IVec3::new(20, 1, 8), VoxelType::Lava, 2).map(|voxel| voxel.voxel_type), Some(VoxelType::Stone));
+        assert_eq!(next_fluid_state(&world, IVec3::new(30, 1, 8), VoxelType::Lava, 2).map(|voxel| voxel.voxel_type), Some(VoxelType::Ash));
+        //water never flows into lava
+        assert_eq!(next_fluid_state(&world, IVec3::new(20, 1, 8), VoxelType::Water, 1), None);
+    }
+
+    #[test]
+    fn flows_across_chunk_borders()
+    {
+        let mut world = floor_world(&[(IVec3::new(29, 1, 8), source(VoxelType::Water))]);
+        settle(&mut world, VoxelType::Water, 1);
+        assert_eq!(level(&world, IVec3::new(32, 1, 8), VoxelType::Water), Some(4));
+        assert_eq!(level(&world, IVec3::new(36, 1, 8), VoxelType::Water), Some(0));
+    }
+
+    #[test]
+    fn falls_before_spreading()
+    {
+        let mut world = floor_world(&[(IVec3::new(20, 3, 8), source(VoxelType::Water))]);
+        let below = next_fluid_state(&world, IVec3::new(20, 2, 8), VoxelType::Water, 1).unwrap();
+        assert_eq!((below.fluid.level, below.fluid.flow), (7, FlowDirection::Down));
+        //nothing spreads sideways from water that can still fall
+        set(&mut world, IVec3::new(20, 2, 8), below);
+        assert_eq!(next_fluid_state(&world, IVec3::new(21, 2, 8), VoxelType::Water, 1), None);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::database::chunk::*;
+use crate::database::settings::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use image::RgbImage;
+use std::path::Path;
+use std::sync::Arc;
+
+//fills in the voxel data of a chunk. implementations run on the async compute pool so they have to be Send and Sync
+pub trait WorldGenerator: Send + Sync
+{
+    fn generate(&self, chunk_position: [i32; 3]) -> Vec<Voxel>;
+
+    //the voxel filling the whole cube of size voxels at min, None if it is mixed or too costly to tell
+    //lets the octree builder skip over empty sky and solid rock without generating them
+    fn uniform_region(&self, _min: IVec3, _size: i32) -> Option<Voxel>
+    {
+        None
+    }
+
+    //a single voxel without generating its chunk, None if the generator can only make whole chunks
+    fn voxel(&self, _position: IVec3) -> Option<Voxel>
+    {
+        None
+    }
+}
+
+//the generator load_chunks and undo into unloaded chunks use, swap it before chunks start loading
+#[derive(Resource, Clone)]
+pub struct ActiveGenerator(pub Arc<dyn WorldGenerator>);
+
+impl Default for ActiveGenerator
+{
+    fn default() -> Self
+    {
+        ActiveGenerator(Arc::new(NoiseGenerator { seed: WORLD_SEED }))
+    }
+}
+
+//the spline shaped noise terrain from generate_chunk
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseGenerator
+{
+    pub seed: u32,
+}
+
+impl WorldGenerator for NoiseGenerator
+{
+    fn generate(&self, chunk_position: [i32; 3]) -> Vec<Voxel>
+    {
+        generate_chunk_with_seed(chunk_position, self.seed)
+    }
+}
+
+//what happens to columns outside of the heightmap image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeightmapWrap
+{
+    //the image repeats in every direction
+    #[default]
+    Tile,
+    //the edge pixels stretch out forever
+    Clamp,
+}
+
+//terrain from a greyscale image, one pixel per column
+pub struct HeightmapGenerator
+{
+    width: u32,
+    depth: u32,
+    //every pixel normalised to 0..=1, 8 and 16 bit images end up the same
+    heights: Vec<f32>,
+    //optional colour or splat map the same size as the heightmap, picks the surface block of every column
+    surface: Option<RgbImage>,
+    //column the top left pixel sits on
+    pub origin: IVec2,
+    //height of a white pixel above base_height. 255 with base_height 0 reads back the height.png from the maps command 1:1
+    pub vertical_scale: f32,
+    pub base_height: i32,
+    pub wrap: HeightmapWrap,
+    //voxels of dirt between the surface and the stone below
+    pub dirt_depth: i32,
+    //columns below this are flooded with water sources, None for no water
+    pub sea_level: Option<i32>,
+    //splat map colours and the surface block they stand for, the closest colour wins
+    pub surface_colours: Vec<([u8; 3], VoxelType)>,
+}
+
+impl HeightmapGenerator
+{
+    pub fn new(heightmap: GrayImage16) -> HeightmapGenerator
+    {
+        HeightmapGenerator
+        {
+            width: heightmap.width(),
+            depth: heightmap.height(),
+            heights: heightmap.pixels().map(|pixel| pixel.0[0] as f32 / u16::MAX as f32).collect(),
+            surface: None,
+            origin: IVec2::ZERO,
+            vertical_scale: 255.0,
+            base_height: 0,
+            wrap: HeightmapWrap::Tile,
+            dirt_depth: 3,
+            sea_level: Some(SEA_LEVEL),
+            surface_colours: vec![
+                ([90, 170, 70], VoxelType::Grass),
+                ([40, 110, 40], VoxelType::JungleGrass),
+                ([170, 160, 80], VoxelType::SavannahGrass),
+                ([70, 90, 60], VoxelType::SwampGrass),
+                ([220, 210, 150], VoxelType::Sand),
+                ([190, 100, 50], VoxelType::RedSand),
+                ([130, 120, 110], VoxelType::Stone),
+                ([120, 85, 60], VoxelType::Dirt),
+                ([240, 245, 250], VoxelType::SnowBlock),
+                ([40, 70, 180], VoxelType::Sand),
+            ],
+        }
+    }
+
+    //loads an 8 or 16 bit greyscale png, colour images are turned grey first
+    pub fn load(path: impl AsRef<Path>) -> image::ImageResult<HeightmapGenerator>
+    {
+        Ok(HeightmapGenerator::new(image::open(path)?.to_luma16()))
+    }
+
+    //colour or splat map for the surface blocks, has to match the heightmap size
+    pub fn with_surface_map(mut self, path: impl AsRef<Path>) -> image::ImageResult<HeightmapGenerator>
+    {
+        let surface = image::open(path)?.to_rgb8();
+        if surface.dimensions() != (self.width, self.depth)
+        {
+            return Err(image::ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::DimensionMismatch)));
+        }
+        self.surface = Some(surface);
+        Ok(self)
+    }
+
+    //pixel a column reads from
+    fn pixel(&self, column: IVec2) -> (u32, u32)
+    {
+        let local = column - self.origin;
+        match self.wrap
+        {
+            HeightmapWrap::Tile => (local.x.rem_euclid(self.width as i32) as u32, local.y.rem_euclid(self.depth as i32) as u32),
+            HeightmapWrap::Clamp => (local.x.clamp(0, self.width as i32 - 1) as u32, local.y.clamp(0, self.depth as i32 - 1) as u32),
+        }
+    }
+
+    pub fn height(&self, column: IVec2) -> i32
+    {
+        let (x, z) = self.pixel(column);
+        self.base_height + (self.heights[(z * self.width + x) as usize] * self.vertical_scale).round() as i32
+    }
+
+    pub fn surface_type(&self, column: IVec2) -> VoxelType
+    {
+        let Some(surface) = &self.surface else { return VoxelType::Grass };
+        let (x, z) = self.pixel(column);
+        let colour = surface.get_pixel(x, z).0;
+        let distance = |reference: &[u8; 3]| (0..3).map(|channel| (colour[channel] as i32 - reference[channel] as i32).pow(2)).sum::<i32>();
+        self.surface_colours.iter().min_by_key(|(reference, _)| distance(reference)).map_or(VoxelType::Grass, |(_, voxel_type)| *voxel_type)
+    }
+
+    fn column(&self, column: IVec2) -> HeightmapColumn
+    {
+        let height = self.height(column);
+        let underwater = self.sea_level.is_some_and(|sea_level| height <= sea_level);
+        let surface_type = if underwater { VoxelType::Sand } else { self.surface_type(column) };
+        //sandy surfaces keep going down instead of turning into dirt
+        let layer_type = if matches!(surface_type, VoxelType::Sand | VoxelType::RedSand | VoxelType::Stone) { surface_type } else { VoxelType::Dirt };
+        HeightmapColumn { height, surface_type, layer_type, dirt_depth: self.dirt_depth, sea_level: self.sea_level }
+    }
+}
+
+type GrayImage16 = image::ImageBuffer<image::Luma<u16>, Vec<u16>>;
+
+//everything generate needs to know about one heightmap column
+struct HeightmapColumn
+{
+    height: i32,
+    surface_type: VoxelType,
+    layer_type: VoxelType,
+    dirt_depth: i32,
+    sea_level: Option<i32>,
+}
+
+impl HeightmapColumn
+{
+    //same layering as the noise terrain: surface block, dirt, then stone, with sand under the sea. None for air
+    fn voxel_type(&self, world_y: i32) -> Option<VoxelType>
+    {
+        match self.height - world_y
+        {
+            0 => Some(self.surface_type),
+            depth if depth > 0 && depth <= self.dirt_depth => Some(self.layer_type),
+            depth if depth > 0 => Some(VoxelType::Stone),
+            _ if self.sea_level.is_some_and(|sea_level| world_y <= sea_level) => Some(VoxelType::Water),
+            _ => None,
+        }
+    }
+}
+
+impl WorldGenerator for HeightmapGenerator
+{
+    fn generate(&self, chunk_position: [i32; 3]) -> Vec<Voxel>
+    {
+        let mut data = vec![Voxel::default(); CHUNK_SIZE.pow(3) as usize];
+        if self.width == 0 || self.depth == 0
+        {
+            return data;
+        }
+        let offset = IVec3::from(chunk_position) * CHUNK_SIZE;
+        for x in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                let column = self.column(IVec2::new(offset.x + x, offset.z + z));
+                for y in 0..CHUNK_SIZE
+                {
+                    if let Some(voxel_type) = column.voxel_type(offset.y + y)
+                    {
+                        data[voxel_index(x, y, z)] = Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default());
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    //all air above the highest column and all stone below the dirt of the lowest one
+    fn uniform_region(&self, min: IVec3, size: i32) -> Option<Voxel>
+    {
+        if self.width == 0 || self.depth == 0
+        {
+            return Some(Voxel::default());
+        }
+        let (mut lowest, mut highest) = (i32::MAX, i32::MIN);
+        for x in min.x..min.x + size
+        {
+            for z in min.z..min.z + size
+            {
+                let height = self.height(IVec2::new(x, z));
+                lowest = lowest.min(height);
+                highest = highest.max(height);
+            }
+        }
+        let top = min.y + size - 1;
+        if min.y > highest && self.sea_level.is_none_or(|sea_level| min.y > sea_level)
+        {
+            return Some(Voxel::default());
+        }
+        if min.y > highest && self.sea_level.is_some_and(|sea_level| top <= sea_level)
+        {
+            return Some(Voxel::new(VoxelType::Water, VoxelVariant::Block, VoxelRotation::default()));
+        }
+        (top < lowest - self.dirt_depth).then(|| Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::default()))
+    }
+
+    fn voxel(&self, position: IVec3) -> Option<Voxel>
+    {
+        if self.width == 0 || self.depth == 0
+        {
+            return Some(Voxel::default());
+        }
+        let voxel_type = self.column(position.xz()).voxel_type(position.y);
+        Some(voxel_type.map_or_else(Voxel::default, |voxel_type| Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default())))
+    }
+}
+
+//uses the heightmap from settings instead of noise terrain when one is set
+pub fn configured_generator() -> ActiveGenerator
+{
+    let Some(path) = HEIGHTMAP else { return ActiveGenerator::default() };
+    let generator = HeightmapGenerator::load(path).and_then(|generator| match HEIGHTMAP_SURFACE
+    {
+        Some(surface) => generator.with_surface_map(surface),
+        None => Ok(generator),
+    });
+    match generator
+    {
+        Ok(generator) => ActiveGenerator(Arc::new(generator)),
+        Err(error) =>
+        {
+            warn!("could not load heightmap {}: {}, using noise terrain", path, error);
+            ActiveGenerator::default()
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::database::edit::*;
+use crate::database::physics::collision_boxes;
+use crate::database::scheduler::*;
+use crate::database::sector::*;
+use crate::database::texture::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::utils::HashSet;
+
+//a voxel that lost its support and is falling as an entity until it lands and turns back into a voxel
+#[derive(Component, Debug, Clone)]
+pub struct FallingBlock
+{
+    pub sector: [i32; 3],
+    pub voxel: Voxel,
+    pub velocity: f32,
+}
+
+pub const FALLING_GRAVITY: f32 = 20.0;
+
+//voxel types that fall when nothing is below them
+pub fn falls(voxel_type: VoxelType) -> bool
+{
+    matches!(voxel_type, VoxelType::Sand | VoxelType::RedSand | VoxelType::Snow | VoxelType::Ash)
+}
+
+//falling blocks pass through air and fluids
+fn passable(voxel: &Voxel) -> bool
+{
+    voxel.voxel_type == VoxelType::Air || voxel.is_fluid()
+}
+
+fn sector_offset(sector: [i32; 3]) -> Vec3
+{
+    Vec3::new(sector[0] as f32, sector[1] as f32, sector[2] as f32)
+}
+
+//unit cube with every face showing the voxel's texture from the atlas
+fn falling_block_mesh(voxel_type: VoxelType, texture_atlas_data: &TextureAtlasBuilt) -> Mesh
+{
+    let uv_rect = block_to_tex(voxel_type, texture_atlas_data.texture_map.clone(), texture_atlas_data.texture_rects.clone(), texture_atlas_data.texture_size);
+    let mut mesh = Mesh::from(shape::Cube { size: 1.0 });
+    if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+    {
+        for uv in uvs.iter_mut()
+        {
+            *uv = [uv_rect.min.x + (uv_rect.max.x - uv_rect.min.x) * uv[0], uv_rect.min.y + (uv_rect.max.y - uv_rect.min.y) * uv[1]];
+        }
+    }
+    mesh
+}
+
+//gravity check for every position next to a change, unsupported voxels are swapped for falling entities
+pub fn start_falling_blocks
+(
+    mut commands: Commands,
+    block_updates: Res<BlockUpdates>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    texture_atlas_data: Res<TextureAtlasBuilt>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut edits: EventWriter<SetVoxel>,
+)
+{
+    for (sector, position) in &block_updates.positions
+    {
+        let Some(loaded_sector) = loaded_sectors.sectors.get(sector) else { continue };
+        let Some(voxel) = loaded_sector.voxel(*position) else { continue };
+        //a voxel above an unloaded chunk waits, it gets checked again when something next to it changes
+        if !falls(voxel.voxel_type) || !loaded_sector.voxel(*position + IVec3::NEG_Y).is_some_and(passable)
+        {
+            continue;
+        }
+        //removing it sends VoxelChanged, which schedules the voxel above so whole columns cascade
+        edits.send(SetVoxel { sector: *sector, position: *position, voxel: Voxel::default(), undoable: false });
+        commands.spawn((
+            FallingBlock { sector: *sector, voxel: voxel.clone(), velocity: 0.0 },
+            PbrBundle {
+                mesh: meshes.add(falling_block_mesh(voxel.voxel_type, &texture_atlas_data)),
+                material: materials.add(StandardMaterial
+                {
+                    emissive: Color::WHITE,
+                    emissive_texture: Some(texture_atlas_data.tex.clone()),
+                    ..default()
+                }),
+                transform: Transform::from_translation(sector_offset(*sector) + position.as_vec3() + Vec3::splat(0.5)),
+                ..default()
+            },
+            Name::new("Falling Block"),
+        ));
+    }
+}
+
+//first cell from target upwards that a landing block can go in, skipping cells other blocks claimed this frame
+//None if the column reaches a chunk that is not loaded before there is room
+pub fn landing_cell(world: &impl VoxelAccess, mut target: IVec3, claimed: impl Fn(IVec3) -> bool) -> Option<IVec3>
+{
+    loop
+    {
+        let voxel = world.voxel(target)?;
+        if passable(voxel) && !claimed(target)
+        {
+            return Some(target);
+        }
+        target += IVec3::Y;
+    }
+}
+
+//moves falling blocks down and places them back as voxels once something solid is below
+pub fn update_falling_blocks
+(
+    mut commands: Commands,
+    time: Res<Time>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    mut falling_blocks: Query<(Entity, &mut Transform, &mut FallingBlock)>,
+    mut edits: EventWriter<SetVoxel>,
+)
+{
+    let delta = time.delta_seconds().min(0.05);
+    //several blocks landing in the same column this frame stack instead of replacing each other
+    let mut claimed: HashSet<([i32; 3], IVec3)> = HashSet::new();
+    for (entity, mut transform, mut falling_block) in &mut falling_blocks
+    {
+        let Some(loaded_sector) = loaded_sectors.sectors.get(&falling_block.sector) else { continue };
+        let offset = sector_offset(falling_block.sector);
+        let bottom = transform.translation.y - offset.y - 0.5;
+        let column = (transform.translation - offset).floor().as_ivec3();
+        let next_bottom = bottom - (falling_block.velocity + FALLING_GRAVITY * delta) * delta;
+        //checks every voxel the block passes this frame so fast blocks do not tunnel through thin floors
+        let mut landed = None;
+        for y in (next_bottom.floor() as i32..bottom.ceil() as i32).rev()
+        {
+            let cell = IVec3::new(column.x, y, column.z);
+            match loaded_sector.voxel(cell)
+            {
+                //the chunk below is not loaded yet, hold still and try again next frame
+                None =>
+                {
+                    landed = Some(None);
+                    break;
+                }
+                Some(voxel) if !passable(voxel) || !collision_boxes(voxel).is_empty() =>
+                {
+                    landed = Some(Some(cell + IVec3::Y));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        match landed
+        {
+            None =>
+            {
+                falling_block.velocity += FALLING_GRAVITY * delta;
+                transform.translation.y = next_bottom + offset.y + 0.5;
+            }
+            Some(None) => falling_block.velocity = 0.0,
+            Some(Some(target)) =>
+            {
+                let sector = falling_block.sector;
+                match landing_cell(loaded_sector, target, |cell| claimed.contains(&(sector, cell)))
+                {
+                    Some(cell) =>
+                    {
+                        claimed.insert((sector, cell));
+                        edits.send(SetVoxel { sector, position: cell, voxel: falling_block.voxel.clone(), undoable: false });
+                        commands.entity(entity).despawn_recursive();
+                    }
+                    //the column is filled up to a chunk that is not loaded, hold still until there is room
+                    None => falling_block.velocity = 0.0,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+
+    fn sand() -> Voxel
+    {
+        Voxel::new(VoxelType::Sand, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    #[test]
+    fn lands_on_the_first_free_cell()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(2, 3, 2), sand())]);
+        assert_eq!(landing_cell(&world, IVec3::new(2, 3, 2), |_| false), Some(IVec3::new(2, 4, 2)));
+        assert_eq!(landing_cell(&world, IVec3::new(2, 4, 2), |cell| cell.y == 4), Some(IVec3::new(2, 5, 2)));
+    }
+
+    #[test]
+    fn waits_when_the_column_reaches_an_unloaded_chunk()
+    {
+        //the top cell of the only loaded chunk is taken, the chunk above is not loaded
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(2, 31, 2), sand())]);
+        assert_eq!(landing_cell(&world, IVec3::new(2, 31, 2), |_| false), None);
+        assert_eq!(landing_cell(&world, IVec3::new(2, 30, 2), |cell| cell.y == 30), None);
+    }
+}
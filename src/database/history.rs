@@ -0,0 +1,375 @@
+use crate::database::bulk::*;
+use crate::database::chunk::voxel_index;
+use crate::database::edit::*;
+use crate::database::events::*;
+use crate::database::generator::{ActiveGenerator, WorldGenerator};
+use crate::database::sector::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::VecDeque;
+
+//undo and redo for bulk edits and anything else recorded as a VoxelDiff
+//one entry is one undo step, a transaction groups several diffs into a single entry
+#[derive(Resource)]
+pub struct EditHistory
+{
+    //rough upper bound in bytes for everything kept in both stacks, the oldest entries are dropped first
+    pub memory_cap: usize,
+    undo: VecDeque<Vec<VoxelDiff>>,
+    redo: Vec<Vec<VoxelDiff>>,
+    transaction: Option<Vec<VoxelDiff>>,
+    memory: usize,
+}
+
+impl Default for EditHistory
+{
+    fn default() -> Self
+    {
+        EditHistory
+        {
+            memory_cap: 64 * 1024 * 1024,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            transaction: None,
+            memory: 0,
+        }
+    }
+}
+
+fn entry_size(entry: &[VoxelDiff]) -> usize
+{
+    entry.iter().map(|diff| std::mem::size_of::<VoxelDiff>() + diff.changes.len() * std::mem::size_of::<VoxelDelta>()).sum()
+}
+
+impl EditHistory
+{
+    //adds a diff as its own undo step, or to the open transaction. a new edit throws away everything that could be redone
+    pub fn record(&mut self, diff: VoxelDiff)
+    {
+        if diff.is_empty()
+        {
+            return;
+        }
+        if let Some(transaction) = &mut self.transaction
+        {
+            transaction.push(diff);
+            return;
+        }
+        self.clear_redo();
+        self.push_undo(vec![diff]);
+    }
+
+    //everything recorded until end_transaction is undone and redone together
+    pub fn begin_transaction(&mut self)
+    {
+        if self.transaction.is_none()
+        {
+            self.transaction = Some(Vec::new());
+        }
+    }
+
+    pub fn end_transaction(&mut self)
+    {
+        let Some(transaction) = self.transaction.take() else { return };
+        if transaction.is_empty()
+        {
+            return;
+        }
+        self.clear_redo();
+        self.push_undo(transaction);
+    }
+
+    pub fn can_undo(&self) -> bool
+    {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool
+    {
+        !self.redo.is_empty()
+    }
+
+    //dropped before a new step is pushed so it does not count against the memory cap
+    fn clear_redo(&mut self)
+    {
+        let redo = std::mem::take(&mut self.redo);
+        self.memory -= redo.iter().map(|entry| entry_size(entry)).sum::<usize>();
+    }
+
+    fn push_undo(&mut self, entry: Vec<VoxelDiff>)
+    {
+        self.memory += entry_size(&entry);
+        self.undo.push_back(entry);
+        while self.memory > self.memory_cap
+        {
+            let Some(oldest) = self.undo.pop_front() else { break };
+            self.memory -= entry_size(&oldest);
+        }
+    }
+
+    //diffs to apply to undo the last step, in the order they have to be applied
+    fn take_undo(&mut self) -> Option<Vec<VoxelDiff>>
+    {
+        let entry = self.undo.pop_back()?;
+        let inverse = entry.iter().rev().map(|diff| diff.inverse()).collect();
+        self.redo.push(entry);
+        Some(inverse)
+    }
+
+    //goes back on the undo stack through push_undo so a memory cap lowered since the undo still holds
+    fn take_redo(&mut self) -> Option<Vec<VoxelDiff>>
+    {
+        let entry = self.redo.pop()?;
+        let forward = entry.clone();
+        self.memory -= entry_size(&entry);
+        self.push_undo(entry);
+        Some(forward)
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryCommand
+{
+    Undo,
+    Redo,
+}
+
+//ctrl+z undoes, ctrl+y or ctrl+shift+z redoes
+pub fn keyboard_history_input
+(
+    keys: Res<Input<KeyCode>>,
+    mut commands: EventWriter<HistoryCommand>,
+)
+{
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+    {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z))
+    {
+        commands.send(HistoryCommand::Redo);
+    }
+    else if keys.just_pressed(KeyCode::Z)
+    {
+        commands.send(HistoryCommand::Undo);
+    }
+}
+
+pub fn record_edit_history
+(
+    mut history: ResMut<EditHistory>,
+    mut bulk_applied: EventReader<BulkEditApplied>,
+)
+{
+    for applied in bulk_applied.read()
+    {
+        history.record(applied.diff.clone());
+    }
+}
+
+//writes a diff from the history back. loaded chunks are changed in place and remeshed, unloaded chunks are loaded
+//from their save or generated, changed and saved again so they come back the way the diff left them the next time they load
+//voxels that no longer hold the diff's old state were changed by something else since, such as fluids or falling
+//blocks, and are left alone. returns what was changed in loaded chunks and how many voxels were left alone
+pub fn apply_history_diff(diff: VoxelDiff, universe: &mut Universe, generator: &dyn WorldGenerator, loaded_sectors: &mut CurrentlyLoaded, dirty_chunks: &mut DirtyChunks) -> (VoxelDiff, usize)
+{
+    let mut loaded = Vec::new();
+    let mut unloaded: HashMap<[i32; 3], Vec<VoxelDelta>> = HashMap::new();
+    let mut conflicts = 0;
+    for delta in diff.changes
+    {
+        let (chunk, _) = voxel_to_chunk(delta.position);
+        let loaded_sector = loaded_sectors.sectors.get(&diff.sector).filter(|sector| sector.chunks.get(&chunk).is_some_and(|chunk| chunk.is_some()));
+        match loaded_sector
+        {
+            Some(sector) if sector.voxel(delta.position) == Some(&delta.old) => loaded.push((delta.position, delta.new)),
+            Some(_) => conflicts += 1,
+            None => unloaded.entry(chunk).or_default().push(delta),
+        }
+    }
+    let applied = apply_voxel_changes(loaded_sectors, dirty_chunks, diff.sector, loaded);
+    for (chunk_id, deltas) in unloaded
+    {
+        let mut chunk = universe.load_or_generate(diff.sector, chunk_id, generator);
+        for delta in deltas
+        {
+            let local = voxel_to_chunk(delta.position).1;
+            let voxel = &mut chunk.block_data[voxel_index(local[0], local[1], local[2])];
+            if *voxel == delta.old
+            {
+                *voxel = delta.new;
+            }
+            else
+            {
+                conflicts += 1;
+            }
+        }
+        universe.save_chunk(diff.sector, chunk_id, chunk);
+    }
+    (applied, conflicts)
+}
+
+pub fn apply_history_commands
+(
+    mut history_commands: EventReader<HistoryCommand>,
+    mut history: ResMut<EditHistory>,
+    mut universe: ResMut<Universe>,
+    generator: Res<ActiveGenerator>,
+    mut loaded_sectors: ResMut<CurrentlyLoaded>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut voxel_changed: EventWriter<VoxelChanged>,
+)
+{
+    for command in history_commands.read()
+    {
+        let diffs = match command
+        {
+            HistoryCommand::Undo => history.take_undo(),
+            HistoryCommand::Redo => history.take_redo(),
+        };
+        let mut conflicts = 0;
+        for diff in diffs.into_iter().flatten()
+        {
+            let (applied, skipped) = apply_history_diff(diff, &mut universe, generator.0.as_ref(), &mut loaded_sectors, &mut dirty_chunks);
+            conflicts += skipped;
+            for delta in applied.changes
+            {
+                let (chunk, local) = voxel_to_chunk(delta.position);
+                voxel_changed.send(VoxelChanged { sector: applied.sector, chunk, voxel: local, old: delta.old, new: delta.new });
+            }
+        }
+        if conflicts > 0
+        {
+            warn!("{:?} left {} voxels alone that were changed since they were recorded", command, conflicts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+    use crate::database::voxel::*;
+    use std::sync::Arc;
+
+    fn stone() -> Voxel
+    {
+        Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    fn diff(changes: &[(IVec3, Voxel, Voxel)]) -> VoxelDiff
+    {
+        VoxelDiff { sector: [0, 0, 0], changes: changes.iter().map(|(position, old, new)| VoxelDelta { position: *position, old: old.clone(), new: new.clone() }).collect() }
+    }
+
+    fn loaded_world(voxels: &[(IVec3, Voxel)]) -> CurrentlyLoaded
+    {
+        let chunks = test_world([0, 0, 0], [0, 0, 0], voxels).into_iter().map(|(chunk_id, chunk)| (chunk_id, Some(Arc::new(chunk)))).collect();
+        let mut loaded = CurrentlyLoaded::default();
+        loaded.sectors.insert([0, 0, 0], SectorsRendering { chunks });
+        loaded
+    }
+
+    #[test]
+    fn undoable_single_edits_become_one_step_per_frame()
+    {
+        let mut app = App::new();
+        app.insert_resource(loaded_world(&[]))
+            .init_resource::<DirtyChunks>()
+            .init_resource::<EditHistory>()
+            .add_event::<SetVoxel>()
+            .add_event::<VoxelChanged>()
+            .add_event::<BulkEditApplied>()
+            .add_systems(Update, (apply_voxel_edits, record_edit_history).chain());
+        for (x, undoable) in [(1, true), (2, true), (3, false)]
+        {
+            app.world.send_event(SetVoxel { sector: [0, 0, 0], position: IVec3::new(x, 1, 1), voxel: stone(), undoable });
+        }
+        app.update();
+        let history = app.world.resource::<EditHistory>();
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.undo[0][0].changes.iter().map(|delta| delta.position.x).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn undo_and_redo_move_steps_between_the_stacks()
+    {
+        let mut history = EditHistory::default();
+        history.record(diff(&[(IVec3::ZERO, Voxel::default(), stone())]));
+        history.record(diff(&[(IVec3::X, Voxel::default(), stone())]));
+        assert_eq!(history.take_undo(), Some(vec![diff(&[(IVec3::X, stone(), Voxel::default())])]));
+        assert_eq!(history.take_redo(), Some(vec![diff(&[(IVec3::X, Voxel::default(), stone())])]));
+        assert!(history.can_undo() && !history.can_redo());
+        //a new edit after an undo throws the redo away
+        history.take_undo();
+        history.record(diff(&[(IVec3::Y, Voxel::default(), stone())]));
+        assert!(!history.can_redo());
+        assert_eq!(history.undo.len(), 2);
+    }
+
+    #[test]
+    fn redo_keeps_to_a_lowered_memory_cap()
+    {
+        let mut history = EditHistory::default();
+        let step = diff(&[(IVec3::ZERO, Voxel::default(), stone())]);
+        for _ in 0..4
+        {
+            history.record(step.clone());
+        }
+        while history.take_undo().is_some() {}
+        history.memory_cap = entry_size(std::slice::from_ref(&step)) * 2;
+        while history.take_redo().is_some() {}
+        assert_eq!(history.undo.len(), 2);
+        assert!(history.memory <= history.memory_cap);
+    }
+
+    #[test]
+    fn a_new_step_does_not_drop_undo_steps_to_make_room_for_cleared_redo_steps()
+    {
+        let step = diff(&[(IVec3::ZERO, Voxel::default(), stone())]);
+        let mut history = EditHistory { memory_cap: entry_size(std::slice::from_ref(&step)) * 2, ..default() };
+        history.record(step.clone());
+        history.record(step.clone());
+        history.take_undo();
+        history.record(step.clone());
+        assert_eq!(history.undo.len(), 2);
+    }
+
+    #[test]
+    fn voxels_changed_since_the_edit_are_left_alone()
+    {
+        let water = Voxel::new(VoxelType::Water, VoxelVariant::Block, VoxelRotation::Forward);
+        //stone was placed at both, then water flowed over the second one
+        let mut loaded = loaded_world(&[(IVec3::new(1, 1, 1), stone()), (IVec3::new(2, 1, 1), water.clone())]);
+        let undo = diff(&[(IVec3::new(1, 1, 1), Voxel::default(), stone()), (IVec3::new(2, 1, 1), Voxel::default(), stone())]).inverse();
+        let mut universe = Universe::default();
+        let mut dirty_chunks = DirtyChunks::default();
+        let generator = crate::database::generator::NoiseGenerator { seed: 1 };
+        let (applied, conflicts) = apply_history_diff(undo, &mut universe, &generator, &mut loaded, &mut dirty_chunks);
+        assert_eq!(conflicts, 1);
+        assert_eq!(applied.changes.iter().map(|delta| delta.position).collect::<Vec<_>>(), vec![IVec3::new(1, 1, 1)]);
+        let sector = &loaded.sectors[&[0, 0, 0]];
+        assert_eq!(sector.voxel(IVec3::new(1, 1, 1)), Some(&Voxel::default()));
+        assert_eq!(sector.voxel(IVec3::new(2, 1, 1)), Some(&water));
+    }
+
+    #[test]
+    fn unloaded_chunks_are_checked_against_their_saved_copy()
+    {
+        let mut loaded = CurrentlyLoaded::default();
+        let mut universe = Universe::default();
+        let mut saved = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(1, 1, 1), stone())]).remove(&[0, 0, 0]).unwrap();
+        saved.block_data[voxel_index(2, 1, 1)] = Voxel::new(VoxelType::Sand, VoxelVariant::Block, VoxelRotation::Forward);
+        universe.save_chunk([0, 0, 0], [0, 0, 0], saved);
+        let undo = diff(&[(IVec3::new(1, 1, 1), Voxel::default(), stone()), (IVec3::new(2, 1, 1), Voxel::default(), stone())]).inverse();
+        let generator = crate::database::generator::NoiseGenerator { seed: 1 };
+        let (applied, conflicts) = apply_history_diff(undo, &mut universe, &generator, &mut loaded, &mut DirtyChunks::default());
+        assert!(applied.is_empty());
+        assert_eq!(conflicts, 1);
+        let saved = universe.saved_chunk([0, 0, 0], [0, 0, 0]).unwrap();
+        assert_eq!(saved.block_data[voxel_index(1, 1, 1)], Voxel::default());
+        assert_eq!(saved.block_data[voxel_index(2, 1, 1)].voxel_type, VoxelType::Sand);
+    }
+}
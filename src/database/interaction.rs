@@ -0,0 +1,303 @@
+use crate::database::bulk::*;
+use crate::database::edit::*;
+use crate::database::physics::*;
+use crate::database::raycast::*;
+use crate::database::sector::*;
+use crate::database::structs::AppState;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy_flycam::FlyCam;
+
+//breaking and placing voxels by raycasting from an interactor, with a highlight around the targeted voxel
+pub struct InteractionPlugin;
+
+impl Plugin for InteractionPlugin
+{
+    fn build(&self, app: &mut App)
+    {
+        app.add_event::<InteractionRequest>()
+            .add_systems(Update, (mouse_interaction_input, handle_interactions.before(apply_bulk_edits), draw_target_highlight).run_if(in_state(AppState::Generating)));
+    }
+}
+
+//an entity that can break and place voxels, rays start at its global transform and go along its forward direction
+#[derive(Component, Debug, Clone)]
+pub struct Interactor
+{
+    pub sector: [i32; 3],
+    //how far away voxels can be reached
+    pub reach: f32,
+    //what right click places, stairs get their rotation from the facing direction and their half from where the ray hit
+    pub selected_type: VoxelType,
+    pub selected_variant: VoxelVariant,
+}
+
+impl Default for Interactor
+{
+    fn default() -> Self
+    {
+        Interactor
+        {
+            sector: [0, 0, 0],
+            reach: 8.0,
+            selected_type: VoxelType::Stone,
+            selected_variant: VoxelVariant::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InteractionAction
+{
+    Break,
+    Place
+    {
+        voxel_type: VoxelType,
+        voxel_variant: VoxelVariant,
+    },
+}
+
+//a ray to break or place along, sent by mouse input or by anything else such as AI or tests
+#[derive(Event, Debug, Clone)]
+pub struct InteractionRequest
+{
+    pub sector: [i32; 3],
+    //world space
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub reach: f32,
+    pub action: InteractionAction,
+}
+
+//the fly camera can break and place voxels
+pub fn attach_flycam_interactors
+(
+    mut commands: Commands,
+    cameras: Query<Entity, (Added<FlyCam>, Without<Interactor>)>,
+)
+{
+    for camera in &cameras
+    {
+        commands.entity(camera).insert(Interactor::default());
+    }
+}
+
+fn sector_offset(sector: [i32; 3]) -> Vec3
+{
+    Vec3::new(sector[0] as f32, sector[1] as f32, sector[2] as f32)
+}
+
+//stairs rise away from whoever places them
+pub fn rotation_from_facing(direction: Vec3) -> VoxelRotation
+{
+    if direction.x.abs() > direction.z.abs()
+    {
+        return if direction.x > 0.0 { VoxelRotation::Left } else { VoxelRotation::Right };
+    }
+    if direction.z > 0.0 { VoxelRotation::Forward } else { VoxelRotation::Backward }
+}
+
+//slabs and stairs go in the top half when placed against a ceiling or the upper half of a wall
+//vertical slabs stand against the face they are placed on
+pub fn placed_voxel(voxel_type: VoxelType, voxel_variant: VoxelVariant, hit: &RaycastHit, origin: Vec3, direction: Vec3) -> Voxel
+{
+    let point = origin + direction.normalize_or_zero() * hit.distance;
+    let voxel_half = match hit.normal.y
+    {
+        -1 => VoxelHalf::Top,
+        1 => VoxelHalf::Bottom,
+        _ => if point.y - hit.position.y as f32 > 0.5 { VoxelHalf::Top } else { VoxelHalf::Bottom },
+    };
+    let rotation = match voxel_variant
+    {
+        VoxelVariant::VerticalSlab => VoxelRotation::from_direction(-hit.normal).unwrap_or_else(|| rotation_from_facing(direction)),
+        _ => rotation_from_facing(direction),
+    };
+    Voxel::new(voxel_type, voxel_variant, rotation).with_half(voxel_half)
+}
+
+pub fn mouse_interaction_input
+(
+    buttons: Res<Input<MouseButton>>,
+    interactors: Query<(&GlobalTransform, &Interactor)>,
+    mut requests: EventWriter<InteractionRequest>,
+)
+{
+    for (transform, interactor) in &interactors
+    {
+        let action = match (buttons.just_pressed(MouseButton::Left), buttons.just_pressed(MouseButton::Right))
+        {
+            (true, _) => InteractionAction::Break,
+            (_, true) => InteractionAction::Place { voxel_type: interactor.selected_type, voxel_variant: interactor.selected_variant },
+            _ => continue,
+        };
+        requests.send(InteractionRequest
+        {
+            sector: interactor.sector,
+            origin: transform.translation(),
+            direction: transform.forward(),
+            reach: interactor.reach,
+            action,
+        });
+    }
+}
+
+//the voxel an interaction changes and what it becomes, None if the ray hits nothing or there is no room to place
+//occupied tells if a box is taken by something other than voxels, such as a character
+pub fn interaction_edit(world: &impl VoxelAccess, origin: Vec3, direction: Vec3, reach: f32, action: &InteractionAction, occupied: impl Fn(&BoundingBox) -> bool) -> Option<(IVec3, Voxel)>
+{
+    let hit = raycast(world, origin, direction, reach)?;
+    match action
+    {
+        InteractionAction::Break => Some((hit.position, Voxel::default())),
+        InteractionAction::Place { voxel_type, voxel_variant } =>
+        {
+            let position = hit.position + hit.normal;
+            //only replace empty space or fluids
+            let target = world.voxel(position)?;
+            if !collision_boxes(target).is_empty()
+            {
+                return None;
+            }
+            let voxel = placed_voxel(*voxel_type, *voxel_variant, &hit, origin, direction);
+            let blocked = collision_boxes(&voxel).iter().any(|bounds| occupied(&bounds.offset(position.as_vec3())));
+            (!blocked).then_some((position, voxel))
+        }
+    }
+}
+
+//turns interaction requests into voxel edits, chunk_handler remeshes the chunks they touch
+//they go through BulkEdit so they end up in the edit history
+pub fn handle_interactions
+(
+    mut requests: EventReader<InteractionRequest>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    characters: Query<(&Transform, &CharacterController)>,
+    mut edits: EventWriter<BulkEdit>,
+)
+{
+    for request in requests.read()
+    {
+        let Some(sector) = loaded_sectors.sectors.get(&request.sector) else { continue };
+        let origin = request.origin - sector_offset(request.sector);
+        //do not place voxels inside characters
+        let occupied = |bounds: &BoundingBox| characters.iter().any(|(transform, controller)| controller.sector == request.sector && bounds.intersects(&controller.bounds(transform.translation)));
+        if let Some(edit) = interaction_edit(sector, origin, request.direction, request.reach, &request.action, occupied)
+        {
+            edits.send(BulkEdit { sector: request.sector, operation: BulkOperation::Set { voxels: vec![edit] } });
+        }
+    }
+}
+
+pub fn draw_target_highlight
+(
+    mut gizmos: Gizmos,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    interactors: Query<(&GlobalTransform, &Interactor)>,
+)
+{
+    for (transform, interactor) in &interactors
+    {
+        let Some(sector) = loaded_sectors.sectors.get(&interactor.sector) else { continue };
+        let offset = sector_offset(interactor.sector);
+        if let Some(hit) = raycast(sector, transform.translation() - offset, transform.forward(), interactor.reach)
+        {
+            //slightly larger than the voxel so the lines are not hidden by its faces
+            let center = offset + hit.position.as_vec3() + Vec3::splat(0.5);
+            gizmos.cuboid(Transform::from_translation(center).with_scale(Vec3::splat(1.01)), Color::BLACK);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+
+    fn stone() -> Voxel
+    {
+        Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    fn place(voxel_variant: VoxelVariant) -> InteractionAction
+    {
+        InteractionAction::Place { voxel_type: VoxelType::Brick, voxel_variant }
+    }
+
+    fn nothing_in_the_way(_bounds: &BoundingBox) -> bool
+    {
+        false
+    }
+
+    #[test]
+    fn breaking_replaces_the_hit_voxel_with_air()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone())]);
+        let edit = interaction_edit(&world, Vec3::new(4.5, 6.5, 4.5), Vec3::NEG_Y, 8.0, &InteractionAction::Break, nothing_in_the_way);
+        assert_eq!(edit, Some((IVec3::new(4, 2, 4), Voxel::default())));
+    }
+
+    #[test]
+    fn plants_can_be_broken_and_built_on()
+    {
+        let plant = Voxel::new(VoxelType::Grass, VoxelVariant::Cross, VoxelRotation::Forward);
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone()), (IVec3::new(4, 3, 4), plant)]);
+        let edit = interaction_edit(&world, Vec3::new(4.5, 6.5, 4.5), Vec3::NEG_Y, 8.0, &InteractionAction::Break, nothing_in_the_way);
+        assert_eq!(edit, Some((IVec3::new(4, 3, 4), Voxel::default())));
+        let edit = interaction_edit(&world, Vec3::new(4.5, 6.5, 4.5), Vec3::NEG_Y, 8.0, &place(VoxelVariant::Block), nothing_in_the_way);
+        assert_eq!(edit.map(|(position, _)| position), Some(IVec3::new(4, 4, 4)));
+    }
+
+    #[test]
+    fn nothing_happens_out_of_reach()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone())]);
+        assert_eq!(interaction_edit(&world, Vec3::new(4.5, 12.5, 4.5), Vec3::NEG_Y, 8.0, &InteractionAction::Break, nothing_in_the_way), None);
+        assert_eq!(interaction_edit(&world, Vec3::new(4.5, 12.5, 4.5), Vec3::NEG_Y, 8.0, &place(VoxelVariant::Block), nothing_in_the_way), None);
+    }
+
+    #[test]
+    fn placing_goes_against_the_hit_face()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone())]);
+        let on_top = interaction_edit(&world, Vec3::new(4.5, 6.5, 4.5), Vec3::NEG_Y, 8.0, &place(VoxelVariant::Block), nothing_in_the_way);
+        assert_eq!(on_top.map(|(position, voxel)| (position, voxel.voxel_type)), Some((IVec3::new(4, 3, 4), VoxelType::Brick)));
+        let on_side = interaction_edit(&world, Vec3::new(0.5, 2.5, 4.5), Vec3::X, 8.0, &place(VoxelVariant::Block), nothing_in_the_way);
+        assert_eq!(on_side.map(|(position, _)| position), Some(IVec3::new(3, 2, 4)));
+    }
+
+    #[test]
+    fn placing_does_not_replace_solid_voxels_or_characters()
+    {
+        //the ray starts inside the stone so the place would be the stone itself
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone())]);
+        assert_eq!(interaction_edit(&world, Vec3::new(4.5, 2.5, 4.5), Vec3::NEG_Y, 8.0, &place(VoxelVariant::Block), nothing_in_the_way), None);
+        let character = BoundingBox::new(Vec3::new(4.2, 3.0, 4.2), Vec3::new(4.8, 4.8, 4.8));
+        let inside_character = interaction_edit(&world, Vec3::new(4.5, 6.5, 4.5), Vec3::NEG_Y, 8.0, &place(VoxelVariant::Block), |bounds| bounds.intersects(&character));
+        assert_eq!(inside_character, None);
+    }
+
+    #[test]
+    fn slabs_take_the_half_that_was_aimed_at()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone()), (IVec3::new(4, 6, 4), stone())]);
+        let under_ceiling = interaction_edit(&world, Vec3::new(4.5, 3.5, 4.5), Vec3::Y, 8.0, &place(VoxelVariant::Slab), nothing_in_the_way).unwrap();
+        assert_eq!((under_ceiling.0, under_ceiling.1.voxel_half), (IVec3::new(4, 5, 4), VoxelHalf::Top));
+        let upper_side = interaction_edit(&world, Vec3::new(0.5, 2.8, 4.5), Vec3::X, 8.0, &place(VoxelVariant::Slab), nothing_in_the_way).unwrap();
+        assert_eq!((upper_side.0, upper_side.1.voxel_half), (IVec3::new(3, 2, 4), VoxelHalf::Top));
+        let lower_side = interaction_edit(&world, Vec3::new(0.5, 2.2, 4.5), Vec3::X, 8.0, &place(VoxelVariant::Slab), nothing_in_the_way).unwrap();
+        assert_eq!(lower_side.1.voxel_half, VoxelHalf::Bottom);
+    }
+
+    #[test]
+    fn vertical_slabs_stand_against_the_hit_face()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(4, 2, 4), stone())]);
+        let (position, voxel) = interaction_edit(&world, Vec3::new(0.5, 2.5, 4.5), Vec3::X, 8.0, &place(VoxelVariant::VerticalSlab), nothing_in_the_way).unwrap();
+        assert_eq!(voxel.voxel_rotation.direction(), IVec3::X);
+        //the slab's solid half is the one touching the stone
+        let bounds = collision_boxes(&voxel)[0].offset(position.as_vec3());
+        assert_eq!((bounds.min.x, bounds.max.x), (3.5, 4.0));
+    }
+}
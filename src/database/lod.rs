@@ -0,0 +1,284 @@
+use crate::database::chunk::*;
+use crate::database::edit::DirtyChunks;
+use crate::database::sector::*;
+use crate::database::settings::{CHUNK_SIZE, FULL_DETAIL_DISTANCE, RENDER_DISTANCE};
+use crate::database::mesher::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::sync::Arc;
+
+//how the voxels inside a coarse cell become one voxel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LodSampling
+{
+    //the most common voxel type in the cell, air included
+    MostCommon,
+    //cells at least half full take the type of their highest voxel, keeps grass and snow on top of distant hills
+    #[default]
+    TopMost,
+}
+
+//which level of detail chunks are meshed at, by their distance in chunks to the closest loader
+#[derive(Resource, Debug, Clone)]
+pub struct LodSettings
+{
+    //(distance, factor) pairs sorted by distance, chunks at least distance away are meshed factor times coarser
+    pub levels: Vec<(i32, i32)>,
+    pub sampling: LodSampling,
+}
+
+impl Default for LodSettings
+{
+    fn default() -> Self
+    {
+        LodSettings::for_radius(FULL_DETAIL_DISTANCE, RENDER_DISTANCE)
+    }
+}
+
+impl LodSettings
+{
+    //full detail up to full_detail chunks out, then 2, 4 and 8 times coarser rings splitting the rest of the load radius
+    //so every level is in use. rings are at least a chunk wide, radiuses close to full_detail get the coarse ones last
+    pub fn for_radius(full_detail: i32, radius: i32) -> LodSettings
+    {
+        let ring = ((radius - full_detail) / 3).max(1);
+        LodSettings
+        {
+            levels: (0..3).map(|level| (full_detail + 1 + level * ring, 2 << level)).collect(),
+            sampling: LodSampling::default(),
+        }
+    }
+
+    //1 for full detail, otherwise 2, 4 or 8
+    pub fn factor(&self, distance: i32) -> i32
+    {
+        self.levels.iter().filter(|(min_distance, _)| distance >= *min_distance).map(|(_, factor)| *factor).next_back().unwrap_or(1)
+    }
+}
+
+//the factor a chunk's current mesh was built with
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLod(pub i32);
+
+//distance in chunks from a chunk to the closest loader in its sector, along the axis that is furthest away
+pub fn lod_distance(loader_chunks: &LoaderChunks, sector: [i32; 3], chunk: [i32; 3]) -> i32
+{
+    loader_chunks.centers.iter()
+        .filter(|((_, loader_sector), _)| *loader_sector == sector)
+        .map(|(_, (center, _))| (0..3).map(|axis| (chunk[axis] - center[axis]).abs()).max().unwrap_or(0))
+        .min()
+        .unwrap_or(0)
+}
+
+//voxel grid factor times smaller on every axis, indexed x major like chunk data
+pub fn downsample(chunk: &Chunk, factor: i32, sampling: LodSampling) -> Vec<Voxel>
+{
+    let size = CHUNK_SIZE / factor;
+    let mut cells = Vec::with_capacity(size.pow(3) as usize);
+    let mut counts: HashMap<VoxelType, i32> = HashMap::new();
+    for cell_x in 0..size
+    {
+        for cell_y in 0..size
+        {
+            for cell_z in 0..size
+            {
+                counts.clear();
+                let mut top: Option<(i32, VoxelType)> = None;
+                for x in cell_x * factor..(cell_x + 1) * factor
+                {
+                    for y in cell_y * factor..(cell_y + 1) * factor
+                    {
+                        for z in cell_z * factor..(cell_z + 1) * factor
+                        {
+                            let voxel_type = chunk.block_data[voxel_index(x, y, z)].voxel_type;
+                            *counts.entry(voxel_type).or_default() += 1;
+                            if voxel_type != VoxelType::Air && top.is_none_or(|(top_y, _)| y > top_y)
+                            {
+                                top = Some((y, voxel_type));
+                            }
+                        }
+                    }
+                }
+                let voxel_type = match sampling
+                {
+                    //ties go to the lower voxel type so the result does not depend on hash order
+                    LodSampling::MostCommon => counts.iter().max_by_key(|(voxel_type, count)| (**count, std::cmp::Reverse(**voxel_type as usize))).map_or(VoxelType::Air, |(voxel_type, _)| *voxel_type),
+                    LodSampling::TopMost =>
+                    {
+                        let air = counts.get(&VoxelType::Air).copied().unwrap_or(0);
+                        match top
+                        {
+                            Some((_, voxel_type)) if air * 2 <= factor.pow(3) => voxel_type,
+                            _ => VoxelType::Air,
+                        }
+                    }
+                };
+                cells.push(Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default()));
+            }
+        }
+    }
+    cells
+}
+
+//meshes a chunk factor times coarser with render_update. the coarse grid is put inside an empty chunk with a ring of air
+//around it, so every face on the chunk border is drawn. those border faces work as skirts that close the gaps
+//against neighbours meshed at a different level of detail
+pub fn render_lod(neighbourhood: &ChunkNeighbourhood, context: &MeshContext) -> ChunkMesh
+{
+    let center = neighbourhood.center().clone();
+    let factor = context.lod;
+    let size = CHUNK_SIZE / factor;
+    let cells = downsample(&center, factor, context.sampling);
+    let mut padded = Chunk { block_data: vec![Voxel::default(); CHUNK_SIZE.pow(3) as usize], pos: (0, 0, 0) };
+    for x in 0..size
+    {
+        for y in 0..size
+        {
+            for z in 0..size
+            {
+                padded.block_data[voxel_index(x + 1, y + 1, z + 1)] = cells[(x * size * size + y * size + z) as usize].clone();
+            }
+        }
+    }
+    let mut chunk_mesh = render_update(&ChunkNeighbourhood::single(Arc::new(padded)), &context.texture_info, &context.block_models, &context.filter);
+    let origin = Vec3::new(center.pos.0 as f32, center.pos.1 as f32, center.pos.2 as f32);
+    for vertex in &mut chunk_mesh.positions
+    {
+        *vertex = (origin + (Vec3::from(*vertex) - Vec3::ONE) * factor as f32).to_array();
+    }
+    chunk_mesh
+}
+
+//remeshes chunks whose level of detail changed after a loader moved to another chunk
+pub fn update_chunk_lods
+(
+    lod_settings: Res<LodSettings>,
+    loader_chunks: Res<LoaderChunks>,
+    chunks: Query<(&ChunkCoords, &ChunkLod)>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+)
+{
+    if !loader_chunks.is_changed() && !lod_settings.is_changed()
+    {
+        return;
+    }
+    for (coords, lod) in &chunks
+    {
+        if lod_settings.factor(lod_distance(&loader_chunks, coords.sector, coords.chunk)) != lod.0
+        {
+            dirty_chunks.chunks.insert((coords.sector, coords.chunk));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::model::BlockModels;
+    use crate::database::settings::TextureMode;
+    use crate::database::texture::TextureInfo;
+
+    #[test]
+    fn default_levels_span_the_load_radius()
+    {
+        let settings = LodSettings::default();
+        for distance in 0..=FULL_DETAIL_DISTANCE
+        {
+            assert_eq!(settings.factor(distance), 1);
+        }
+        assert_eq!(settings.factor(FULL_DETAIL_DISTANCE + 1), 2);
+        //every level is reached inside the load radius
+        assert!(settings.levels.iter().all(|(distance, _)| *distance <= RENDER_DISTANCE));
+        assert_eq!(settings.factor(RENDER_DISTANCE), 8);
+    }
+
+    #[test]
+    fn levels_scale_with_the_radius()
+    {
+        assert_eq!(LodSettings::for_radius(5, 32).levels, vec![(6, 2), (15, 4), (24, 8)]);
+        assert_eq!(LodSettings::for_radius(5, 8).levels, vec![(6, 2), (7, 4), (8, 8)]);
+        assert_eq!(LodSettings::for_radius(5, 5).levels, vec![(6, 2), (7, 4), (8, 8)]);
+    }
+
+    fn block(voxel_type: VoxelType) -> Voxel
+    {
+        Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default())
+    }
+
+    //stone up to y 14, a grass layer at y 15 and air above
+    fn hill() -> Chunk
+    {
+        let mut block_data = vec![Voxel::default(); CHUNK_SIZE.pow(3) as usize];
+        for x in 0..CHUNK_SIZE
+        {
+            for y in 0..16
+            {
+                for z in 0..CHUNK_SIZE
+                {
+                    block_data[voxel_index(x, y, z)] = block(if y == 15 { VoxelType::Grass } else { VoxelType::Stone });
+                }
+            }
+        }
+        Chunk { block_data, pos: (0, 0, 0) }
+    }
+
+    fn cell(cells: &[Voxel], factor: i32, position: IVec3) -> VoxelType
+    {
+        let size = CHUNK_SIZE / factor;
+        cells[(position.x * size * size + position.y * size + position.z) as usize].voxel_type
+    }
+
+    #[test]
+    fn downsampling_picks_the_most_common_or_the_top()
+    {
+        let chunk = hill();
+        for factor in [2, 4, 8]
+        {
+            let surface = IVec3::new(0, 15 / factor, 0);
+            let most_common = downsample(&chunk, factor, LodSampling::MostCommon);
+            let top_most = downsample(&chunk, factor, LodSampling::TopMost);
+            assert_eq!(most_common.len(), (CHUNK_SIZE / factor).pow(3) as usize);
+            //at factor 2 stone and grass tie and the lower voxel type wins, further out stone outnumbers the grass
+            assert_eq!(cell(&most_common, factor, surface), VoxelType::Stone);
+            assert_eq!(cell(&top_most, factor, surface), VoxelType::Grass);
+            assert_eq!(cell(&top_most, factor, IVec3::ZERO), VoxelType::Stone);
+            assert_eq!(cell(&top_most, factor, surface + IVec3::Y), VoxelType::Air);
+        }
+    }
+
+    #[test]
+    fn top_most_needs_a_half_full_cell()
+    {
+        //the 2 cube cell at y 14..=15 keeps its grass with half of it air, one more air voxel empties it
+        let mut chunk = hill();
+        for (x, z) in [(0, 0), (0, 1), (1, 0), (1, 1)]
+        {
+            chunk.block_data[voxel_index(x, 14, z)] = Voxel::default();
+        }
+        assert_eq!(cell(&downsample(&chunk, 2, LodSampling::TopMost), 2, IVec3::new(0, 7, 0)), VoxelType::Grass);
+        chunk.block_data[voxel_index(0, 15, 0)] = Voxel::default();
+        assert_eq!(cell(&downsample(&chunk, 2, LodSampling::TopMost), 2, IVec3::new(0, 7, 0)), VoxelType::Air);
+    }
+
+    #[test]
+    fn coarse_meshes_close_the_chunk_with_skirts()
+    {
+        //a full chunk at the origin of chunk 1,0,0 is drawn as a box of coarse faces, even though it would be hidden
+        //by full neighbours at full detail
+        let chunk = Chunk { block_data: vec![block(VoxelType::Stone); CHUNK_SIZE.pow(3) as usize], pos: (CHUNK_SIZE, 0, 0) };
+        for factor in [2, 4, 8]
+        {
+            let texture_info = TextureInfo { map: vec![0; VoxelType::ALL.len()], rects: vec![Rect::new(0.0, 0.0, 16.0, 16.0)], size: Vec2::splat(16.0), mode: TextureMode::Atlas, animations: Vec::new(), layers: Vec::new() };
+            let context = MeshContext { texture_info, lod: factor, sampling: LodSampling::TopMost, block_models: BlockModels::default(), filter: VoxelFilter::All };
+            let chunk_mesh = render_lod(&ChunkNeighbourhood::single(Arc::new(chunk.clone())), &context);
+            let cells = CHUNK_SIZE / factor;
+            assert_eq!(chunk_mesh.indices.len(), (6 * cells * cells * 6) as usize);
+            let (min, max) = chunk_mesh.positions.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), position| (min.min(Vec3::from(*position)), max.max(Vec3::from(*position))));
+            assert_eq!((min, max), (Vec3::new(CHUNK_SIZE as f32, 0.0, 0.0), Vec3::new(2.0 * CHUNK_SIZE as f32, CHUNK_SIZE as f32, CHUNK_SIZE as f32)));
+            //every vertex sits on the coarse grid
+            assert!(chunk_mesh.positions.iter().flatten().all(|coordinate| coordinate % factor as f32 == 0.0));
+        }
+    }
+}
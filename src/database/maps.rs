@@ -0,0 +1,139 @@
+use crate::database::biome::*;
+use crate::database::chunk::*;
+use crate::database::settings::CHUNK_SIZE;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+
+//columns are searched from this chunk down, terrain tops out around y 158
+const TOP_CHUNK: i32 = 5;
+//columns with nothing above this chunk are left empty
+const BOTTOM_CHUNK: i32 = -2;
+
+//top down images of a generated area, one pixel per column. x goes right and z goes down
+pub struct WorldMaps
+{
+    //surface colour from the average texture colour, shaded by the slope so hills stand out
+    pub colour: RgbImage,
+    //terrain height without fluids, one grey level per voxel
+    pub height: GrayImage,
+    pub biome: RgbImage,
+}
+
+//top voxel of a column that is not air or floating glass
+struct Column
+{
+    surface: Option<(i32, VoxelType)>,
+    //terrain below fluids
+    terrain: Option<i32>,
+}
+
+//generates one column of chunks from the top down until every voxel column in it hits terrain,
+//so deep chunks are only generated where they are needed
+fn scan_chunk_column(chunk_x: i32, chunk_z: i32, seed: u32) -> Vec<Column>
+{
+    let mut columns: Vec<Column> = (0..CHUNK_SIZE * CHUNK_SIZE).map(|_| Column { surface: None, terrain: None }).collect();
+    for chunk_y in (BOTTOM_CHUNK..=TOP_CHUNK).rev()
+    {
+        let block_data = generate_chunk_with_seed([chunk_x, chunk_y, chunk_z], seed);
+        for x in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                let column = &mut columns[(x * CHUNK_SIZE + z) as usize];
+                if column.terrain.is_some()
+                {
+                    continue;
+                }
+                for y in (0..CHUNK_SIZE).rev()
+                {
+                    let voxel = &block_data[voxel_index(x, y, z)];
+                    if matches!(voxel.voxel_type, VoxelType::Air | VoxelType::Glass)
+                    {
+                        continue;
+                    }
+                    let height = chunk_y * CHUNK_SIZE + y;
+                    if column.surface.is_none()
+                    {
+                        column.surface = Some((height, voxel.voxel_type));
+                    }
+                    if !voxel.is_fluid()
+                    {
+                        column.terrain = Some(height);
+                        break;
+                    }
+                }
+            }
+        }
+        if columns.iter().all(|column| column.terrain.is_some())
+        {
+            break;
+        }
+    }
+    columns
+}
+
+//colours holds the map colour of every voxel type, indexed by voxel type
+pub fn render_world_maps(seed: u32, min: IVec2, max: IVec2, colours: &[[u8; 3]]) -> WorldMaps
+{
+    let (low, high) = (min.min(max), min.max(max));
+    let size = (high - low + IVec2::ONE).as_uvec2();
+    let mut maps = WorldMaps
+    {
+        colour: RgbImage::new(size.x, size.y),
+        height: GrayImage::new(size.x, size.y),
+        biome: RgbImage::new(size.x, size.y),
+    };
+    let chunk_low = IVec2::new(low.x.div_euclid(CHUNK_SIZE), low.y.div_euclid(CHUNK_SIZE));
+    let chunk_high = IVec2::new(high.x.div_euclid(CHUNK_SIZE), high.y.div_euclid(CHUNK_SIZE));
+    let chunk_columns: Vec<IVec2> = (chunk_low.x..=chunk_high.x).flat_map(|x| (chunk_low.y..=chunk_high.y).map(move |z| IVec2::new(x, z))).collect();
+    //chunk columns are independent so they are split over every core
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let scanned: Vec<(IVec2, Vec<Column>)> = std::thread::scope(|scope|
+    {
+        let handles: Vec<_> = chunk_columns.chunks(chunk_columns.len().div_ceil(threads).max(1)).map(|part|
+        {
+            scope.spawn(move || part.iter().map(|chunk| (*chunk, scan_chunk_column(chunk.x, chunk.y, seed))).collect::<Vec<_>>())
+        }).collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+    let climate = Climate::default();
+    let mut heights = vec![None; (size.x * size.y) as usize];
+    for (chunk, columns) in &scanned
+    {
+        for (index, column) in columns.iter().enumerate()
+        {
+            let position = *chunk * CHUNK_SIZE + IVec2::new(index as i32 / CHUNK_SIZE, index as i32 % CHUNK_SIZE);
+            if position.cmplt(low).any() || position.cmpgt(high).any()
+            {
+                continue;
+            }
+            let pixel = (position - low).as_uvec2();
+            let Some(terrain) = column.terrain else { continue };
+            heights[(pixel.y * size.x + pixel.x) as usize] = Some(terrain);
+            maps.height.put_pixel(pixel.x, pixel.y, Luma([terrain.clamp(0, 255) as u8]));
+            let (surface_height, surface_type) = column.surface.unwrap_or((terrain, VoxelType::Stone));
+            let underwater = surface_height > terrain;
+            let biome = Biome::classify(&climate, IVec3::new(position.x, terrain, position.y), underwater);
+            maps.biome.put_pixel(pixel.x, pixel.y, Rgb(biome.colour()));
+            maps.colour.put_pixel(pixel.x, pixel.y, Rgb(colours.get(surface_type as usize).copied().unwrap_or([255, 0, 255])));
+        }
+    }
+    //light comes from the north west, slopes facing it get brighter
+    for y in 0..size.y
+    {
+        for x in 0..size.x
+        {
+            let here = heights[(y * size.x + x) as usize];
+            let previous = if x > 0 && y > 0 { heights[((y - 1) * size.x + x - 1) as usize] } else { here };
+            let (Some(here), Some(previous)) = (here, previous) else { continue };
+            let shade = (1.0 + (here - previous) as f32 * 0.1).clamp(0.6, 1.4);
+            let pixel = maps.colour.get_pixel_mut(x, y);
+            for channel in &mut pixel.0
+            {
+                *channel = (*channel as f32 * shade).min(255.0) as u8;
+            }
+        }
+    }
+    maps
+}
@@ -0,0 +1,688 @@
+use crate::database::chunk::*;
+use crate::database::lod::*;
+use crate::database::model::BlockModels;
+use crate::database::sector::LoaderChunks;
+use crate::database::settings::{TextureMode, SMOOTH_BLOCK_TYPES, SMOOTH_SECTORS};
+use crate::database::surface_nets::SurfaceNetsMesher;
+use crate::database::texture::*;
+use crate::database::texture_array::{ATTRIBUTE_TEXTURE_ANIMATION, ATTRIBUTE_TEXTURE_LAYER};
+use crate::database::voxel::*;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+//the triangles of one material inside a ChunkMesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubMesh
+{
+    pub material: VoxelType,
+    //range into ChunkMesh::indices
+    pub indices: Range<usize>,
+}
+
+//mesh buffers a chunk mesher hands back, every vertex attribute has one entry per position
+#[derive(Default, Debug, Clone)]
+pub struct ChunkMesh
+{
+    pub positions: Vec<[f32; 3]>,
+    //left empty by meshers that only build flat shaded triangles, finish fills them in from the winding
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub colours: Vec<[f32; 4]>,
+    //texture array layer of every vertex, filled by set_texture_attributes and left empty for the atlas
+    pub layers: Vec<u32>,
+    //frame count, seconds per frame and the distance between frames of every vertex's texture, filled by
+    //set_texture_attributes for both texture modes
+    pub animations: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+    //filled by finish, indices are grouped so every material is one contiguous range
+    pub sub_meshes: Vec<SubMesh>,
+    //material of every triangle in indices until finish groups them
+    triangle_materials: Vec<VoxelType>,
+}
+
+impl ChunkMesh
+{
+    //adds a vertex with its own index, every third one closes a triangle of the given material
+    pub fn push_vertex(&mut self, material: VoxelType, position: [f32; 3], uv: [f32; 2])
+    {
+        self.positions.push(position);
+        self.uvs.push(uv);
+        self.colours.push([1.0; 4]);
+        self.indices.push((self.positions.len() - 1) as u32);
+        if self.indices.len().is_multiple_of(3)
+        {
+            self.triangle_materials.push(material);
+        }
+    }
+
+    //adds a triangle between vertices that are already in the mesh
+    pub fn push_triangle(&mut self, material: VoxelType, corners: [u32; 3])
+    {
+        self.indices.extend(corners);
+        self.triangle_materials.push(material);
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.indices.is_empty()
+    }
+
+    pub fn append(&mut self, mut other: ChunkMesh)
+    {
+        let offset = self.positions.len() as u32;
+        //a mesh that brings its own normals keeps them, the other side gets flat ones so the buffers stay the same length
+        if self.normals.is_empty() != other.normals.is_empty()
+        {
+            self.fill_flat_normals();
+            other.fill_flat_normals();
+        }
+        self.positions.append(&mut other.positions);
+        self.normals.append(&mut other.normals);
+        self.uvs.append(&mut other.uvs);
+        self.colours.append(&mut other.colours);
+        self.layers.append(&mut other.layers);
+        self.animations.append(&mut other.animations);
+        self.indices.extend(other.indices.iter().map(|index| index + offset));
+        self.triangle_materials.append(&mut other.triangle_materials);
+        self.sub_meshes.clear();
+    }
+
+    //one normal per vertex from the winding of the triangle using it, only right for meshes that do not share vertices
+    fn fill_flat_normals(&mut self)
+    {
+        if !self.normals.is_empty()
+        {
+            return;
+        }
+        self.normals = vec![[0.0, 1.0, 0.0]; self.positions.len()];
+        for triangle in self.indices.chunks_exact(3)
+        {
+            let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from(self.positions[triangle[corner] as usize]));
+            let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+            for index in triangle
+            {
+                self.normals[*index as usize] = normal;
+            }
+        }
+    }
+
+    //fills in missing normals and groups the triangles into one sub mesh per material, in VoxelType order
+    pub fn finish(mut self) -> ChunkMesh
+    {
+        self.fill_flat_normals();
+        let mut triangles: Vec<(VoxelType, [u32; 3])> = self.indices.chunks_exact(3).zip(&self.triangle_materials).map(|(triangle, material)| (*material, [triangle[0], triangle[1], triangle[2]])).collect();
+        triangles.sort_by_key(|(material, _)| *material as usize);
+        self.indices.clear();
+        self.sub_meshes.clear();
+        for (material, triangle) in &triangles
+        {
+            match self.sub_meshes.last_mut()
+            {
+                Some(sub_mesh) if sub_mesh.material == *material => sub_mesh.indices.end += 3,
+                _ => self.sub_meshes.push(SubMesh { material: *material, indices: self.indices.len()..self.indices.len() + 3 }),
+            }
+            self.indices.extend(triangle);
+        }
+        self.triangle_materials = triangles.into_iter().map(|(material, _)| material).collect();
+        self
+    }
+
+    //gives every vertex the animation of its triangle's material and its layer for texture arrays, a vertex shared by
+    //triangles of different materials gets the last one
+    pub fn set_texture_attributes(&mut self, texture_info: &TextureInfo)
+    {
+        let array = texture_info.mode == TextureMode::Array;
+        self.layers = if array { vec![0; self.positions.len()] } else { Vec::new() };
+        self.animations = vec![[1.0, 0.0, 0.0]; self.positions.len()];
+        for (triangle, material) in self.indices.chunks_exact(3).zip(&self.triangle_materials)
+        {
+            let animation = texture_info.animation(*material);
+            let frame_step = texture_info.frame_step(*material);
+            for index in triangle
+            {
+                if array
+                {
+                    self.layers[*index as usize] = texture_info.layer(*material);
+                }
+                self.animations[*index as usize] = [animation.frames as f32, animation.frame_time, frame_step];
+            }
+        }
+    }
+
+    pub fn into_mesh(mut self) -> Mesh
+    {
+        self.fill_flat_normals();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colours);
+        if !self.layers.is_empty()
+        {
+            mesh.insert_attribute(ATTRIBUTE_TEXTURE_LAYER, self.layers);
+        }
+        if !self.animations.is_empty()
+        {
+            mesh.insert_attribute(ATTRIBUTE_TEXTURE_ANIMATION, self.animations);
+        }
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+//which voxel types a mesher is asked to mesh
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum VoxelFilter
+{
+    #[default]
+    All,
+    Only(Vec<VoxelType>),
+    Except(Vec<VoxelType>),
+}
+
+impl VoxelFilter
+{
+    pub fn contains(&self, voxel_type: VoxelType) -> bool
+    {
+        match self
+        {
+            VoxelFilter::All => true,
+            VoxelFilter::Only(voxel_types) => voxel_types.contains(&voxel_type),
+            VoxelFilter::Except(voxel_types) => !voxel_types.contains(&voxel_type),
+        }
+    }
+}
+
+//everything about a meshing job that is not voxel data
+#[derive(Debug)]
+pub struct MeshContext
+{
+    pub texture_info: TextureInfo,
+    //factor from LodSettings, 1 for full detail
+    pub lod: i32,
+    pub sampling: LodSampling,
+    pub block_models: BlockModels,
+    //voxels the mesher should build geometry for, the others are still there to cull against
+    pub filter: VoxelFilter,
+}
+
+//turns a chunk into a mesh. runs on the async compute pool so it only gets a snapshot of the chunk and its neighbours
+pub trait ChunkMesher: Send + Sync
+{
+    //chunk is the center of the neighbourhood, the neighbourhood reaches one chunk further in every direction
+    fn mesh(&self, chunk: &Chunk, neighbourhood: &ChunkNeighbourhood, context: &MeshContext) -> ChunkMesh;
+
+    //whether the mesh depends on the chunks sharing only an edge or corner with it, not just the 6 face neighbours
+    //those get remeshed too when a chunk finishes generating
+    fn uses_diagonal_neighbours(&self) -> bool
+    {
+        false
+    }
+}
+
+//the voxel faces from render_update, coarser levels of detail go through render_lod
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockyMesher;
+
+impl ChunkMesher for BlockyMesher
+{
+    fn mesh(&self, _chunk: &Chunk, neighbourhood: &ChunkNeighbourhood, context: &MeshContext) -> ChunkMesh
+    {
+        if context.lod > 1 { render_lod(neighbourhood, context) } else { render_update(neighbourhood, &context.texture_info, &context.block_models, &context.filter) }
+    }
+}
+
+//which mesher builds which part of a sector's chunks. a block type registered here is meshed by its own mesher in
+//every sector, everything else by the sector's mesher. change them before the chunks load, chunks already meshed
+//keep their mesh until remeshed
+#[derive(Resource, Clone)]
+pub struct ChunkMeshers
+{
+    pub default: Arc<dyn ChunkMesher>,
+    sectors: HashMap<[i32; 3], Arc<dyn ChunkMesher>>,
+    block_types: HashMap<VoxelType, Arc<dyn ChunkMesher>>,
+}
+
+impl Default for ChunkMeshers
+{
+    fn default() -> Self
+    {
+        ChunkMeshers { default: Arc::new(BlockyMesher), sectors: HashMap::new(), block_types: HashMap::new() }
+    }
+}
+
+impl ChunkMeshers
+{
+    pub fn set_sector(&mut self, sector: [i32; 3], mesher: impl ChunkMesher + 'static)
+    {
+        self.sectors.insert(sector, Arc::new(mesher));
+    }
+
+    //back to the default mesher
+    pub fn reset_sector(&mut self, sector: [i32; 3])
+    {
+        self.sectors.remove(&sector);
+    }
+
+    //one mesher can be shared by several block types by registering the same arc for each
+    pub fn set_block_type(&mut self, voxel_type: VoxelType, mesher: Arc<dyn ChunkMesher>)
+    {
+        self.block_types.insert(voxel_type, mesher);
+    }
+
+    pub fn reset_block_type(&mut self, voxel_type: VoxelType)
+    {
+        self.block_types.remove(&voxel_type);
+    }
+
+    pub fn sector(&self, sector: [i32; 3]) -> &Arc<dyn ChunkMesher>
+    {
+        self.sectors.get(&sector).unwrap_or(&self.default)
+    }
+
+    //the meshers a chunk in the sector goes through
+    pub fn for_sector(&self, sector: [i32; 3]) -> MesherSet
+    {
+        //block types sharing a mesher are meshed in one go
+        let mut block_meshers: Vec<(Arc<dyn ChunkMesher>, Vec<VoxelType>)> = Vec::new();
+        let mut block_types: Vec<_> = self.block_types.iter().collect();
+        block_types.sort_by_key(|(voxel_type, _)| **voxel_type as usize);
+        for (voxel_type, mesher) in block_types
+        {
+            match block_meshers.iter_mut().find(|(existing, _)| Arc::ptr_eq(existing, mesher))
+            {
+                Some((_, voxel_types)) => voxel_types.push(*voxel_type),
+                None => block_meshers.push((mesher.clone(), vec![*voxel_type])),
+            }
+        }
+        MesherSet { sector: self.sector(sector).clone(), block_meshers }
+    }
+}
+
+//the smooth sectors and block types from settings, everything else is blocky
+pub fn configured_meshers() -> ChunkMeshers
+{
+    let mut meshers = ChunkMeshers::default();
+    for sector in SMOOTH_SECTORS
+    {
+        meshers.set_sector(*sector, SurfaceNetsMesher::default());
+    }
+    //shared so all the smooth block types are meshed together and join up into one surface
+    let smooth: Arc<dyn ChunkMesher> = Arc::new(SurfaceNetsMesher::default());
+    for voxel_type in SMOOTH_BLOCK_TYPES
+    {
+        meshers.set_block_type(*voxel_type, smooth.clone());
+    }
+    meshers
+}
+
+//the meshers for one chunk, cheap to clone into a meshing task
+#[derive(Clone)]
+pub struct MesherSet
+{
+    pub sector: Arc<dyn ChunkMesher>,
+    pub block_meshers: Vec<(Arc<dyn ChunkMesher>, Vec<VoxelType>)>,
+}
+
+impl MesherSet
+{
+    pub fn uses_diagonal_neighbours(&self) -> bool
+    {
+        self.sector.uses_diagonal_neighbours() || self.block_meshers.iter().any(|(mesher, _)| mesher.uses_diagonal_neighbours())
+    }
+
+    //runs the sector mesher on everything without a block mesher, then every block mesher on its own types
+    pub fn mesh(&self, neighbourhood: &ChunkNeighbourhood, texture_info: TextureInfo, block_models: BlockModels, lod: i32, sampling: LodSampling) -> ChunkMesh
+    {
+        let chunk = neighbourhood.center();
+        let registered: Vec<VoxelType> = self.block_meshers.iter().flat_map(|(_, voxel_types)| voxel_types.iter().copied()).collect();
+        let filter = if registered.is_empty() { VoxelFilter::All } else { VoxelFilter::Except(registered) };
+        let mut context = MeshContext { texture_info, lod, sampling, block_models, filter };
+        let mut chunk_mesh = self.sector.mesh(chunk, neighbourhood, &context);
+        for (mesher, voxel_types) in &self.block_meshers
+        {
+            if !chunk.block_data.iter().any(|voxel| voxel_types.contains(&voxel.voxel_type))
+            {
+                continue;
+            }
+            context.filter = VoxelFilter::Only(voxel_types.clone());
+            chunk_mesh.append(mesher.mesh(chunk, neighbourhood, &context));
+        }
+        let mut chunk_mesh = chunk_mesh.finish();
+        chunk_mesh.set_texture_attributes(&context.texture_info);
+        chunk_mesh
+    }
+}
+
+//everything chunk_handler needs to start meshing a chunk
+#[derive(SystemParam)]
+pub struct ChunkMeshing<'w>
+{
+    pub texture_atlas_data: Res<'w, TextureAtlasBuilt>,
+    pub loader_chunks: Res<'w, LoaderChunks>,
+    pub lod_settings: Res<'w, LodSettings>,
+    pub meshers: Res<'w, ChunkMeshers>,
+    pub block_models: Res<'w, BlockModels>,
+}
+
+impl ChunkMeshing<'_>
+{
+    //chunks remeshed along with a chunk that just finished generating, the chunk itself first
+    pub fn affected_neighbours(&self, sector: [i32; 3]) -> Vec<[i32; 3]>
+    {
+        let mut offsets = vec![[0, 0, 0], [1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+        if self.meshers.for_sector(sector).uses_diagonal_neighbours()
+        {
+            for x in -1..=1
+            {
+                for y in -1..=1
+                {
+                    for z in -1..=1
+                    {
+                        if [x, y, z].iter().filter(|offset| **offset != 0).count() > 1
+                        {
+                            offsets.push([x, y, z]);
+                        }
+                    }
+                }
+            }
+        }
+        offsets
+    }
+
+    //meshes a chunk neighbourhood on the async compute pool with the sector's meshers at the chunk's level of detail
+    pub fn spawn_task(&self, thread_pool: &AsyncComputeTaskPool, sector: [i32; 3], position: [i32; 3], neighbourhood: ChunkNeighbourhood) -> (Task<ChunkMesh>, ChunkLod)
+    {
+        let lod = ChunkLod(self.lod_settings.factor(lod_distance(&self.loader_chunks, sector, position)));
+        let sampling = self.lod_settings.sampling;
+        let meshers = self.meshers.for_sector(sector);
+        let block_models = self.block_models.clone();
+        let texture_atlas_data_copy = self.texture_atlas_data.clone();
+        let task = thread_pool.spawn(async move
+            {
+                let texture_info = TextureInfo
+                {
+                    map: texture_atlas_data_copy.texture_map.to_vec(),
+                    rects: texture_atlas_data_copy.texture_rects.to_vec(),
+                    size: texture_atlas_data_copy.texture_size,
+                    mode: if texture_atlas_data_copy.array.is_some() { TextureMode::Array } else { TextureMode::Atlas },
+                    animations: texture_atlas_data_copy.animations.to_vec(),
+                    layers: texture_atlas_data_copy.array_layers.to_vec(),
+                };
+                meshers.mesh(&neighbourhood, texture_info, block_models, lod.0, sampling)
+            });
+        (task, lod)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+    use crate::database::sector::SectorsRendering;
+
+    fn block(voxel_type: VoxelType) -> Voxel
+    {
+        Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default())
+    }
+
+    //every voxel type drawn with the same 16 pixel texture
+    fn texture_info() -> TextureInfo
+    {
+        TextureInfo
+        {
+            map: vec![0; VoxelType::ALL.len()],
+            rects: vec![Rect::new(0.0, 0.0, 16.0, 16.0)],
+            size: Vec2::splat(16.0),
+            mode: TextureMode::Atlas,
+            animations: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    //meshes chunk 0,0,0 of a world holding only the given voxels
+    fn mesh(meshers: &MesherSet, voxels: &[(IVec3, Voxel)]) -> ChunkMesh
+    {
+        mesh_chunks(meshers, voxels, &[[0, 0, 0]])
+    }
+
+    //meshes the given chunks of a world reaching one chunk past chunk 1,1,1 and puts their meshes together
+    fn mesh_chunks(meshers: &MesherSet, voxels: &[(IVec3, Voxel)], chunks: &[[i32; 3]]) -> ChunkMesh
+    {
+        let mut sector = SectorsRendering::default();
+        for (chunk_id, chunk) in test_world([-1, -1, -1], [2, 2, 2], voxels)
+        {
+            sector.chunks.insert(chunk_id, Some(Arc::new(chunk)));
+        }
+        let mut chunk_mesh = ChunkMesh::default();
+        for chunk in chunks
+        {
+            let neighbourhood = ChunkNeighbourhood::from_loaded(&sector, *chunk).unwrap();
+            chunk_mesh.append(meshers.mesh(&neighbourhood, texture_info(), BlockModels::default(), 1, LodSampling::default()));
+        }
+        chunk_mesh.finish()
+    }
+
+    fn only(mesher: impl ChunkMesher + 'static) -> MesherSet
+    {
+        MesherSet { sector: Arc::new(mesher), block_meshers: Vec::new() }
+    }
+
+    fn slab(voxel_type: VoxelType) -> Vec<(IVec3, Voxel)>
+    {
+        vec![(IVec3::new(10, 10, 10), block(voxel_type)), (IVec3::new(11, 10, 10), block(voxel_type)), (IVec3::new(10, 10, 11), block(voxel_type)), (IVec3::new(11, 10, 11), block(voxel_type))]
+    }
+
+    fn triangles(chunk_mesh: &ChunkMesh) -> usize
+    {
+        chunk_mesh.indices.len() / 3
+    }
+
+    //vertices are not shared between triangles, so corners are counted by position
+    fn corners(chunk_mesh: &ChunkMesh) -> usize
+    {
+        let mut positions: Vec<[u32; 3]> = chunk_mesh.positions.iter().map(|position| position.map(f32::to_bits)).collect();
+        positions.sort();
+        positions.dedup();
+        positions.len()
+    }
+
+    fn check_buffers(chunk_mesh: &ChunkMesh)
+    {
+        assert_eq!(chunk_mesh.normals.len(), chunk_mesh.positions.len());
+        assert_eq!(chunk_mesh.uvs.len(), chunk_mesh.positions.len());
+        assert_eq!(chunk_mesh.animations.len(), chunk_mesh.positions.len());
+        assert!(chunk_mesh.indices.iter().all(|index| (*index as usize) < chunk_mesh.positions.len()));
+        let covered: usize = chunk_mesh.sub_meshes.iter().map(|sub_mesh| sub_mesh.indices.len()).sum();
+        assert_eq!(covered, chunk_mesh.indices.len());
+    }
+
+    #[test]
+    fn empty_chunk_has_no_mesh()
+    {
+        assert!(mesh(&only(BlockyMesher), &[]).is_empty());
+        assert!(mesh(&only(SurfaceNetsMesher::default()), &[]).is_empty());
+    }
+
+    #[test]
+    fn cube_mesher_single_voxel()
+    {
+        let chunk_mesh = mesh(&only(BlockyMesher), &[(IVec3::new(10, 10, 10), block(VoxelType::Stone))]);
+        check_buffers(&chunk_mesh);
+        assert_eq!(triangles(&chunk_mesh), 6 * 2);
+        assert_eq!(corners(&chunk_mesh), 8);
+        assert_eq!(chunk_mesh.sub_meshes.len(), 1);
+        assert_eq!(chunk_mesh.sub_meshes[0].material, VoxelType::Stone);
+    }
+
+    #[test]
+    fn cube_mesher_culls_faces_inside_a_slab()
+    {
+        //4 top, 4 bottom and 8 side faces, the 4 faces between the voxels are hidden
+        let chunk_mesh = mesh(&only(BlockyMesher), &slab(VoxelType::Stone));
+        check_buffers(&chunk_mesh);
+        assert_eq!(triangles(&chunk_mesh), 16 * 2);
+        assert_eq!(chunk_mesh.sub_meshes.len(), 1);
+    }
+
+    #[test]
+    fn sub_meshes_follow_voxel_type_order()
+    {
+        let mut voxels = slab(VoxelType::Dirt);
+        voxels[0].1 = block(VoxelType::Stone);
+        let chunk_mesh = mesh(&only(BlockyMesher), &voxels);
+        check_buffers(&chunk_mesh);
+        let materials: Vec<VoxelType> = chunk_mesh.sub_meshes.iter().map(|sub_mesh| sub_mesh.material).collect();
+        assert_eq!(materials, vec![VoxelType::Stone, VoxelType::Dirt]);
+        assert_eq!(chunk_mesh.sub_meshes[0].indices.len(), 4 * 6);
+        assert_eq!(chunk_mesh.sub_meshes[1].indices.len(), 12 * 6);
+    }
+
+    #[test]
+    fn surface_nets_single_voxel()
+    {
+        //one vertex in each of the 8 cells around the voxel and one quad for each of its 6 edges to air
+        let chunk_mesh = mesh(&only(SurfaceNetsMesher::default()), &[(IVec3::new(10, 10, 10), block(VoxelType::Stone))]);
+        check_buffers(&chunk_mesh);
+        assert_eq!(corners(&chunk_mesh), 8);
+        assert_eq!(triangles(&chunk_mesh), 6 * 2);
+        assert_eq!(chunk_mesh.sub_meshes.len(), 1);
+        assert_eq!(chunk_mesh.sub_meshes[0].material, VoxelType::Stone);
+    }
+
+    #[test]
+    fn surface_nets_slab()
+    {
+        //3 by 2 by 3 cells touch the slab, every one of its 16 edges to air is a quad
+        let chunk_mesh = mesh(&only(SurfaceNetsMesher::default()), &slab(VoxelType::Stone));
+        check_buffers(&chunk_mesh);
+        assert_eq!(corners(&chunk_mesh), 18);
+        assert_eq!(triangles(&chunk_mesh), 16 * 2);
+        assert_eq!(chunk_mesh.sub_meshes.len(), 1);
+    }
+
+    #[test]
+    fn block_mesher_takes_its_own_types()
+    {
+        //stone is smooth, the dirt voxel registered to the cube mesher keeps its 6 faces
+        let meshers = MesherSet { sector: Arc::new(SurfaceNetsMesher::default()), block_meshers: vec![(Arc::new(BlockyMesher), vec![VoxelType::Dirt])] };
+        let chunk_mesh = mesh(&meshers, &[(IVec3::new(10, 10, 10), block(VoxelType::Stone)), (IVec3::new(20, 10, 10), block(VoxelType::Dirt))]);
+        check_buffers(&chunk_mesh);
+        let materials: Vec<VoxelType> = chunk_mesh.sub_meshes.iter().map(|sub_mesh| sub_mesh.material).collect();
+        assert_eq!(materials, vec![VoxelType::Stone, VoxelType::Dirt]);
+        assert_eq!(chunk_mesh.sub_meshes[0].indices.len(), 6 * 6);
+        assert_eq!(chunk_mesh.sub_meshes[1].indices.len(), 6 * 6);
+    }
+
+    #[test]
+    fn surface_nets_seams_are_watertight()
+    {
+        //balls straddling a chunk face, a chunk edge and the corner between 8 chunks
+        let mut voxels = Vec::new();
+        for center in [IVec3::new(32, 10, 10), IVec3::new(32, 20, 32), IVec3::splat(32)]
+        {
+            for x in -4..=4
+            {
+                for y in -4..=4
+                {
+                    for z in -4..=4
+                    {
+                        let offset = IVec3::new(x, y, z);
+                        if offset.length_squared() <= 12
+                        {
+                            voxels.push((center + offset, block(VoxelType::Stone)));
+                        }
+                    }
+                }
+            }
+        }
+        let mut meshers = ChunkMeshers::default();
+        meshers.set_sector([0, 0, 0], SurfaceNetsMesher::default());
+        let chunks: Vec<[i32; 3]> = (0..8).map(|index| [index >> 2 & 1, index >> 1 & 1, index & 1]).collect();
+        let chunk_mesh = mesh_chunks(&meshers.for_sector([0, 0, 0]), &voxels, &chunks);
+        check_buffers(&chunk_mesh);
+        assert!(!chunk_mesh.is_empty());
+
+        //closed when every edge of every triangle is walked the other way by exactly one other triangle
+        let mut edges: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+        for triangle in chunk_mesh.indices.chunks_exact(3)
+        {
+            let corners = [0, 1, 2].map(|corner| chunk_mesh.positions[triangle[corner] as usize].map(f32::to_bits));
+            for corner in 0..3
+            {
+                *edges.entry((corners[corner], corners[(corner + 1) % 3])).or_default() += 1;
+            }
+        }
+        for ((from, to), count) in &edges
+        {
+            assert_eq!(*count, 1, "edge {from:?} {to:?} is used more than once");
+            assert_eq!(edges.get(&(*to, *from)), Some(&1), "edge {:?} {:?} is open", from.map(f32::from_bits), to.map(f32::from_bits));
+        }
+    }
+
+    #[test]
+    fn block_types_from_settings_share_a_mesher()
+    {
+        let mut meshers = ChunkMeshers::default();
+        let smooth: Arc<dyn ChunkMesher> = Arc::new(SurfaceNetsMesher::default());
+        meshers.set_block_type(VoxelType::Stone, smooth.clone());
+        meshers.set_block_type(VoxelType::Dirt, smooth);
+        meshers.set_sector([1, 0, 0], SurfaceNetsMesher::default());
+        let set = meshers.for_sector([0, 0, 0]);
+        assert_eq!(set.block_meshers.len(), 1);
+        assert_eq!(set.block_meshers[0].1, vec![VoxelType::Stone, VoxelType::Dirt]);
+        assert!(set.uses_diagonal_neighbours());
+        assert!(!Arc::ptr_eq(meshers.sector([1, 0, 0]), &meshers.default));
+        meshers.reset_sector([1, 0, 0]);
+        assert!(Arc::ptr_eq(meshers.sector([1, 0, 0]), &meshers.default));
+        meshers.reset_block_type(VoxelType::Dirt);
+        assert_eq!(meshers.for_sector([0, 0, 0]).block_meshers[0].1, vec![VoxelType::Stone]);
+    }
+
+    #[test]
+    fn atlas_meshes_animate_in_the_shader()
+    {
+        //lava is a strip of 4 frames in a 32x64 atlas, everything else uses the still texture next to it
+        let mut info = TextureInfo
+        {
+            map: vec![1; VoxelType::ALL.len()],
+            rects: vec![Rect::new(0.0, 0.0, 16.0, 64.0), Rect::new(16.0, 0.0, 32.0, 16.0)],
+            size: Vec2::new(32.0, 64.0),
+            mode: TextureMode::Atlas,
+            animations: vec![TextureAnimation { frames: 4, frame_time: 0.25 }, TextureAnimation::default()],
+            layers: Vec::new(),
+        };
+        info.map[VoxelType::Lava as usize] = 0;
+        assert_eq!(info.uv_rect(VoxelType::Lava), Rect::new(0.0, 0.0, 0.5, 0.25));
+        let mut chunk_mesh = ChunkMesh::default();
+        chunk_mesh.push_vertex(VoxelType::Lava, [0.0; 3], [0.0; 2]);
+        chunk_mesh.push_vertex(VoxelType::Lava, [1.0, 0.0, 0.0], [0.0; 2]);
+        chunk_mesh.push_vertex(VoxelType::Lava, [0.0, 0.0, 1.0], [0.0; 2]);
+        chunk_mesh.push_vertex(VoxelType::Stone, [0.0; 3], [0.0; 2]);
+        chunk_mesh.push_vertex(VoxelType::Stone, [1.0, 0.0, 0.0], [0.0; 2]);
+        chunk_mesh.push_vertex(VoxelType::Stone, [0.0, 0.0, 1.0], [0.0; 2]);
+        let mut chunk_mesh = chunk_mesh.finish();
+        chunk_mesh.set_texture_attributes(&info);
+        //the atlas has no layers, every frame is a frame height further down
+        assert!(chunk_mesh.layers.is_empty());
+        assert_eq!(chunk_mesh.animations[..3], [[4.0, 0.25, 0.25]; 3]);
+        assert_eq!(chunk_mesh.animations[3..], [[1.0, 0.0, 0.25]; 3]);
+        let atlas_mesh = chunk_mesh.into_mesh();
+        assert!(atlas_mesh.attribute(ATTRIBUTE_TEXTURE_ANIMATION).is_some());
+        assert!(atlas_mesh.attribute(ATTRIBUTE_TEXTURE_LAYER).is_none());
+
+        //texture arrays step a layer per frame
+        info.mode = TextureMode::Array;
+        info.layers = vec![0, 4];
+        let mut chunk_mesh = mesh(&only(BlockyMesher), &[(IVec3::new(10, 10, 10), block(VoxelType::Stone))]);
+        chunk_mesh.set_texture_attributes(&info);
+        assert!(chunk_mesh.layers.iter().all(|layer| *layer == 4));
+        assert!(chunk_mesh.animations.iter().all(|animation| *animation == [1.0, 0.0, 1.0]));
+    }
+}
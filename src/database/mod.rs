@@ -4,4 +4,27 @@ pub mod voxel;
 pub mod chunk;
 pub mod example_chunk;
 pub mod settings;
-pub mod sector;
\ No newline at end of file
+pub mod sector;
+pub mod events;
+pub mod edit;
+pub mod physics;
+pub mod raycast;
+pub mod interaction;
+pub mod fluid;
+pub mod scheduler;
+pub mod gravity;
+pub mod biome;
+pub mod bulk;
+pub mod history;
+pub mod schematic;
+pub mod vox;
+pub mod export;
+pub mod cli;
+pub mod maps;
+pub mod generator;
+pub mod lod;
+pub mod svo;
+pub mod mesher;
+pub mod surface_nets;
+pub mod model;
+pub mod texture_array;
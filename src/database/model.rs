@@ -0,0 +1,500 @@
+use crate::database::schematic::invalid;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+//side of a voxel, named like minecraft models: north is -z, east is +x
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFace
+{
+    Down,
+    Up,
+    North,
+    South,
+    West,
+    East,
+}
+
+impl ModelFace
+{
+    pub fn normal(self) -> IVec3
+    {
+        match self
+        {
+            ModelFace::Down => IVec3::NEG_Y,
+            ModelFace::Up => IVec3::Y,
+            ModelFace::North => IVec3::NEG_Z,
+            ModelFace::South => IVec3::Z,
+            ModelFace::West => IVec3::NEG_X,
+            ModelFace::East => IVec3::X,
+        }
+    }
+
+    //the texture coordinates of a point on the face in texels, minecraft's default face uvs
+    fn uv(self, point: Vec3) -> Vec2
+    {
+        match self
+        {
+            ModelFace::Down => Vec2::new(point.x, 16.0 - point.z),
+            ModelFace::Up => Vec2::new(point.x, point.z),
+            ModelFace::North => Vec2::new(16.0 - point.x, 16.0 - point.y),
+            ModelFace::South => Vec2::new(point.x, 16.0 - point.y),
+            ModelFace::West => Vec2::new(point.z, 16.0 - point.y),
+            ModelFace::East => Vec2::new(16.0 - point.z, 16.0 - point.y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelAxis
+{
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ElementRotation
+{
+    //in texels like from and to
+    pub origin: [f32; 3],
+    pub axis: ModelAxis,
+    //degrees, counter clockwise looking down the axis
+    pub angle: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FaceDefinition
+{
+    //u1, v1, u2, v2 in texels of the voxel's texture, defaults to the part of the texture the face covers
+    #[serde(default)]
+    pub uv: Option<[f32; 4]>,
+    //the face is skipped when the neighbour on this side is solid
+    #[serde(default)]
+    pub cullface: Option<ModelFace>,
+}
+
+//a box from one corner to the other, in texels where 16 is a whole voxel. faces that are not listed are not drawn
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ElementDefinition
+{
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    #[serde(default)]
+    pub rotation: Option<ElementRotation>,
+    pub faces: HashMap<ModelFace, FaceDefinition>,
+    //only drawn when the neighbour on this side is solid or the same variant, for fence rails and pane arms
+    #[serde(default)]
+    pub connect: Option<ModelFace>,
+}
+
+//the json or ron layout of a block model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BlockModelDefinition
+{
+    pub elements: Vec<ElementDefinition>,
+    //voxel types and variants drawn with this model instead of the variant's own, like ["grass", "cross"] for tufts
+    //a stair model named tuft is joined by tuft_inner and tuft_outer for the corners
+    #[serde(default)]
+    pub used_by: Vec<(VoxelType, VoxelVariant)>,
+}
+
+//one face of a model element, ready to mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelQuad
+{
+    //in the voxel's local 0..1 space before the voxel rotation, counter clockwise seen from the front
+    pub corners: [Vec3; 4],
+    //0..1 inside the voxel's texture
+    pub uvs: [Vec2; 4],
+    pub cullface: Option<ModelFace>,
+    pub connect: Option<ModelFace>,
+}
+
+//a block shape made of quads, built from a BlockModelDefinition
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockModel
+{
+    pub quads: Vec<ModelQuad>,
+    pub used_by: Vec<(VoxelType, VoxelVariant)>,
+}
+
+impl BlockModel
+{
+    pub fn from_definition(definition: &BlockModelDefinition) -> BlockModel
+    {
+        let mut quads = Vec::new();
+        for element in &definition.elements
+        {
+            let from = Vec3::from(element.from).min(Vec3::from(element.to));
+            let to = Vec3::from(element.from).max(Vec3::from(element.to));
+            //listed in a fixed order so the mesh does not depend on hash order
+            for face in [ModelFace::Down, ModelFace::Up, ModelFace::North, ModelFace::South, ModelFace::West, ModelFace::East]
+            {
+                let Some(face_definition) = element.faces.get(&face) else { continue };
+                let mut corners = face_corners(face, from, to);
+                let normal = face.normal().as_vec3();
+                if (corners[1] - corners[0]).cross(corners[2] - corners[0]).dot(normal) < 0.0
+                {
+                    corners.reverse();
+                }
+                let default_uvs = corners.map(|corner| face.uv(corner));
+                let default_min = default_uvs.iter().fold(Vec2::splat(f32::MAX), |min, uv| min.min(*uv));
+                let default_size = default_uvs.iter().fold(Vec2::splat(f32::MIN), |max, uv| max.max(*uv)) - default_min;
+                let uvs = default_uvs.map(|uv| match face_definition.uv
+                {
+                    //the explicit rectangle is stretched over the face the same way the default one would be
+                    Some([u1, v1, u2, v2]) =>
+                    {
+                        let along = ((uv - default_min) / default_size.max(Vec2::splat(f32::EPSILON))).clamp(Vec2::ZERO, Vec2::ONE);
+                        Vec2::new(u1 + (u2 - u1) * along.x, v1 + (v2 - v1) * along.y) / 16.0
+                    }
+                    None => uv / 16.0,
+                });
+                let corners = corners.map(|corner| match &element.rotation
+                {
+                    Some(rotation) => rotate_around(corner, rotation) / 16.0,
+                    None => corner / 16.0,
+                });
+                quads.push(ModelQuad { corners, uvs, cullface: face_definition.cullface, connect: element.connect });
+            }
+        }
+        BlockModel { quads, used_by: definition.used_by.clone() }
+    }
+
+    pub fn from_json(text: &str) -> std::io::Result<BlockModel>
+    {
+        let definition: BlockModelDefinition = serde_json::from_str(text).map_err(|error| invalid(&error.to_string()))?;
+        Ok(BlockModel::from_definition(&definition))
+    }
+
+    pub fn from_ron(text: &str) -> std::io::Result<BlockModel>
+    {
+        let definition: BlockModelDefinition = ron::from_str(text).map_err(|error| invalid(&error.to_string()))?;
+        Ok(BlockModel::from_definition(&definition))
+    }
+
+    //.ron files are read as ron, anything else as json
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<BlockModel>
+    {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "ron") { BlockModel::from_ron(&text) } else { BlockModel::from_json(&text) }
+    }
+}
+
+//the 4 corners of one face of the box, in either winding
+fn face_corners(face: ModelFace, from: Vec3, to: Vec3) -> [Vec3; 4]
+{
+    let (axis, value) = match face
+    {
+        ModelFace::Down => (1, from.y),
+        ModelFace::Up => (1, to.y),
+        ModelFace::North => (2, from.z),
+        ModelFace::South => (2, to.z),
+        ModelFace::West => (0, from.x),
+        ModelFace::East => (0, to.x),
+    };
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    [(false, false), (true, false), (true, true), (false, true)].map(|(high_u, high_v)|
+    {
+        let mut corner = Vec3::ZERO;
+        corner[axis] = value;
+        corner[u] = if high_u { to[u] } else { from[u] };
+        corner[v] = if high_v { to[v] } else { from[v] };
+        corner
+    })
+}
+
+fn rotate_around(point: Vec3, rotation: &ElementRotation) -> Vec3
+{
+    let axis = match rotation.axis
+    {
+        ModelAxis::X => Vec3::X,
+        ModelAxis::Y => Vec3::Y,
+        ModelAxis::Z => Vec3::Z,
+    };
+    let origin = Vec3::from(rotation.origin);
+    origin + Quat::from_axis_angle(axis, rotation.angle.to_radians()) * (point - origin)
+}
+
+//every block model by name and the voxels that use them. cheap to clone into meshing tasks
+#[derive(Resource, Debug, Clone)]
+pub struct BlockModels
+{
+    models: Arc<HashMap<String, BlockModel>>,
+    //models voxel types use in place of their variant's model
+    assigned: Arc<HashMap<(VoxelType, VoxelVariant), String>>,
+}
+
+//the models in Assets/models, built in so meshing works without the files
+const BUILT_IN_MODELS: [(&str, &str); 10] = [
+    ("block", include_str!("../../Assets/models/block.json")),
+    ("slab", include_str!("../../Assets/models/slab.json")),
+    ("stair", include_str!("../../Assets/models/stair.json")),
+    ("stair_inner", include_str!("../../Assets/models/stair_inner.json")),
+    ("stair_outer", include_str!("../../Assets/models/stair_outer.json")),
+    ("cross", include_str!("../../Assets/models/cross.json")),
+    ("fence", include_str!("../../Assets/models/fence.json")),
+    ("pane", include_str!("../../Assets/models/pane.json")),
+    ("pillar", include_str!("../../Assets/models/pillar.json")),
+    ("vertical_slab", include_str!("../../Assets/models/vertical_slab.json")),
+];
+
+impl Default for BlockModels
+{
+    fn default() -> Self
+    {
+        let mut models = BlockModels { models: Arc::default(), assigned: Arc::default() };
+        for (name, text) in BUILT_IN_MODELS
+        {
+            models.set(name, BlockModel::from_json(text).expect("built in block model is invalid"));
+        }
+        models
+    }
+}
+
+impl BlockModels
+{
+    //the built in models along with every .json and .ron file in the folder, named after the file
+    //a file with a built in name replaces it, files are read in name order so later ones win the voxels they both use
+    pub fn load_dir(dir: impl AsRef<Path>) -> std::io::Result<BlockModels>
+    {
+        let mut models = BlockModels::default();
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)?
+        {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|extension| extension == "json" || extension == "ron")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths
+        {
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else { continue };
+            let model = BlockModel::load(&path).map_err(|error| invalid(&format!("{}: {}", path.display(), error)))?;
+            models.set(name, model);
+        }
+        Ok(models)
+    }
+
+    //adds or replaces a model, along with the voxels it says it is used by
+    pub fn set(&mut self, name: impl Into<String>, model: BlockModel)
+    {
+        let name = name.into();
+        for key in &model.used_by
+        {
+            Arc::make_mut(&mut self.assigned).insert(*key, name.clone());
+        }
+        Arc::make_mut(&mut self.models).insert(name, model);
+    }
+
+    pub fn get(&self, name: &str) -> &BlockModel
+    {
+        static EMPTY: BlockModel = BlockModel { quads: Vec::new(), used_by: Vec::new() };
+        self.models.get(name).unwrap_or(&EMPTY)
+    }
+
+    //the name of the model a voxel is drawn with before stair corners are picked
+    pub fn model_name(&self, voxel: &Voxel) -> &str
+    {
+        self.assigned.get(&(voxel.voxel_type, voxel.voxel_variant)).map_or(voxel.voxel_variant.name(), String::as_str)
+    }
+
+    //the model a voxel is drawn with, stairs pick theirs by the corner they make
+    pub fn for_voxel(&self, voxel: &Voxel, shape: StairShape) -> &BlockModel
+    {
+        let name = self.model_name(voxel);
+        if voxel.voxel_variant == VoxelVariant::Stair && shape != StairShape::Straight
+        {
+            return self.get(&format!("{}{}", name, shape.model_suffix()));
+        }
+        self.get(name)
+    }
+}
+
+//whether a fence rail or pane arm reaches over to the neighbour
+pub fn connects(voxel: &Voxel, neighbour: &Voxel) -> bool
+{
+    neighbour.solid || neighbour.voxel_variant == voxel.voxel_variant
+}
+
+//models in Assets/models join or replace the built in ones, a broken file keeps the built in models
+pub fn configured_block_models() -> BlockModels
+{
+    BlockModels::load_dir("Assets/models").unwrap_or_else(|error|
+    {
+        warn!("could not load block models: {}, using the built in ones", error);
+        BlockModels::default()
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    //the front of a quad by its winding
+    fn quad_normal(quad: &ModelQuad) -> Vec3
+    {
+        (quad.corners[1] - quad.corners[0]).cross(quad.corners[2] - quad.corners[0]).normalize()
+    }
+
+    fn quad(model: &BlockModel, normal: Vec3) -> &ModelQuad
+    {
+        model.quads.iter().find(|quad| quad_normal(quad).abs_diff_eq(normal, 1e-5)).unwrap()
+    }
+
+    const HALF_BOX: &str = r#"{
+        "elements": [
+            {
+                "from": [0, 0, 0],
+                "to": [16, 8, 16],
+                "connect": "east",
+                "faces": {
+                    "up": { "uv": [0, 0, 8, 8] },
+                    "north": {},
+                    "east": { "cullface": "east" }
+                }
+            }
+        ],
+        "used_by": [["grass", "slab"]]
+    }"#;
+
+    #[test]
+    fn faces_wind_towards_their_side_and_only_listed_faces_are_drawn()
+    {
+        let model = BlockModel::from_json(HALF_BOX).unwrap();
+        assert_eq!(model.quads.len(), 3);
+        for (quad, face) in model.quads.iter().zip([ModelFace::Up, ModelFace::North, ModelFace::East])
+        {
+            assert!(quad_normal(quad).abs_diff_eq(face.normal().as_vec3(), 1e-5), "{face:?}");
+        }
+        assert_eq!(model.used_by, vec![(VoxelType::Grass, VoxelVariant::Slab)]);
+    }
+
+    #[test]
+    fn face_uvs_default_to_the_covered_part_or_stretch_the_given_rectangle()
+    {
+        let model = BlockModel::from_json(HALF_BOX).unwrap();
+        //the top is given the top left quarter of the texture
+        let up = quad(&model, Vec3::Y);
+        for (corner, uv) in up.corners.iter().zip(up.uvs)
+        {
+            assert_eq!(uv, Vec2::new(corner.x, corner.z) * 0.5);
+        }
+        //the side only covers the lower half of the texture
+        let north = quad(&model, Vec3::NEG_Z);
+        for (corner, uv) in north.corners.iter().zip(north.uvs)
+        {
+            assert_eq!(uv, Vec2::new(1.0 - corner.x, 1.0 - corner.y));
+        }
+        assert!(north.uvs.iter().all(|uv| uv.y >= 0.5));
+    }
+
+    #[test]
+    fn cullface_and_connect_are_kept_per_face_and_element()
+    {
+        let model = BlockModel::from_json(HALF_BOX).unwrap();
+        assert_eq!(quad(&model, Vec3::X).cullface, Some(ModelFace::East));
+        assert_eq!(quad(&model, Vec3::Y).cullface, None);
+        assert!(model.quads.iter().all(|quad| quad.connect == Some(ModelFace::East)));
+
+        //the fence post is always drawn, its rails only towards what they connect to
+        let fence = BlockModels::default().get("fence").clone();
+        let post: Vec<&ModelQuad> = fence.quads.iter().filter(|quad| quad.connect.is_none()).collect();
+        assert_eq!(post.len(), 6);
+        assert_eq!(post.iter().filter(|quad| quad.cullface.is_some()).count(), 2);
+        for face in [ModelFace::North, ModelFace::South, ModelFace::West, ModelFace::East]
+        {
+            assert_eq!(fence.quads.iter().filter(|quad| quad.connect == Some(face)).count(), 10, "{face:?}");
+        }
+    }
+
+    #[test]
+    fn rotated_elements_turn_around_their_origin()
+    {
+        //the cross planes are turned 45 degrees onto the voxel's diagonals
+        let cross = BlockModels::default().get("cross").clone();
+        assert_eq!(cross.quads.len(), 4);
+        for corner in cross.quads.iter().flat_map(|quad| quad.corners)
+        {
+            let flat = Vec2::new(corner.x - 0.5, corner.z - 0.5);
+            assert!((flat.x.abs() - flat.y.abs()).abs() < 1e-5, "{corner}");
+            assert!((flat.length() - 0.5).abs() < 1e-5, "{corner}");
+        }
+
+        let tilted = BlockModel::from_json(r#"{ "elements": [{ "from": [0, 0, 0], "to": [16, 0, 16], "rotation": { "origin": [0, 0, 0], "axis": "x", "angle": 90 }, "faces": { "up": {} } }] }"#).unwrap();
+        //the floor is turned down against the north side of the origin, facing south
+        assert!(quad_normal(&tilted.quads[0]).abs_diff_eq(Vec3::Z, 1e-5));
+        assert!(tilted.quads[0].corners.iter().all(|corner| corner.z.abs() < 1e-5 && corner.y <= 1e-5));
+    }
+
+    #[test]
+    fn ron_reads_like_json()
+    {
+        let ron = r#"(
+            elements: [(
+                from: (0.0, 0.0, 0.0),
+                to: (16.0, 8.0, 16.0),
+                connect: Some(east),
+                faces: {
+                    up: (uv: Some((0.0, 0.0, 8.0, 8.0))),
+                    north: (),
+                    east: (cullface: Some(east)),
+                },
+            )],
+            used_by: [(grass, slab)],
+        )"#;
+        assert_eq!(BlockModel::from_ron(ron).unwrap(), BlockModel::from_json(HALF_BOX).unwrap());
+        assert!(BlockModel::from_json(r#"{ "elements": [{ "from": [0, 0, 0], "to": [1, 1, 1], "faces": { "top": {} } }] }"#).is_err());
+    }
+
+    #[test]
+    fn load_dir_registers_every_model_file_by_name()
+    {
+        let dir = std::env::temp_dir().join(format!("voxel_engine_models_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tuft.json"), HALF_BOX.replace("slab", "cross")).unwrap();
+        std::fs::write(dir.join("slab.ron"), "(elements: [])").unwrap();
+        std::fs::write(dir.join("readme.txt"), "not a model").unwrap();
+        let models = BlockModels::load_dir(&dir);
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        let broken = BlockModels::load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let models = models.unwrap();
+        let tuft = BlockModel::from_json(&HALF_BOX.replace("slab", "cross")).unwrap();
+        assert_eq!(models.get("tuft"), &tuft);
+        assert!(models.get("slab").quads.is_empty());
+        assert!(!models.get("cross").quads.is_empty());
+        let grass = Voxel::new(VoxelType::Grass, VoxelVariant::Cross, VoxelRotation::Forward);
+        let stone = Voxel::new(VoxelType::Stone, VoxelVariant::Cross, VoxelRotation::Forward);
+        assert_eq!(models.for_voxel(&grass, StairShape::Straight), &tuft);
+        assert_eq!(models.for_voxel(&stone, StairShape::Straight), models.get("cross"));
+        assert!(broken.unwrap_err().to_string().contains("broken.json"));
+    }
+
+    #[test]
+    fn stairs_add_their_corner_to_the_model_name()
+    {
+        let mut models = BlockModels::default();
+        let stair = Voxel::new(VoxelType::Brick, VoxelVariant::Stair, VoxelRotation::Forward);
+        assert_eq!(models.for_voxel(&stair, StairShape::Straight), models.get("stair"));
+        assert_eq!(models.for_voxel(&stair, StairShape::InnerRight), models.get("stair_inner"));
+        assert_eq!(models.for_voxel(&stair, StairShape::OuterLeft), models.get("stair_outer"));
+        models.set("step_outer", BlockModel::from_json(HALF_BOX).unwrap());
+        models.set("step", BlockModel { quads: Vec::new(), used_by: vec![(VoxelType::Brick, VoxelVariant::Stair)] });
+        assert_eq!(models.model_name(&stair), "step");
+        assert_eq!(models.for_voxel(&stair, StairShape::OuterLeft), models.get("step_outer"));
+        assert_eq!(models.for_voxel(&stair, StairShape::InnerLeft), models.get("step_inner"));
+        assert!(models.for_voxel(&stair, StairShape::InnerLeft).quads.is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::database::edit::*;
+use crate::database::sector::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+
+//axis aligned box in voxel space, relative to a sector origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox
+{
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox
+{
+    pub fn new(min: Vec3, max: Vec3) -> BoundingBox
+    {
+        BoundingBox { min, max }
+    }
+
+    pub fn offset(&self, offset: Vec3) -> BoundingBox
+    {
+        BoundingBox { min: self.min + offset, max: self.max + offset }
+    }
+
+    //grows the box in the direction of motion so it covers the whole sweep
+    pub fn expand(&self, motion: Vec3) -> BoundingBox
+    {
+        BoundingBox { min: self.min + motion.min(Vec3::ZERO), max: self.max + motion.max(Vec3::ZERO) }
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool
+    {
+        (0..3).all(|axis| self.overlaps_on(other, axis))
+    }
+
+    fn overlaps_on(&self, other: &BoundingBox, axis: usize) -> bool
+    {
+        self.min[axis] < other.max[axis] && self.max[axis] > other.min[axis]
+    }
+
+    //shortens motion along axis so a box moving by it stops at the face of this box
+    pub fn clip(&self, moving: &BoundingBox, axis: usize, motion: f32) -> f32
+    {
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        if !self.overlaps_on(moving, a) || !self.overlaps_on(moving, b)
+        {
+            return motion;
+        }
+        if motion > 0.0 && moving.max[axis] <= self.min[axis]
+        {
+            return motion.min(self.min[axis] - moving.max[axis]);
+        }
+        if motion < 0.0 && moving.min[axis] >= self.max[axis]
+        {
+            return motion.max(self.max[axis] - moving.min[axis]);
+        }
+        motion
+    }
+}
+
+//solid parts of a voxel in its local 0..1 space. fluids and air have none. stairs are taken as straight
+pub fn collision_boxes(voxel: &Voxel) -> Vec<BoundingBox>
+{
+    shaped_collision_boxes(voxel, StairShape::Straight)
+}
+
+//collision boxes of a stair making the given corner, see stair_shape
+pub fn shaped_collision_boxes(voxel: &Voxel, shape: StairShape) -> Vec<BoundingBox>
+{
+    if matches!(voxel.voxel_type, VoxelType::Air | VoxelType::Water | VoxelType::Lava)
+    {
+        return Vec::new();
+    }
+    //matching the block models before they are oriented
+    let bottom = BoundingBox::new(Vec3::ZERO, Vec3::new(1.0, 0.5, 1.0));
+    let back = BoundingBox::new(Vec3::new(0.0, 0.5, 0.5), Vec3::ONE);
+    let model_boxes = match voxel.voxel_variant
+    {
+        VoxelVariant::Block => vec![BoundingBox::new(Vec3::ZERO, Vec3::ONE)],
+        VoxelVariant::Slab => vec![bottom],
+        VoxelVariant::VerticalSlab => vec![BoundingBox::new(Vec3::new(0.0, 0.0, 0.5), Vec3::ONE)],
+        VoxelVariant::Stair => match shape
+        {
+            StairShape::Straight => vec![bottom, back],
+            StairShape::InnerLeft | StairShape::InnerRight => vec![bottom, back, BoundingBox::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(1.0, 1.0, 0.5))],
+            StairShape::OuterLeft | StairShape::OuterRight => vec![bottom, BoundingBox::new(Vec3::new(0.5, 0.5, 0.5), Vec3::ONE)],
+        },
+        VoxelVariant::Cross => Vec::new(),
+        //just the middle post, rails and pane arms depend on the neighbours
+        VoxelVariant::Fence => vec![BoundingBox::new(Vec3::new(0.375, 0.0, 0.375), Vec3::new(0.625, 1.0, 0.625))],
+        VoxelVariant::Pane => vec![BoundingBox::new(Vec3::new(0.4375, 0.0, 0.4375), Vec3::new(0.5625, 1.0, 0.5625))],
+        VoxelVariant::Pillar => vec![BoundingBox::new(Vec3::new(0.0625, 0.0, 0.0625), Vec3::new(0.9375, 1.0, 0.9375))],
+    };
+    let orientation = voxel.orientation(shape);
+    model_boxes.into_iter().map(|bounds|
+    {
+        let (a, b) = (orientation.point(bounds.min), orientation.point(bounds.max));
+        BoundingBox::new(a.min(b), a.max(b))
+    }).collect()
+}
+
+//what a raycast can hit, the collision boxes plus a box around crosses so plants can be picked and broken
+//they are still walked through, movement only uses the collision boxes
+pub fn selection_boxes(voxel: &Voxel, shape: StairShape) -> Vec<BoundingBox>
+{
+    if voxel.voxel_variant == VoxelVariant::Cross && !matches!(voxel.voxel_type, VoxelType::Air | VoxelType::Water | VoxelType::Lava)
+    {
+        return vec![BoundingBox::new(Vec3::splat(0.2), Vec3::splat(0.8))];
+    }
+    shaped_collision_boxes(voxel, shape)
+}
+
+//every voxel collision box touching the area. voxels in chunks that are not loaded count as full blocks so nothing falls through the world before it loads
+pub fn boxes_in(world: &impl VoxelAccess, area: &BoundingBox) -> Vec<BoundingBox>
+{
+    let min = area.min.floor().as_ivec3();
+    let max = area.max.ceil().as_ivec3();
+    let mut boxes = Vec::new();
+    for x in min.x..max.x
+    {
+        for y in min.y..max.y
+        {
+            for z in min.z..max.z
+            {
+                let position = IVec3::new(x, y, z);
+                let offset = position.as_vec3();
+                match world.voxel(position)
+                {
+                    Some(voxel) => boxes.extend(shaped_collision_boxes(voxel, stair_shape(world, position, voxel)).iter().map(|bounds| bounds.offset(offset))),
+                    None => boxes.push(BoundingBox::new(offset, offset + Vec3::ONE)),
+                }
+            }
+        }
+    }
+    boxes
+}
+
+//moves a box through the voxels one axis at a time, vertical first, and returns the motion that was possible
+pub fn sweep(world: &impl VoxelAccess, bounds: &BoundingBox, motion: Vec3) -> Vec3
+{
+    let boxes = boxes_in(world, &bounds.expand(motion));
+    let mut moving = *bounds;
+    let mut result = Vec3::ZERO;
+    for axis in [1, 0, 2]
+    {
+        let mut distance = motion[axis];
+        for solid in &boxes
+        {
+            distance = solid.clip(&moving, axis, distance);
+        }
+        result[axis] = distance;
+        let mut offset = Vec3::ZERO;
+        offset[axis] = distance;
+        moving = moving.offset(offset);
+    }
+    result
+}
+
+//like sweep but a grounded box that gets blocked sideways tries stepping up onto the obstacle, used for slabs and stairs
+pub fn sweep_with_step(world: &impl VoxelAccess, bounds: &BoundingBox, motion: Vec3, step_height: f32, grounded: bool) -> Vec3
+{
+    let moved = sweep(world, bounds, motion);
+    let blocked = moved.x != motion.x || moved.z != motion.z;
+    if !grounded || !blocked || step_height <= 0.0
+    {
+        return moved;
+    }
+    //up by the step height, across, then back down onto whatever is there
+    let up = sweep(world, bounds, Vec3::new(0.0, step_height, 0.0));
+    let raised = bounds.offset(up);
+    let across = sweep(world, &raised, Vec3::new(motion.x, 0.0, motion.z));
+    let moved_across = raised.offset(across);
+    let down = sweep(world, &moved_across, Vec3::new(0.0, -up.y + motion.y.min(0.0), 0.0));
+    let stepped = up + across + down;
+    let stepped_further = stepped.x * stepped.x + stepped.z * stepped.z > moved.x * moved.x + moved.z * moved.z;
+    if stepped_further { stepped } else { moved }
+}
+
+//first person walking with gravity against the loaded voxels. the transform translation is the bottom center of the box
+#[derive(Component, Debug, Clone)]
+pub struct CharacterController
+{
+    pub sector: [i32; 3],
+    pub half_extents: Vec3,
+    pub velocity: Vec3,
+    pub walk_speed: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+    //highest ledge that is walked up without jumping
+    pub step_height: f32,
+    pub grounded: bool,
+}
+
+impl Default for CharacterController
+{
+    fn default() -> Self
+    {
+        CharacterController
+        {
+            sector: [0, 0, 0],
+            half_extents: Vec3::new(0.3, 0.9, 0.3),
+            velocity: Vec3::ZERO,
+            walk_speed: 5.0,
+            jump_speed: 8.0,
+            gravity: 25.0,
+            step_height: 0.6,
+            grounded: false,
+        }
+    }
+}
+
+//what a character wants to do this frame. filled by any input source, keyboard, AI or tests
+#[derive(Component, Debug, Clone, Default)]
+pub struct CharacterInput
+{
+    //horizontal direction in world space, y is ignored. length above 1 is clamped
+    pub movement: Vec3,
+    pub jump: bool,
+}
+
+impl CharacterController
+{
+    pub fn bounds(&self, translation: Vec3) -> BoundingBox
+    {
+        let feet = translation - Vec3::new(self.sector[0] as f32, self.sector[1] as f32, self.sector[2] as f32);
+        BoundingBox::new(feet - Vec3::new(self.half_extents.x, 0.0, self.half_extents.z), feet + Vec3::new(self.half_extents.x, self.half_extents.y * 2.0, self.half_extents.z))
+    }
+
+    //advances the character by delta seconds and returns how far it moved
+    pub fn step(&mut self, world: &impl VoxelAccess, translation: Vec3, input: &CharacterInput, delta: f32) -> Vec3
+    {
+        let movement = Vec3::new(input.movement.x, 0.0, input.movement.z).clamp_length_max(1.0) * self.walk_speed;
+        self.velocity.x = movement.x;
+        self.velocity.z = movement.z;
+        if self.grounded && input.jump
+        {
+            self.velocity.y = self.jump_speed;
+        }
+        self.velocity.y -= self.gravity * delta;
+        let motion = self.velocity * delta;
+        let moved = sweep_with_step(world, &self.bounds(translation), motion, self.step_height, self.grounded);
+        //ground detection, falling motion was cut short by something below
+        self.grounded = motion.y < 0.0 && moved.y > motion.y;
+        if moved.y != motion.y
+        {
+            self.velocity.y = 0.0;
+        }
+        moved
+    }
+}
+
+pub fn character_controller
+(
+    time: Res<Time>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    mut characters: Query<(&mut Transform, &mut CharacterController, &CharacterInput)>,
+)
+{
+    //large frame spikes would let characters tunnel, so very long frames are capped
+    let delta = time.delta_seconds().min(0.05);
+    for (mut transform, mut controller, input) in &mut characters
+    {
+        //characters wait in place until their sector is loaded
+        let Some(sector) = loaded_sectors.sectors.get(&controller.sector) else { continue };
+        let moved = controller.step(sector, transform.translation, input, delta);
+        transform.translation += moved;
+    }
+}
+
+//wasd and space relative to where the character is facing
+pub fn keyboard_character_input
+(
+    keys: Res<Input<KeyCode>>,
+    mut characters: Query<(&Transform, &mut CharacterInput)>,
+)
+{
+    for (transform, mut input) in &mut characters
+    {
+        let forward = Vec3::new(transform.forward().x, 0.0, transform.forward().z).normalize_or_zero();
+        let right = Vec3::new(transform.right().x, 0.0, transform.right().z).normalize_or_zero();
+        let mut movement = Vec3::ZERO;
+        if keys.pressed(KeyCode::W) { movement += forward; }
+        if keys.pressed(KeyCode::S) { movement -= forward; }
+        if keys.pressed(KeyCode::D) { movement += right; }
+        if keys.pressed(KeyCode::A) { movement -= right; }
+        input.movement = movement;
+        input.jump = keys.pressed(KeyCode::Space);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::chunk::{generate_chunk, Chunk};
+    use crate::database::edit::test_world;
+    use crate::database::settings::CHUNK_SIZE;
+    use std::collections::HashMap;
+
+    const DELTA: f32 = 1.0 / 60.0;
+
+    fn stone(variant: VoxelVariant, rotation: VoxelRotation) -> Voxel
+    {
+        Voxel::new(VoxelType::Stone, variant, rotation)
+    }
+
+    //a stone floor with its top at y 0 under x and z 0..16, plus the given voxels above it
+    fn floor_world(voxels: &[(IVec3, Voxel)]) -> HashMap<[i32; 3], Chunk>
+    {
+        let mut all: Vec<(IVec3, Voxel)> = (0..16).flat_map(|x| (0..16).map(move |z| (IVec3::new(x, -1, z), stone(VoxelVariant::Block, VoxelRotation::Forward)))).collect();
+        all.extend_from_slice(voxels);
+        test_world([-1, -1, -1], [1, 1, 1], &all)
+    }
+
+    //runs the controller for a number of frames and returns where its feet end up
+    fn walk(world: &HashMap<[i32; 3], Chunk>, controller: &mut CharacterController, start: Vec3, movement: Vec3, frames: usize) -> Vec3
+    {
+        let input = CharacterInput { movement, jump: false };
+        let mut translation = start;
+        for _ in 0..frames
+        {
+            translation += controller.step(world, translation, &input, DELTA);
+        }
+        translation
+    }
+
+    fn grounded_controller() -> CharacterController
+    {
+        CharacterController { grounded: true, ..default() }
+    }
+
+    #[test]
+    fn lands_on_the_floor_and_is_grounded()
+    {
+        let world = floor_world(&[]);
+        let mut controller = CharacterController::default();
+        let feet = walk(&world, &mut controller, Vec3::new(4.5, 3.0, 4.5), Vec3::ZERO, 120);
+        assert!((feet.y - 0.0).abs() < 1e-4, "feet at {}", feet.y);
+        assert!(controller.grounded);
+        assert_eq!(controller.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn falls_when_walking_off_an_edge()
+    {
+        let world = floor_world(&[]);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(14.5, 0.0, 4.5), Vec3::X, 60);
+        assert!(feet.x > 16.3, "feet at {}", feet.x);
+        assert!(feet.y < -0.5, "feet at {}", feet.y);
+        assert!(!controller.grounded);
+    }
+
+    #[test]
+    fn walking_into_a_wall_stops_at_its_face()
+    {
+        let wall: Vec<(IVec3, Voxel)> = (0..3).flat_map(|y| (0..16).map(move |z| (IVec3::new(6, y, z), stone(VoxelVariant::Block, VoxelRotation::Forward)))).collect();
+        let world = floor_world(&wall);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(2.5, 0.0, 4.5), Vec3::X, 120);
+        assert!((feet.x - (6.0 - controller.half_extents.x)).abs() < 1e-4, "feet at {}", feet.x);
+        assert_eq!(feet.y, 0.0);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn a_full_block_is_too_high_to_step_onto()
+    {
+        let world = floor_world(&[(IVec3::new(6, 0, 4), stone(VoxelVariant::Block, VoxelRotation::Forward))]);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(2.5, 0.0, 4.5), Vec3::X, 120);
+        assert!((feet.x - (6.0 - controller.half_extents.x)).abs() < 1e-4, "feet at {}", feet.x);
+        assert_eq!(feet.y, 0.0);
+    }
+
+    #[test]
+    fn steps_up_onto_a_slab()
+    {
+        let world = floor_world(&[(IVec3::new(6, 0, 4), stone(VoxelVariant::Slab, VoxelRotation::Forward))]);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(2.5, 0.0, 4.5), Vec3::X, 50);
+        assert!(feet.x > 6.3 && feet.x < 6.7, "feet at {}", feet.x);
+        assert!((feet.y - 0.5).abs() < 1e-4, "feet at {}", feet.y);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn climbs_a_stair_one_half_at_a_time()
+    {
+        //rising towards +x onto a platform, the walk comes from its low side
+        let rising = VoxelRotation::from_direction(IVec3::X).unwrap();
+        let mut voxels: Vec<(IVec3, Voxel)> = (7..14).map(|x| (IVec3::new(x, 0, 4), stone(VoxelVariant::Block, VoxelRotation::Forward))).collect();
+        voxels.push((IVec3::new(6, 0, 4), stone(VoxelVariant::Stair, rising)));
+        let world = floor_world(&voxels);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(2.5, 0.0, 4.5), Vec3::X, 90);
+        assert!(feet.x > 7.3, "feet at {}", feet.x);
+        assert!((feet.y - 1.0).abs() < 1e-4, "feet at {}", feet.y);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn cannot_walk_into_the_high_side_of_a_stair()
+    {
+        //rising towards -x, the walk runs into its full height back
+        let rising = VoxelRotation::from_direction(IVec3::NEG_X).unwrap();
+        let world = floor_world(&[(IVec3::new(6, 0, 4), stone(VoxelVariant::Stair, rising))]);
+        let mut controller = grounded_controller();
+        let feet = walk(&world, &mut controller, Vec3::new(2.5, 0.0, 4.5), Vec3::X, 120);
+        assert!((feet.x - (6.0 - controller.half_extents.x)).abs() < 1e-4, "feet at {}", feet.x);
+        assert_eq!(feet.y, 0.0);
+    }
+
+    #[test]
+    fn lands_on_generated_terrain()
+    {
+        let world: HashMap<[i32; 3], Chunk> = (-1..=1).flat_map(|x| (0..8).flat_map(move |y| (-1..=1).map(move |z| [x, y, z])))
+            .map(|chunk_id| (chunk_id, Chunk { block_data: generate_chunk(chunk_id), pos: (chunk_id[0] * CHUNK_SIZE, chunk_id[1] * CHUNK_SIZE, chunk_id[2] * CHUNK_SIZE) }))
+            .collect();
+        let mut controller = CharacterController::default();
+        let start = Vec3::new(16.5, 8.0 * CHUNK_SIZE as f32 - 2.0, 16.5);
+        let mut translation = start;
+        for _ in 0..2000
+        {
+            translation += controller.step(&world, translation, &CharacterInput::default(), DELTA);
+            if controller.grounded
+            {
+                break;
+            }
+        }
+        assert!(controller.grounded, "still falling at {}", translation);
+        //resting on top of something solid, with nothing solid inside the character
+        let bounds = controller.bounds(translation);
+        assert!(boxes_in(&world, &bounds).iter().all(|solid| !solid.intersects(&bounds)));
+        assert!(boxes_in(&world, &bounds.offset(Vec3::new(0.0, -0.01, 0.0))).iter().any(|solid| solid.intersects(&bounds.offset(Vec3::new(0.0, -0.01, 0.0)))));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::database::edit::*;
+use crate::database::physics::*;
+use crate::database::voxel::stair_shape;
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaycastHit
+{
+    //voxel that was hit, relative to the sector origin
+    pub position: IVec3,
+    //face of the voxel the ray entered through, zero if the ray started inside it
+    pub normal: IVec3,
+    pub distance: f32,
+}
+
+//distance along the ray to where it enters the box, None if it misses
+pub fn ray_box(origin: Vec3, direction: Vec3, bounds: &BoundingBox) -> Option<f32>
+{
+    let inverse = direction.recip();
+    let a = (bounds.min - origin) * inverse;
+    let b = (bounds.max - origin) * inverse;
+    let near = a.min(b).max_element();
+    let far = a.max(b).min_element();
+    (far >= near.max(0.0)).then_some(near.max(0.0))
+}
+
+//walks the voxel grid along the ray and returns the first voxel with selection geometry the ray touches
+//slabs and stairs are only hit where they have geometry, fluids and unloaded chunks are passed through
+pub fn raycast(world: &impl VoxelAccess, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit>
+{
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO
+    {
+        return None;
+    }
+    let mut position = origin.floor().as_ivec3();
+    let step = direction.signum().as_ivec3();
+    let delta = direction.recip().abs();
+    //distance along the ray to the next grid line on each axis
+    let mut next = Vec3::ZERO;
+    for axis in 0..3
+    {
+        let boundary = if step[axis] > 0 { position[axis] as f32 + 1.0 } else { position[axis] as f32 };
+        next[axis] = if direction[axis] == 0.0 { f32::INFINITY } else { (boundary - origin[axis]) / direction[axis] };
+    }
+    let mut normal = IVec3::ZERO;
+    let mut travelled = 0.0;
+    while travelled <= max_distance
+    {
+        if let Some(voxel) = world.voxel(position)
+        {
+            let offset = position.as_vec3();
+            let hit = selection_boxes(voxel, stair_shape(world, position, voxel)).iter().filter_map(|bounds| ray_box(origin, direction, &bounds.offset(offset))).reduce(f32::min);
+            if let Some(distance) = hit.filter(|distance| *distance <= max_distance)
+            {
+                return Some(RaycastHit { position, normal, distance });
+            }
+        }
+        let axis = if next.x < next.y && next.x < next.z { 0 } else if next.y < next.z { 1 } else { 2 };
+        travelled = next[axis];
+        next[axis] += delta[axis];
+        position[axis] += step[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+    use crate::database::voxel::*;
+
+    fn stone(voxel_variant: VoxelVariant) -> Voxel
+    {
+        Voxel::new(VoxelType::Stone, voxel_variant, VoxelRotation::Forward)
+    }
+
+    #[test]
+    fn hits_the_first_voxel_with_the_face_it_entered()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(5, 5, 5), stone(VoxelVariant::Block))]);
+        let center = Vec3::splat(5.5);
+        for normal in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z]
+        {
+            let origin = center + normal.as_vec3() * 3.0;
+            let hit = raycast(&world, origin, -normal.as_vec3(), 8.0).unwrap();
+            assert_eq!(hit, RaycastHit { position: IVec3::splat(5), normal, distance: 2.5 });
+        }
+    }
+
+    #[test]
+    fn diagonal_rays_report_the_face_they_crossed()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(5, 5, 5), stone(VoxelVariant::Block))]);
+        //enters through the top, the ray drops two voxels while moving one sideways
+        let hit = raycast(&world, Vec3::new(4.7, 7.5, 5.5), Vec3::new(0.5, -1.0, 0.0), 8.0).unwrap();
+        assert_eq!((hit.position, hit.normal), (IVec3::splat(5), IVec3::Y));
+        let hit = raycast(&world, Vec3::new(3.5, 6.2, 5.5), Vec3::new(1.0, -0.5, 0.0), 8.0).unwrap();
+        assert_eq!((hit.position, hit.normal), (IVec3::splat(5), IVec3::NEG_X));
+    }
+
+    #[test]
+    fn stops_at_max_distance()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(5, 5, 5), stone(VoxelVariant::Block))]);
+        assert_eq!(raycast(&world, Vec3::new(5.5, 9.5, 5.5), Vec3::NEG_Y, 3.0), None);
+        assert!(raycast(&world, Vec3::new(5.5, 9.5, 5.5), Vec3::NEG_Y, 3.5).is_some());
+        assert_eq!(raycast(&world, Vec3::new(5.5, 9.5, 5.5), Vec3::ZERO, 8.0), None);
+    }
+
+    #[test]
+    fn starting_inside_a_voxel_hits_it_with_no_normal()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(5, 5, 5), stone(VoxelVariant::Block))]);
+        assert_eq!(raycast(&world, Vec3::splat(5.5), Vec3::X, 8.0), Some(RaycastHit { position: IVec3::splat(5), normal: IVec3::ZERO, distance: 0.0 }));
+    }
+
+    #[test]
+    fn passes_through_fluids_and_over_slabs()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[
+            (IVec3::new(3, 5, 5), Voxel::new(VoxelType::Water, VoxelVariant::Block, VoxelRotation::Forward)),
+            (IVec3::new(4, 5, 5), stone(VoxelVariant::Slab)),
+            (IVec3::new(6, 5, 5), stone(VoxelVariant::Block)),
+        ]);
+        //above the slab's bottom half
+        let hit = raycast(&world, Vec3::new(0.5, 5.75, 5.5), Vec3::X, 10.0).unwrap();
+        assert_eq!((hit.position, hit.normal), (IVec3::new(6, 5, 5), IVec3::NEG_X));
+        let hit = raycast(&world, Vec3::new(0.5, 5.25, 5.5), Vec3::X, 10.0).unwrap();
+        assert_eq!((hit.position, hit.distance), (IVec3::new(4, 5, 5), 3.5));
+    }
+
+    #[test]
+    fn plants_are_hit_inside_their_selection_box_only()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[
+            (IVec3::new(4, 5, 5), stone(VoxelVariant::Cross)),
+            (IVec3::new(6, 5, 5), stone(VoxelVariant::Block)),
+        ]);
+        let hit = raycast(&world, Vec3::new(0.5, 5.5, 5.5), Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.position, IVec3::new(4, 5, 5));
+        assert!((hit.distance - 3.7).abs() < 1e-5);
+        //passes by the plant's leaves
+        let hit = raycast(&world, Vec3::new(0.5, 5.1, 5.5), Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.position, IVec3::new(6, 5, 5));
+        //still walked through
+        assert!(boxes_in(&world, &BoundingBox::new(Vec3::new(4.0, 5.0, 5.0), Vec3::new(5.0, 6.0, 6.0))).is_empty());
+    }
+
+    #[test]
+    fn crosses_chunk_borders_and_skips_unloaded_chunks()
+    {
+        //chunk 1 is loaded, chunk 0 on the way is not
+        let world = test_world([1, 0, 0], [1, 0, 0], &[(IVec3::new(33, 5, 5), stone(VoxelVariant::Block))]);
+        let hit = raycast(&world, Vec3::new(28.5, 5.5, 5.5), Vec3::X, 8.0).unwrap();
+        assert_eq!((hit.position, hit.normal, hit.distance), (IVec3::new(33, 5, 5), IVec3::NEG_X, 4.5));
+    }
+}
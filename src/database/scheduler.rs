@@ -0,0 +1,342 @@
+use crate::database::biome::Climate;
+use crate::database::chunk::{voxel_index, Chunk};
+use crate::database::edit::*;
+use crate::database::events::*;
+use crate::database::sector::*;
+use crate::database::settings::*;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+//positions whose neighbourhood changed during the last voxel edits, rebuilt every frame
+//block behaviours like falling blocks and fluids read this instead of listening to VoxelChanged themselves
+#[derive(Default, Resource)]
+pub struct BlockUpdates
+{
+    //positions relative to their sector origin
+    pub positions: HashSet<([i32; 3], IVec3)>,
+}
+
+impl BlockUpdates
+{
+    //queues a position and its six neighbours
+    pub fn schedule(&mut self, sector: [i32; 3], position: IVec3)
+    {
+        for offset in [IVec3::ZERO, IVec3::Y, IVec3::NEG_Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z]
+        {
+            self.positions.insert((sector, position + offset));
+        }
+    }
+}
+
+//runs after apply_voxel_edits so every change made this frame is seen once
+pub fn collect_block_updates
+(
+    mut block_updates: ResMut<BlockUpdates>,
+    mut voxel_changed: EventReader<VoxelChanged>,
+)
+{
+    block_updates.positions.clear();
+    for change in voxel_changed.read()
+    {
+        let position = IVec3::from(change.chunk) * CHUNK_SIZE + IVec3::from(change.voxel);
+        block_updates.schedule(change.sector, position);
+    }
+}
+
+//everything a random tick behaviour can look at. positions are relative to the sector origin
+pub struct RandomTickContext<'a>
+{
+    pub world: &'a SectorsRendering,
+    pub climate: &'a Climate,
+    pub sector: [i32; 3],
+    pub position: IVec3,
+    pub voxel: &'a Voxel,
+}
+
+impl RandomTickContext<'_>
+{
+    pub fn world_position(&self) -> IVec3
+    {
+        IVec3::from(self.sector) + self.position
+    }
+
+    pub fn neighbour(&self, offset: IVec3) -> Option<&Voxel>
+    {
+        self.world.voxel(self.position + offset)
+    }
+}
+
+//returns the offset from the ticked voxel of the voxel to change and what it becomes, or None to leave everything alone
+pub type RandomTickBehaviour = fn(&RandomTickContext, &mut StdRng) -> Option<(IVec3, Voxel)>;
+
+//picks a few random voxels in every loaded chunk each tick and runs the behaviours registered for their type
+#[derive(Resource)]
+pub struct RandomTicks
+{
+    //voxels picked per chunk per tick
+    pub speed: u32,
+    //seconds between ticks
+    pub interval: f32,
+    pub climate: Climate,
+    behaviours: HashMap<VoxelType, Vec<RandomTickBehaviour>>,
+    rng: StdRng,
+    timer: f32,
+}
+
+impl Default for RandomTicks
+{
+    fn default() -> Self
+    {
+        let mut random_ticks = RandomTicks
+        {
+            speed: RANDOM_TICK_SPEED,
+            interval: RANDOM_TICK_INTERVAL,
+            climate: Climate::default(),
+            behaviours: HashMap::new(),
+            rng: StdRng::seed_from_u64(RANDOM_TICK_SEED),
+            timer: 0.0,
+        };
+        random_ticks.register(VoxelType::Dirt, spread_grass);
+        random_ticks.register(VoxelType::Ice, melt_ice);
+        //snow settles onto the ground rather than being picked out of the air, and never onto snow or ice
+        for voxel_type in VoxelType::ALL.into_iter().filter(|voxel_type| !matches!(voxel_type, VoxelType::Air | VoxelType::Water | VoxelType::Lava | VoxelType::Snow | VoxelType::SnowBlock | VoxelType::Ice | VoxelType::Glass))
+        {
+            random_ticks.register(voxel_type, settle_snow);
+        }
+        random_ticks
+    }
+}
+
+impl RandomTicks
+{
+    //adds a behaviour for a voxel type, every behaviour for the type runs until one returns a change
+    pub fn register(&mut self, voxel_type: VoxelType, behaviour: RandomTickBehaviour)
+    {
+        self.behaviours.entry(voxel_type).or_default().push(behaviour);
+    }
+
+    //picks the voxels for one tick and returns what their behaviours change them to
+    //every change is worked out from the same snapshot before any of them are applied
+    //chunks and sectors are visited in sorted order so a fixed seed always picks the same voxels
+    pub fn tick(&mut self, loaded_sectors: &CurrentlyLoaded) -> Vec<SetVoxel>
+    {
+        let mut sectors: Vec<(&[i32; 3], &SectorsRendering)> = loaded_sectors.sectors.iter().collect();
+        sectors.sort_by_key(|(sector, _)| **sector);
+        let mut changes = Vec::new();
+        for (sector, loaded_sector) in sectors
+        {
+            let mut chunks: Vec<(&[i32; 3], &Arc<Chunk>)> = loaded_sector.chunks.iter().filter_map(|(chunk_id, chunk)| chunk.as_ref().map(|chunk| (chunk_id, chunk))).collect();
+            chunks.sort_by_key(|(chunk_id, _)| **chunk_id);
+            for (chunk_id, chunk) in chunks
+            {
+                for _ in 0..self.speed
+                {
+                    let local = IVec3::new(self.rng.gen_range(0..CHUNK_SIZE), self.rng.gen_range(0..CHUNK_SIZE), self.rng.gen_range(0..CHUNK_SIZE));
+                    let voxel = &chunk.block_data[voxel_index(local.x, local.y, local.z)];
+                    let Some(behaviours) = self.behaviours.get(&voxel.voxel_type) else { continue };
+                    let context = RandomTickContext
+                    {
+                        world: loaded_sector,
+                        climate: &self.climate,
+                        sector: *sector,
+                        position: IVec3::from(*chunk_id) * CHUNK_SIZE + local,
+                        voxel,
+                    };
+                    if let Some((offset, new)) = behaviours.iter().find_map(|behaviour| behaviour(&context, &mut self.rng))
+                    {
+                        changes.push(SetVoxel { sector: *sector, position: context.position + offset, voxel: new, undoable: false });
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    //restarts the random sequence, ticks after this are the same for the same seed and loaded chunks
+    pub fn reseed(&mut self, seed: u64)
+    {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+}
+
+const NEIGHBOURS: [IVec3; 6] = [IVec3::Y, IVec3::NEG_Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+//light passes through air and glass
+fn transparent(voxel: &Voxel) -> bool
+{
+    matches!(voxel.voxel_type, VoxelType::Air | VoxelType::Glass)
+}
+
+fn is_grass(voxel_type: VoxelType) -> bool
+{
+    matches!(voxel_type, VoxelType::Grass | VoxelType::JungleGrass | VoxelType::SavannahGrass | VoxelType::SwampGrass)
+}
+
+//nothing but transparent voxels between the position and the top of the loaded chunks
+pub fn sees_sky(world: &SectorsRendering, position: IVec3) -> bool
+{
+    let mut above = position + IVec3::Y;
+    while let Some(voxel) = world.voxel(above)
+    {
+        if !transparent(voxel)
+        {
+            return false;
+        }
+        above += IVec3::Y;
+    }
+    true
+}
+
+//dirt touching grass turns into that grass when it can see the sky
+fn spread_grass(context: &RandomTickContext, rng: &mut StdRng) -> Option<(IVec3, Voxel)>
+{
+    if !sees_sky(context.world, context.position)
+    {
+        return None;
+    }
+    let grass: Vec<VoxelType> = (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+        .filter_map(|offset| context.neighbour(offset))
+        .map(|voxel| voxel.voxel_type)
+        .filter(|voxel_type| is_grass(*voxel_type))
+        .collect();
+    if grass.is_empty()
+    {
+        return None;
+    }
+    let mut voxel = context.voxel.clone();
+    voxel.voxel_type = grass[rng.gen_range(0..grass.len())];
+    Some((IVec3::ZERO, voxel))
+}
+
+//ice next to lava melts into a water source
+fn melt_ice(context: &RandomTickContext, _rng: &mut StdRng) -> Option<(IVec3, Voxel)>
+{
+    let near_lava = NEIGHBOURS.iter().any(|offset| context.neighbour(*offset).is_some_and(|voxel| voxel.voxel_type == VoxelType::Lava));
+    near_lava.then(|| (IVec3::ZERO, Voxel::new(VoxelType::Water, VoxelVariant::Block, VoxelRotation::default())))
+}
+
+//snow builds up in the air above open solid ground in cold places. registered on the ground types, so a layer of
+//snow stops further snow and the sky is only looked for above ground that has air on top
+fn settle_snow(context: &RandomTickContext, _rng: &mut StdRng) -> Option<(IVec3, Voxel)>
+{
+    let open = context.voxel.solid && context.neighbour(IVec3::Y).is_some_and(|above| above.voxel_type == VoxelType::Air);
+    if !open || !context.climate.is_cold(context.world_position() + IVec3::Y) || !sees_sky(context.world, context.position)
+    {
+        return None;
+    }
+    Some((IVec3::Y, Voxel::new(VoxelType::Snow, VoxelVariant::Block, VoxelRotation::default())))
+}
+
+pub fn random_tick
+(
+    time: Res<Time>,
+    mut random_ticks: ResMut<RandomTicks>,
+    loaded_sectors: Res<CurrentlyLoaded>,
+    mut edits: EventWriter<SetVoxel>,
+)
+{
+    random_ticks.timer += time.delta_seconds();
+    if random_ticks.timer < random_ticks.interval
+    {
+        return;
+    }
+    let interval = random_ticks.interval;
+    random_ticks.timer -= interval;
+    for change in random_ticks.tick(&loaded_sectors)
+    {
+        edits.send(change);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+
+    //dirt around a patch of grass and ice around lava, all under open sky
+    fn loaded_world() -> CurrentlyLoaded
+    {
+        let voxel = |voxel_type| Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::Forward);
+        let mut voxels: Vec<(IVec3, Voxel)> = (0..CHUNK_SIZE * 2).flat_map(|x| (0..CHUNK_SIZE).map(move |z| (IVec3::new(x, 10, z), voxel(VoxelType::Dirt)))).collect();
+        voxels.extend([(IVec3::new(8, 10, 8), voxel(VoxelType::Grass)), (IVec3::new(40, 10, 8), voxel(VoxelType::JungleGrass))]);
+        voxels.extend((0..CHUNK_SIZE).flat_map(|x| (0..CHUNK_SIZE).map(move |z| (IVec3::new(x, 5, z), voxel(if (x + z) % 2 == 0 { VoxelType::Lava } else { VoxelType::Ice })))));
+        let chunks = test_world([0, 0, 0], [1, 0, 0], &voxels).into_iter().map(|(chunk_id, chunk)| (chunk_id, Some(Arc::new(chunk)))).collect();
+        let mut loaded = CurrentlyLoaded::default();
+        loaded.sectors.insert([0, 0, 0], SectorsRendering { chunks });
+        loaded
+    }
+
+    //every change of a number of ticks, each tick applied before the next so the world evolves like it does in game
+    fn run_ticks(seed: u64, ticks: usize) -> Vec<([i32; 3], IVec3, Voxel)>
+    {
+        let mut loaded = loaded_world();
+        let mut random_ticks = RandomTicks { speed: 512, ..default() };
+        random_ticks.reseed(seed);
+        let mut history = Vec::new();
+        for _ in 0..ticks
+        {
+            for change in random_ticks.tick(&loaded)
+            {
+                let (chunk, local) = voxel_to_chunk(change.position);
+                let chunk = loaded.sectors.get_mut(&change.sector).unwrap().chunks.get_mut(&chunk).unwrap().as_mut().unwrap();
+                Arc::make_mut(chunk).block_data[voxel_index(local[0], local[1], local[2])] = change.voxel.clone();
+                history.push((change.sector, change.position, change.voxel));
+            }
+        }
+        history
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_changes()
+    {
+        let first = run_ticks(7, 40);
+        let second = run_ticks(7, 40);
+        assert!(first.iter().any(|(_, _, voxel)| voxel.voxel_type == VoxelType::Water));
+        assert!(first.iter().any(|(_, _, voxel)| voxel.voxel_type == VoxelType::JungleGrass));
+        assert_eq!(first, second);
+    }
+
+    //cold high ground at y 260 with the given voxels, the context ticks the voxel at position
+    fn snow_on(ground: VoxelType, roof: bool) -> Option<(IVec3, Voxel)>
+    {
+        let position = IVec3::new(8, 260, 8);
+        let mut voxels = vec![(position, Voxel::new(ground, VoxelVariant::Block, VoxelRotation::Forward))];
+        if roof
+        {
+            voxels.push((position + IVec3::new(0, 10, 0), Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::Forward)));
+        }
+        let chunks = test_world([0, 8, 0], [0, 8, 0], &voxels).into_iter().map(|(chunk_id, chunk)| (chunk_id, Some(Arc::new(chunk)))).collect();
+        let world = SectorsRendering { chunks };
+        let climate = Climate::default();
+        assert!(climate.is_cold(position + IVec3::Y));
+        let random_ticks = RandomTicks::default();
+        let context = RandomTickContext { world: &world, climate: &climate, sector: [0, 0, 0], position, voxel: world.voxel(position).unwrap() };
+        let behaviours = random_ticks.behaviours.get(&ground)?;
+        behaviours.iter().find_map(|behaviour| behaviour(&context, &mut StdRng::seed_from_u64(0)))
+    }
+
+    #[test]
+    fn snow_settles_on_open_ground_only()
+    {
+        let (offset, snow) = snow_on(VoxelType::Stone, false).unwrap();
+        assert_eq!((offset, snow.voxel_type), (IVec3::Y, VoxelType::Snow));
+        assert_eq!(snow_on(VoxelType::Stone, true), None);
+        //snow does not stack into pillars
+        for ground in [VoxelType::Snow, VoxelType::SnowBlock, VoxelType::Ice, VoxelType::Glass]
+        {
+            assert_eq!(snow_on(ground, false), None);
+        }
+    }
+
+    #[test]
+    fn a_different_seed_gives_different_changes()
+    {
+        assert_ne!(run_ticks(7, 40), run_ticks(8, 40));
+    }
+}
@@ -0,0 +1,440 @@
+use crate::database::chunk::voxel_index;
+use crate::database::edit::*;
+use crate::database::settings::CHUNK_SIZE;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"VXSC";
+const VERSION: u8 = 1;
+//most voxels a schematic read from a file may have, 256 on every side
+pub const MAX_SCHEMATIC_VOLUME: usize = 256 * 256 * 256;
+
+//a copied box of voxels that can be pasted elsewhere, rotated and mirrored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schematic
+{
+    pub size: IVec3,
+    //x major like chunk data, see index
+    pub voxels: Vec<Voxel>,
+}
+
+//how a schematic is turned before it is pasted. mirroring happens before rotating
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchematicTransform
+{
+    //quarter turns around the y axis, forward(+z) turns to right(-x)
+    pub quarter_turns: u8,
+    pub mirror_x: bool,
+}
+
+fn rotate_flow(flow: FlowDirection) -> FlowDirection
+{
+    match flow
+    {
+        FlowDirection::PosZ => FlowDirection::NegX,
+        FlowDirection::NegX => FlowDirection::NegZ,
+        FlowDirection::NegZ => FlowDirection::PosX,
+        FlowDirection::PosX => FlowDirection::PosZ,
+        other => other,
+    }
+}
+
+fn mirror_flow(flow: FlowDirection) -> FlowDirection
+{
+    match flow
+    {
+        FlowDirection::PosX => FlowDirection::NegX,
+        FlowDirection::NegX => FlowDirection::PosX,
+        other => other,
+    }
+}
+
+pub fn invalid(message: &str) -> Error
+{
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+//number of voxels in a box of the given size. sizes come from files, so negative or huge ones are errors instead of
+//overflowing or asking for more memory than there is
+pub fn checked_volume(size: IVec3) -> std::io::Result<usize>
+{
+    let mut volume: usize = 1;
+    for axis in size.to_array()
+    {
+        let axis = usize::try_from(axis).map_err(|_| invalid("negative schematic size"))?;
+        volume = volume.checked_mul(axis).filter(|volume| *volume <= MAX_SCHEMATIC_VOLUME).ok_or_else(|| invalid("schematic is too big"))?;
+    }
+    Ok(volume)
+}
+
+//bytes one voxel takes in schematic and octree files
+pub const VOXEL_BYTES: usize = 6;
+
+//type, variant, rotation, fluid level, flow direction, then solid, fluid source and top half as bit flags
+pub fn encode_voxel(voxel: &Voxel) -> [u8; VOXEL_BYTES]
+{
+    [
+        voxel.voxel_type as u8,
+        voxel.voxel_variant as u8,
+        voxel.voxel_rotation as u8,
+        voxel.fluid.level,
+        voxel.fluid.flow as u8,
+        voxel.solid as u8 | (voxel.fluid.source as u8) << 1 | ((voxel.voxel_half == VoxelHalf::Top) as u8) << 2,
+    ]
+}
+
+pub fn decode_voxel(entry: &[u8]) -> std::io::Result<Voxel>
+{
+    let [voxel_type, voxel_variant, voxel_rotation, level, flow, flags] = entry else { return Err(invalid("voxel entry has the wrong length")) };
+    Ok(Voxel
+    {
+        voxel_type: *VoxelType::ALL.get(*voxel_type as usize).ok_or_else(|| invalid("unknown voxel type"))?,
+        solid: flags & 1 != 0,
+        voxel_variant: *VoxelVariant::ALL.get(*voxel_variant as usize).ok_or_else(|| invalid("unknown voxel variant"))?,
+        voxel_rotation: *VoxelRotation::ALL.get(*voxel_rotation as usize).ok_or_else(|| invalid("unknown voxel rotation"))?,
+        fluid: FluidState { level: *level, source: flags & 2 != 0, flow: *FlowDirection::ALL.get(*flow as usize).ok_or_else(|| invalid("unknown flow direction"))? },
+        voxel_half: if flags & 4 != 0 { VoxelHalf::Top } else { VoxelHalf::Bottom },
+    })
+}
+
+impl Schematic
+{
+    //only for sizes known to fit, like the size of a schematic that already exists. sizes read from files or picked
+    //by players go through with_size
+    pub fn new(size: IVec3) -> Schematic
+    {
+        Schematic::with_size(size).expect("schematic size has to fit")
+    }
+
+    //an air filled schematic, an error if the size is negative or bigger than MAX_SCHEMATIC_VOLUME
+    pub fn with_size(size: IVec3) -> std::io::Result<Schematic>
+    {
+        Ok(Schematic { size, voxels: vec![Voxel::default(); checked_volume(size)?] })
+    }
+
+    pub fn index(&self, position: IVec3) -> usize
+    {
+        (position.x * self.size.y * self.size.z + position.y * self.size.z + position.z) as usize
+    }
+
+    pub fn get(&self, position: IVec3) -> Option<&Voxel>
+    {
+        let inside = position.cmpge(IVec3::ZERO).all() && position.cmplt(self.size).all();
+        if inside { self.voxels.get(self.index(position)) } else { None }
+    }
+
+    //every position inside the schematic with its voxel
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, &Voxel)>
+    {
+        let size = self.size;
+        (0..size.x).flat_map(move |x| (0..size.y).flat_map(move |y| (0..size.z).map(move |z| IVec3::new(x, y, z))))
+            .zip(self.voxels.iter())
+    }
+
+    //copies an inclusive box, None if part of it is not loaded or it is bigger than MAX_SCHEMATIC_VOLUME
+    pub fn copy(world: &impl VoxelAccess, min: IVec3, max: IVec3) -> Option<Schematic>
+    {
+        let (low, high) = (min.min(max), min.max(max));
+        let mut size = IVec3::ZERO;
+        for axis in 0..3
+        {
+            size[axis] = high[axis].checked_sub(low[axis])?.checked_add(1)?;
+        }
+        let mut schematic = Schematic::with_size(size).ok()?;
+        for x in 0..schematic.size.x
+        {
+            for y in 0..schematic.size.y
+            {
+                for z in 0..schematic.size.z
+                {
+                    let position = IVec3::new(x, y, z);
+                    let index = schematic.index(position);
+                    schematic.voxels[index] = world.voxel(low + position)?.clone();
+                }
+            }
+        }
+        Some(schematic)
+    }
+
+    //a copy with the transform applied, stairs and flowing fluids are turned with it
+    pub fn transformed(&self, transform: SchematicTransform) -> Schematic
+    {
+        let mut result = self.clone();
+        if transform.mirror_x
+        {
+            let mut mirrored = Schematic::new(result.size);
+            for (position, voxel) in result.iter()
+            {
+                let mut voxel = voxel.clone();
+                voxel.voxel_rotation = voxel.voxel_rotation.mirror_x();
+                voxel.fluid.flow = mirror_flow(voxel.fluid.flow);
+                let index = mirrored.index(IVec3::new(result.size.x - 1 - position.x, position.y, position.z));
+                mirrored.voxels[index] = voxel;
+            }
+            result = mirrored;
+        }
+        for _ in 0..transform.quarter_turns % 4
+        {
+            //(x, z) turns to (-z, x), shifted back so the box starts at zero again
+            let mut rotated = Schematic::new(IVec3::new(result.size.z, result.size.y, result.size.x));
+            for (position, voxel) in result.iter()
+            {
+                let mut voxel = voxel.clone();
+                voxel.voxel_rotation = voxel.voxel_rotation.rotate_y();
+                voxel.fluid.flow = rotate_flow(voxel.fluid.flow);
+                let index = rotated.index(IVec3::new(result.size.z - 1 - position.z, position.y, position.x));
+                rotated.voxels[index] = voxel;
+            }
+            result = rotated;
+        }
+        result
+    }
+
+    //voxels to set to paste the schematic with its minimum corner at origin, ready for BulkOperation::Set
+    //air is left out when skip_air is set so the schematic blends into what is already there
+    pub fn paste(&self, origin: IVec3, transform: SchematicTransform, skip_air: bool) -> Vec<(IVec3, Voxel)>
+    {
+        self.transformed(transform).iter()
+            .filter(|(_, voxel)| !skip_air || voxel.voxel_type != VoxelType::Air)
+            .map(|(position, voxel)| (origin + position, voxel.clone()))
+            .collect()
+    }
+
+    //writes the part of an already transformed schematic that falls inside one chunk, for world generators
+    //placing structures while they fill in chunk data. origin is relative to the sector like voxel positions
+    pub fn place_in_chunk(&self, block_data: &mut [Voxel], chunk_position: [i32; 3], origin: IVec3, skip_air: bool)
+    {
+        let chunk_origin = IVec3::from(chunk_position) * CHUNK_SIZE;
+        let low = (origin - chunk_origin).max(IVec3::ZERO);
+        let high = (origin + self.size - chunk_origin).min(IVec3::splat(CHUNK_SIZE));
+        for x in low.x..high.x
+        {
+            for y in low.y..high.y
+            {
+                for z in low.z..high.z
+                {
+                    let voxel = &self.voxels[self.index(chunk_origin + IVec3::new(x, y, z) - origin)];
+                    if !skip_air || voxel.voxel_type != VoxelType::Air
+                    {
+                        block_data[voxel_index(x, y, z)] = voxel.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    //format: magic, version, size as three little endian i32, palette of distinct voxels, then runs of palette indices
+    //runs are (u32 length, u16 palette index) so big areas of the same voxel cost six bytes
+    //the palette length is a u16, so schematics with more different voxels than that cannot be written
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>>
+    {
+        let mut palette: Vec<&Voxel> = Vec::new();
+        let mut palette_indices: HashMap<&Voxel, u16> = HashMap::new();
+        let mut runs: Vec<(u32, u16)> = Vec::new();
+        for voxel in &self.voxels
+        {
+            let index = match palette_indices.get(voxel)
+            {
+                Some(index) => *index,
+                None =>
+                {
+                    let index = u16::try_from(palette.len()).ok().filter(|index| *index < u16::MAX).ok_or_else(|| invalid("schematic has more different voxels than a palette can hold"))?;
+                    palette.push(voxel);
+                    palette_indices.insert(voxel, index);
+                    index
+                }
+            };
+            match runs.last_mut()
+            {
+                Some((length, last)) if *last == index && *length < u32::MAX => *length += 1,
+                _ => runs.push((1, index)),
+            }
+        }
+        let mut bytes = Vec::with_capacity(21 + palette.len() * VOXEL_BYTES + runs.len() * 6);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        for axis in self.size.to_array()
+        {
+            bytes.extend_from_slice(&axis.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+        for voxel in palette
+        {
+            bytes.extend_from_slice(&encode_voxel(voxel));
+        }
+        bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (length, index) in runs
+        {
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Schematic>
+    {
+        let mut cursor = 0;
+        let mut take = |count: usize| -> std::io::Result<&[u8]>
+        {
+            let slice = bytes.get(cursor..cursor + count).ok_or_else(|| invalid("schematic is truncated"))?;
+            cursor += count;
+            Ok(slice)
+        };
+        if take(4)? != MAGIC
+        {
+            return Err(invalid("not a schematic"));
+        }
+        if take(1)?[0] != VERSION
+        {
+            return Err(invalid("unsupported schematic version"));
+        }
+        let mut size = [0; 3];
+        for axis in &mut size
+        {
+            *axis = i32::from_le_bytes(take(4)?.try_into().unwrap());
+        }
+        let size = IVec3::from(size);
+        let volume = checked_volume(size)?;
+        let palette_len = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        let mut palette = Vec::with_capacity(palette_len as usize);
+        for _ in 0..palette_len
+        {
+            palette.push(decode_voxel(take(VOXEL_BYTES)?)?);
+        }
+        let run_count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        //grown from the runs that are actually there rather than trusting the size in the header
+        let mut voxels = Vec::new();
+        for _ in 0..run_count
+        {
+            let run = take(6)?;
+            let length = u32::from_le_bytes(run[0..4].try_into().unwrap()) as usize;
+            let voxel = palette.get(u16::from_le_bytes(run[4..6].try_into().unwrap()) as usize).ok_or_else(|| invalid("palette index out of range"))?;
+            if length > volume - voxels.len()
+            {
+                return Err(invalid("schematic has more voxels than its size"));
+            }
+            voxels.extend(std::iter::repeat_n(voxel.clone(), length));
+        }
+        if voxels.len() != volume
+        {
+            return Err(invalid("schematic has fewer voxels than its size"));
+        }
+        Ok(Schematic { size, voxels })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()>
+    {
+        std::fs::write(path, self.to_bytes()?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Schematic>
+    {
+        Schematic::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn header(size: [i32; 3], palette_len: u16) -> Vec<u8>
+    {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for axis in size
+        {
+            bytes.extend_from_slice(&axis.to_le_bytes());
+        }
+        bytes.extend_from_slice(&palette_len.to_le_bytes());
+        bytes
+    }
+
+    fn error_message(bytes: &[u8]) -> String
+    {
+        Schematic::from_bytes(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn round_trips_through_bytes()
+    {
+        let mut schematic = Schematic::new(IVec3::new(3, 2, 4));
+        for (index, voxel) in schematic.voxels.iter_mut().enumerate()
+        {
+            *voxel = Voxel::new(VoxelType::ALL[index % 5], VoxelVariant::ALL[index % 3], VoxelRotation::ALL[index % 6]).with_half(if index % 2 == 0 { VoxelHalf::Top } else { VoxelHalf::Bottom });
+        }
+        let transformed = schematic.transformed(SchematicTransform { quarter_turns: 1, mirror_x: true });
+        assert_eq!(Schematic::from_bytes(&transformed.to_bytes().unwrap()).unwrap(), transformed);
+        assert_eq!(Schematic::from_bytes(&Schematic::new(IVec3::ZERO).to_bytes().unwrap()).unwrap(), Schematic::new(IVec3::ZERO));
+    }
+
+    #[test]
+    fn rejects_sizes_from_the_header_before_allocating()
+    {
+        assert_eq!(error_message(&header([-1, 4, 4], 0)), "negative schematic size");
+        assert_eq!(error_message(&header([i32::MAX, i32::MAX, i32::MAX], 0)), "schematic is too big");
+        assert_eq!(error_message(&header([257, 256, 256], 0)), "schematic is too big");
+        assert!(checked_volume(IVec3::splat(256)).is_ok());
+        assert!(Schematic::with_size(IVec3::new(70_000, 70_000, 70_000)).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_and_inconsistent_runs()
+    {
+        let bytes = Schematic::new(IVec3::new(2, 2, 2)).to_bytes().unwrap();
+        for length in [0, 3, 10, bytes.len() - 1]
+        {
+            assert!(Schematic::from_bytes(&bytes[..length]).is_err(), "accepted {} bytes", length);
+        }
+        let run = |length: u32, index: u16| [length.to_le_bytes().as_slice(), index.to_le_bytes().as_slice()].concat();
+        let mut bytes = header([2, 2, 2], 1);
+        bytes.extend_from_slice(&encode_voxel(&Voxel::default()));
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        let too_many = [bytes.clone(), run(4, 0), run(u32::MAX, 0)].concat();
+        assert_eq!(error_message(&too_many), "schematic has more voxels than its size");
+        let too_few = [bytes.clone(), run(4, 0), run(3, 0)].concat();
+        assert_eq!(error_message(&too_few), "schematic has fewer voxels than its size");
+        let bad_index = [bytes, run(4, 0), run(4, 1)].concat();
+        assert_eq!(error_message(&bad_index), "palette index out of range");
+    }
+
+    #[test]
+    fn refuses_to_write_more_voxels_than_the_palette_holds()
+    {
+        //distinct voxels by type, variant, rotation and fluid level
+        let distinct = VoxelType::ALL.iter().flat_map(|voxel_type| VoxelVariant::ALL.iter().flat_map(move |variant| VoxelRotation::ALL.iter().flat_map(move |rotation| (0..=255).map(move |level|
+        {
+            let mut voxel = Voxel::new(*voxel_type, *variant, *rotation);
+            voxel.fluid.level = level;
+            voxel
+        }))));
+        let mut schematic = Schematic::new(IVec3::new(u16::MAX as i32, 1, 1));
+        for (slot, voxel) in schematic.voxels.iter_mut().zip(distinct.clone())
+        {
+            *slot = voxel;
+        }
+        assert!(schematic.to_bytes().is_ok());
+        schematic.size.x += 1;
+        schematic.voxels.push(distinct.clone().nth(u16::MAX as usize).unwrap());
+        assert!(schematic.to_bytes().is_err());
+    }
+
+    #[test]
+    fn copies_loaded_boxes_only()
+    {
+        let stone = Voxel::new(VoxelType::Stone, VoxelVariant::Block, VoxelRotation::Forward);
+        let world = crate::database::edit::test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(3, 4, 5), stone.clone())]);
+        let schematic = Schematic::copy(&world, IVec3::new(4, 6, 6), IVec3::new(2, 4, 5)).unwrap();
+        assert_eq!(schematic.size, IVec3::new(3, 3, 2));
+        assert_eq!(schematic.get(IVec3::new(1, 0, 0)), Some(&stone));
+        assert_eq!(schematic.iter().filter(|(_, voxel)| **voxel == stone).count(), 1);
+        //reaches into an unloaded chunk
+        assert_eq!(Schematic::copy(&world, IVec3::new(30, 0, 0), IVec3::new(33, 1, 1)), None);
+        //too big to hold, refused before anything is allocated or an axis overflows
+        assert_eq!(Schematic::copy(&world, IVec3::ZERO, IVec3::splat(300)), None);
+        assert_eq!(Schematic::copy(&world, IVec3::splat(i32::MIN), IVec3::splat(i32::MAX)), None);
+    }
+}
@@ -4,6 +4,8 @@ use crate::database::voxel::*;
 use crate::database::example_chunk::STONE_CHUNK;
 use crate::database::chunk::*;
 use crate::database::settings::*;
+use crate::database::events::*;
+use crate::database::generator::*;
 use bevy_flycam::FlyCam;
 use bevy::{
     core_pipeline::{
@@ -16,14 +18,45 @@ use bevy::{
     prelude::*,
     tasks::{AsyncComputeTaskPool, Task},
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use bevy::math::vec3;
 
 #[derive(Default,Resource)]
 pub struct Universe
 {
     sectors: HashMap<[i32; 3], Sector>,
+    //loaded chunks that changed since they were generated or last saved, keyed by sector
+    pub modified: HashSet<([i32; 3], [i32; 3])>,
+}
+
+impl Universe
+{
+    //saved copy of a chunk, None if it was never changed and can just be generated again
+    pub fn saved_chunk(&self, sector: [i32; 3], chunk_id: [i32; 3]) -> Option<&Chunk>
+    {
+        self.sectors.get(&sector)?.chunks.get(&chunk_id)
+    }
+
+    pub fn save_chunk(&mut self, sector: [i32; 3], chunk_id: [i32; 3], chunk: Chunk)
+    {
+        self.sectors.entry(sector).or_default().chunks.insert(chunk_id, chunk);
+    }
+
+    //voxel data for a chunk that is not loaded, the saved copy if there is one or freshly generated otherwise
+    pub fn load_or_generate(&self, sector: [i32; 3], chunk_id: [i32; 3], generator: &dyn WorldGenerator) -> Chunk
+    {
+        match self.saved_chunk(sector, chunk_id)
+        {
+            Some(chunk) => chunk.clone(),
+            None => Chunk
+            {
+                block_data: generator.generate(chunk_id),
+                pos: (chunk_id[0]*CHUNK_SIZE, chunk_id[1]*CHUNK_SIZE, chunk_id[2]*CHUNK_SIZE),
+            },
+        }
+    }
 }
 
 #[derive(Default,Resource, Clone)]
@@ -35,7 +68,8 @@ pub struct CurrentlyLoaded
 #[derive(Default,Resource, Clone)]
 pub struct SectorsRendering
 {
-    pub chunks: HashMap<[i32; 3], Arc<Option<RenderData>>>
+    //voxel data of every loaded chunk, None while the chunk is still generating
+    pub chunks: HashMap<[i32; 3], Option<Arc<Chunk>>>
 }
 
 
@@ -45,89 +79,167 @@ pub struct Sector
     chunks: HashMap<[i32; 3], Chunk>
 }
 
+//keeps the chunks around an entity loaded. any entity with a transform can carry one, no camera needed
+//the union of every loader's range decides what stays loaded
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLoader
+{
+    pub shape: LoadShape,
+    //horizontal radius in chunks, also the vertical radius for LoadShape::Sphere
+    pub radius: i32,
+    //only used by LoadShape::Cylinder
+    pub vertical_radius: i32,
+}
+
+impl Default for ChunkLoader
+{
+    fn default() -> Self
+    {
+        ChunkLoader
+        {
+            shape: LOAD_SHAPE,
+            radius: RENDER_DISTANCE,
+            vertical_radius: VERTICAL_RENDER_DISTANCE,
+        }
+    }
+}
+
+impl ChunkLoader
+{
+    //horizontal and vertical radius of the load shape in chunks, widened by extra
+    pub fn radii(&self, extra: i32) -> (i32, i32)
+    {
+        match self.shape
+        {
+            LoadShape::Sphere => (self.radius + extra, self.radius + extra),
+            LoadShape::Cylinder => (self.radius + extra, self.vertical_radius + extra),
+        }
+    }
+
+    //checks if a chunk is inside the load shape around center. the bounds are symmetric and inclusive
+    pub fn in_range(&self, center: [i32; 3], chunk: [i32; 3], extra: i32) -> bool
+    {
+        let (horizontal, vertical) = self.radii(extra);
+        let dx = chunk[0] - center[0];
+        let dy = chunk[1] - center[1];
+        let dz = chunk[2] - center[2];
+        match self.shape
+        {
+            LoadShape::Sphere => dx * dx + dy * dy + dz * dz <= horizontal * horizontal,
+            LoadShape::Cylinder => dx * dx + dz * dz <= horizontal * horizontal && dy.abs() <= vertical,
+        }
+    }
+
+    //every chunk inside the load shape around center, widened by extra
+    pub fn chunks_in_range(self, center: [i32; 3], extra: i32) -> impl Iterator<Item = [i32; 3]>
+    {
+        let (horizontal, vertical) = self.radii(extra);
+        (-horizontal..=horizontal).flat_map(move |x| (-vertical..=vertical).flat_map(move |y| (-horizontal..=horizontal).map(move |z| [center[0] + x, center[1] + y, center[2] + z])))
+            .filter(move |chunk_id| self.in_range(center, *chunk_id, extra))
+    }
+}
+
+//chunk each loader was in the last time its needed chunks were worked out
+//the needed set of a loader is every chunk in range of its center so only the center and loader have to be stored
+#[derive(Default,Resource)]
+pub struct LoaderChunks
+{
+    pub centers: HashMap<(Entity, [i32; 3]), ([i32; 3], ChunkLoader)>,
+    //chunks that left the range of a loader and might need unloading, keyed by sector
+    pub stale: Vec<([i32; 3], [i32; 3])>,
+}
+
+//the fly camera streams terrain like any other loader
+pub fn attach_flycam_loaders
+(
+    mut commands: Commands,
+    cameras: Query<Entity, (Added<FlyCam>, Without<ChunkLoader>)>,
+)
+{
+    for camera in &cameras
+    {
+        commands.entity(camera).insert(ChunkLoader::default());
+    }
+}
+
 pub fn load_chunks
 (
     mut commands: Commands,
-    player: Query<(&Camera,&Transform), With<FlyCam>>,
+    loaders: Query<(Entity,&GlobalTransform,&ChunkLoader)>,
     universe: Res<Universe>,
+    generator: Res<ActiveGenerator>,
     mut loaded_sectors: ResMut<CurrentlyLoaded>,
     mut chunk_entitys: ResMut<ChunkEntitys>,
+    mut loader_chunks: ResMut<LoaderChunks>,
 )
 {
     let thread_pool = AsyncComputeTaskPool::get();
-    for (i,b) in player.iter()
+    //loaders that were despawned or removed drop everything they were keeping loaded
+    let removed: Vec<(Entity, [i32; 3])> = loader_chunks.centers.keys().filter(|(entity, _)| !loaders.contains(*entity)).cloned().collect();
+    for key in removed
+    {
+        if let Some((old_center, old_loader)) = loader_chunks.centers.remove(&key)
+        {
+            let stale: Vec<([i32; 3], [i32; 3])> = old_loader.chunks_in_range(old_center, UNLOAD_HYSTERESIS).map(|chunk_id| (key.1, chunk_id)).collect();
+            loader_chunks.stale.extend(stale);
+        }
+    }
+    for (entity,transform,loader) in loaders.iter()
     {
-        //i is camera
-        //b is position
         //repeat through all sectors
         for sector in &universe.sectors
         {
-            //find camera to sector offset
-            let offset = b.translation - Vec3::new(sector.0[0] as f32, sector.0[1] as f32, sector.0[2] as f32);
-            let chunk_pos = (offset / CHUNK_SIZE as f32).round();
-            let chunk_center = [chunk_pos.x as i32, chunk_pos.y as i32, chunk_pos.z as i32];
-            for x in chunk_center[0]-RENDER_DISTANCE..chunk_center[0]+RENDER_DISTANCE
+            //find loader to sector offset
+            let chunk_center = position_to_chunk(transform.translation(), sector.0);
+            let old = loader_chunks.centers.get(&(entity, *sector.0)).copied();
+            if old == Some((chunk_center, *loader))
+            {
+                //loader has not crossed a chunk boundary or changed shape so nothing it needs has changed
+                //reading through ResMut leaves the resource unchanged so update_chunk_lods can skip the frame
+                continue;
+            }
+            loader_chunks.centers.insert((entity, *sector.0), (chunk_center, *loader));
+            //chunks the loader kept loaded before but no longer covers
+            if let Some((old_center, old_loader)) = old
             {
-                for y in chunk_center[1]-RENDER_DISTANCE..chunk_center[1]+RENDER_DISTANCE
+                let stale: Vec<([i32; 3], [i32; 3])> = old_loader.chunks_in_range(old_center, UNLOAD_HYSTERESIS)
+                    .filter(|chunk_id| !loader.in_range(chunk_center, *chunk_id, UNLOAD_HYSTERESIS))
+                    .map(|chunk_id| (*sector.0, chunk_id))
+                    .collect();
+                loader_chunks.stale.extend(stale);
+            }
+            let loaded_sector = loaded_sectors.sectors.entry(*sector.0).or_default();
+            for chunk_id in loader.chunks_in_range(chunk_center, 0)
+            {
+                //check if chunk is loaded before rendering new chunk
+                if loaded_sector.chunks.contains_key(&chunk_id)
                 {
-                    for z in chunk_center[2]-RENDER_DISTANCE..chunk_center[2]+RENDER_DISTANCE
+                    //chunk is loaded currently
+                    //no need to reload chunk because its already loaded so do nothing
+                    continue;
+                }
+                //chunk is not loaded so load up chunk
+                loaded_sector.chunks.insert(chunk_id, None);
+                //build chunk data, chunks that were changed before come back from their saved copy
+                let saved = universe.saved_chunk(*sector.0, chunk_id).cloned();
+                let generator = generator.0.clone();
+                let chunk_task:Task<Chunk> = thread_pool.spawn(async move
                     {
-                        let chunk_id = [x,y,z];
-                        //check if sector is loaded already
-                        if let Some(mut loaded_sector) = loaded_sectors.sectors.get_mut(sector.0)
-                        {
-                            //sector already loaded
-                            //check if chunk is loaded before rendering new chunk
-                            if let Some(chunk) = loaded_sector.chunks.get(&chunk_id)
-                            {
-                                //chunk is loaded currently
-                                //no need to reload chunk because its already loaded so do nothing
-                            }else
-                            {
-                                //chunk is not loaded so load up chunk
-                                loaded_sector.chunks.insert(chunk_id, Arc::new(None));
-                                //build chunk data
-                                let chunk_task:Task<Chunk> = thread_pool.spawn(async move
-                                    {
-                                        Chunk
-                                        {
-                                            block_data: generate_chunk(chunk_id),
-                                            pos: (chunk_id[0]*CHUNK_SIZE, chunk_id[1]*CHUNK_SIZE, chunk_id[2]*CHUNK_SIZE),
-                                        }
-                                    });
-                                //println!("Chunk {:?} in sector {:?} Loaded!", chunk_id, sector.0);
-                                let mut name: String = chunk_id
-                                    .iter()
-                                    .map(|&n| n.to_string())  // Convert each integer to a String
-                                    .collect::<Vec<_>>()       // Collect into a vector of strings
-                                    .join(",");
-                                name = format!("[{}]", name);
-                                let generate_chunk = commands.spawn((GenerateChunk(chunk_task),Name::new(name))).id();
-                                chunk_entitys.entitys.insert(chunk_id,generate_chunk);
-                            }
-                        }else
+                        saved.unwrap_or_else(|| Chunk
                         {
-                            //load up sector
-                            loaded_sectors.sectors.insert(*sector.0, SectorsRendering::default());
-                            //chunk has not been loaded because sector was not loaded so a new chunk can be rendered safely
-                            if let Some(loaded_sector) = loaded_sectors.sectors.get_mut(sector.0)
-                            {
-                                loaded_sector.chunks.insert(chunk_id, Arc::new(None));
-                                let chunk_task:Task<Chunk> = thread_pool.spawn(async move
-                                    {
-                                        Chunk
-                                        {
-                                            block_data: generate_chunk(chunk_id),
-                                            pos: (chunk_id[0]*CHUNK_SIZE, chunk_id[1]*CHUNK_SIZE, chunk_id[2]*CHUNK_SIZE),
-                                        }
-                                    });
-                                //println!("Chunk {:?} in sector {:?} Loaded!", chunk_id, sector.0);
-                                let generate_chunk = commands.spawn(GenerateChunk(chunk_task)).id();
-                                chunk_entitys.entitys.insert(chunk_id,generate_chunk);
-                            }
-                        }
-                    }
-                }
+                            block_data: generator.generate(chunk_id),
+                            pos: (chunk_id[0]*CHUNK_SIZE, chunk_id[1]*CHUNK_SIZE, chunk_id[2]*CHUNK_SIZE),
+                        })
+                    });
+                //println!("Chunk {:?} in sector {:?} Loaded!", chunk_id, sector.0);
+                let mut name: String = chunk_id
+                    .iter()
+                    .map(|&n| n.to_string())  // Convert each integer to a String
+                    .collect::<Vec<_>>()       // Collect into a vector of strings
+                    .join(",");
+                name = format!("[{}]", name);
+                let generate_chunk = commands.spawn((GenerateChunk(chunk_task),ChunkCoords { sector: *sector.0, chunk: chunk_id },Name::new(name))).id();
+                chunk_entitys.entitys.insert(chunk_id,generate_chunk);
             }
         }
     }
@@ -136,50 +248,66 @@ pub fn load_chunks
 pub fn unload_chunks
 (
     mut commands: Commands,
+    mut universe: ResMut<Universe>,
     mut loaded_sectors: ResMut<CurrentlyLoaded>,
     mut chunk_entitys: ResMut<ChunkEntitys>,
-    player: Query<(&Camera,&Transform), With<FlyCam>>,
+    mut loader_chunks: ResMut<LoaderChunks>,
+    mut chunk_unloaded: EventWriter<ChunkUnloaded>,
 )
 {
-    for (i, b) in &player
+    //only chunks that left the range of a loader since the last pass are checked
+    //stale is bookkeeping between the two passes, taking it must not look like the loaders moved
+    let stale = std::mem::take(&mut loader_chunks.bypass_change_detection().stale);
+    for (sector, position) in stale
     {
-        //i is camera
-        //b is position
-        //b.translation
-        for sector in loaded_sectors.sectors.clone()
+        //another loader can still be keeping the chunk loaded
+        //unload radius is wider than the load radius so chunks on the boundary do not flicker
+        let needed = loader_chunks.centers.iter().any(|((_, loader_sector), (center, loader))| *loader_sector == sector && loader.in_range(*center, position, UNLOAD_HYSTERESIS));
+        if needed
+        {
+            continue;
+        }
+        if let Some(loaded_sector) = loaded_sectors.sectors.get_mut(&sector)
         {
-            let offset = b.translation - Vec3::new(sector.0[0] as f32, sector.0[1] as f32, sector.0[2] as f32);
-            let chunk_pos = (offset / CHUNK_SIZE as f32).round();
-            let chunk_center = [chunk_pos.x as i32, chunk_pos.y as i32, chunk_pos.z as i32];
-            for (position, chunk_entity) in chunk_entitys.entitys.clone()
+            if let Some(chunk) = loaded_sector.chunks.remove(&position)
             {
-                let mut outside_range = true;
-                for x in chunk_center[0]-RENDER_DISTANCE..chunk_center[0]+RENDER_DISTANCE
+                //changed chunks are saved so they come back the way they were left
+                if let Some(chunk) = chunk.filter(|_| universe.modified.remove(&(sector, position)))
                 {
-                    for y in chunk_center[1]-RENDER_DISTANCE..chunk_center[1]+RENDER_DISTANCE
-                    {
-                        for z in chunk_center[2]-RENDER_DISTANCE..chunk_center[2]+RENDER_DISTANCE
-                        {
-                            if [x,y,z] == position
-                            {
-
-                                outside_range = false;
-                            }
-                        }
-                    }
-                }
-                if outside_range
-                {
-                    loaded_sectors.sectors.get_mut(&sector.0).unwrap().chunks.remove(&position);
-                    chunk_entitys.entitys.remove(&position);
-                    commands.entity(chunk_entity).despawn();
-                    //println!("Chunk {:?} in sector {:?} Unloaded!", position, sector.0);
+                    universe.save_chunk(sector, position, Arc::unwrap_or_clone(chunk));
                 }
+                chunk_unloaded.send(ChunkUnloaded { sector, chunk: position });
             }
         }
+        if let Some(chunk_entity) = chunk_entitys.entitys.remove(&position)
+        {
+            commands.entity(chunk_entity).despawn();
+            //println!("Chunk {:?} in sector {:?} Unloaded!", position, sector);
+        }
+    }
+}
+
+//remembers which loaded chunks have to be saved when they unload
+pub fn track_modified_chunks
+(
+    mut universe: ResMut<Universe>,
+    mut voxel_changed: EventReader<VoxelChanged>,
+)
+{
+    for change in voxel_changed.read()
+    {
+        universe.modified.insert((change.sector, change.chunk));
     }
 }
 
+//chunk containing a world position, relative to the sector origin
+pub fn position_to_chunk(translation: Vec3, sector: &[i32; 3]) -> [i32; 3]
+{
+    let offset = translation - Vec3::new(sector[0] as f32, sector[1] as f32, sector[2] as f32);
+    let chunk_pos = (offset / CHUNK_SIZE as f32).floor();
+    [chunk_pos.x as i32, chunk_pos.y as i32, chunk_pos.z as i32]
+}
+
 pub fn generate_planet
 (
     mut commands: Commands,
@@ -204,4 +332,137 @@ pub fn generate_planet
         transform: Transform::from_xyz(0.0, 0.5, 0.0),
         ..default()
     });
-}
\ No newline at end of file
+}
+//generates nothing so the loading benchmark measures only the bookkeeping of load_chunks and unload_chunks
+struct EmptyGenerator;
+
+impl WorldGenerator for EmptyGenerator
+{
+    fn generate(&self, _chunk_position: [i32; 3]) -> Vec<Voxel>
+    {
+        Vec::new()
+    }
+}
+
+//result of benchmark_boundary_crossing
+#[derive(Debug, Clone, Copy)]
+pub struct LoadingBenchmark
+{
+    pub loaded: usize,
+    pub crossing: Duration,
+    //the unload pass from before LoaderChunks over the same chunks, None if it was skipped
+    pub full_scan: Option<Duration>,
+}
+
+//loads everything around one loader, then times the frame where it crosses a chunk boundary
+//runs load_chunks and unload_chunks in a headless app, the full scan is only run when asked because it grows with radius^6
+pub fn benchmark_boundary_crossing(loader: ChunkLoader, run_full_scan: bool) -> LoadingBenchmark
+{
+    let mut app = App::new();
+    let mut universe = Universe::default();
+    universe.sectors.insert([0, 0, 0], Sector::default());
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(universe)
+        .insert_resource(ActiveGenerator(Arc::new(EmptyGenerator)))
+        .init_resource::<CurrentlyLoaded>()
+        .init_resource::<ChunkEntitys>()
+        .init_resource::<LoaderChunks>()
+        .add_event::<ChunkUnloaded>()
+        .add_systems(Update, (load_chunks, unload_chunks).chain());
+    let start = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+    let entity = app.world.spawn((GlobalTransform::from_translation(start), loader)).id();
+    app.update();
+
+    *app.world.get_mut::<GlobalTransform>(entity).unwrap() = GlobalTransform::from_translation(start + Vec3::X * CHUNK_SIZE as f32);
+    let timer = Instant::now();
+    app.update();
+    let crossing = timer.elapsed();
+
+    let loaded: Vec<[i32; 3]> = app.world.resource::<CurrentlyLoaded>().sectors.values().flat_map(|sector| sector.chunks.keys().copied()).collect();
+    let full_scan = run_full_scan.then(||
+    {
+        let timer = Instant::now();
+        std::hint::black_box(full_scan_unload(&loaded, [1, 0, 0], loader.radius));
+        timer.elapsed()
+    });
+    LoadingBenchmark { loaded: loaded.len(), crossing, full_scan }
+}
+
+//the unload pass from before LoaderChunks, every loaded chunk is looked for in the whole render cube around the viewer
+//returns how many chunks it would unload
+fn full_scan_unload(loaded: &[[i32; 3]], center: [i32; 3], radius: i32) -> usize
+{
+    let mut outside = 0;
+    for position in loaded
+    {
+        let mut outside_range = true;
+        for x in center[0] - radius..center[0] + radius
+        {
+            for y in center[1] - radius..center[1] + radius
+            {
+                for z in center[2] - radius..center[2] + radius
+                {
+                    if [x, y, z] == *position
+                    {
+                        outside_range = false;
+                    }
+                }
+            }
+        }
+        if outside_range
+        {
+            outside += 1;
+        }
+    }
+    outside
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[derive(Resource, Default)]
+    struct LoaderChanges(usize);
+
+    fn count_loader_changes(loader_chunks: Res<LoaderChunks>, mut changes: ResMut<LoaderChanges>)
+    {
+        if loader_chunks.is_changed()
+        {
+            changes.0 += 1;
+        }
+    }
+
+    #[test]
+    fn resting_loader_leaves_loader_chunks_unchanged()
+    {
+        let mut app = App::new();
+        let mut universe = Universe::default();
+        universe.sectors.insert([0, 0, 0], Sector::default());
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(universe)
+            .insert_resource(ActiveGenerator(Arc::new(EmptyGenerator)))
+            .init_resource::<CurrentlyLoaded>()
+            .init_resource::<ChunkEntitys>()
+            .init_resource::<LoaderChunks>()
+            .init_resource::<LoaderChanges>()
+            .add_event::<ChunkUnloaded>()
+            .add_systems(Update, (load_chunks, unload_chunks, count_loader_changes).chain());
+        let loader = ChunkLoader { shape: LoadShape::Cylinder, radius: 2, vertical_radius: 1 };
+        let start = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+        let entity = app.world.spawn((GlobalTransform::from_translation(start), loader)).id();
+        app.update();
+        assert_eq!(app.world.resource::<LoaderChanges>().0, 1);
+        //moving inside the same chunk does not count
+        *app.world.get_mut::<GlobalTransform>(entity).unwrap() = GlobalTransform::from_translation(start + Vec3::ONE);
+        for _ in 0..3
+        {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<LoaderChanges>().0, 1);
+        *app.world.get_mut::<GlobalTransform>(entity).unwrap() = GlobalTransform::from_translation(start + Vec3::X * CHUNK_SIZE as f32);
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<LoaderChanges>().0, 2);
+    }
+}
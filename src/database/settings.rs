@@ -1,9 +1,64 @@
+use crate::database::voxel::VoxelType;
+
 pub const WIREFRAME: bool = false;
-pub const RENDER_DISTANCE: i32 = 5;
 pub const CHUNK_SIZE: i32 = 32;
 
+//default horizontal load radius in chunks for a ChunkLoader
+pub const RENDER_DISTANCE: i32 = 8;
+//chunks closer than this to a loader are always meshed at full detail, further ones use the LodSettings levels
+pub const FULL_DETAIL_DISTANCE: i32 = 5;
+//default vertical load radius in chunks, only used by LoadShape::Cylinder
+pub const VERTICAL_RENDER_DISTANCE: i32 = 3;
+//extra chunks a chunk may drift outside the load radius before it is unloaded, stops chunks flickering on the boundary
+pub const UNLOAD_HYSTERESIS: i32 = 1;
+//default shape for a ChunkLoader
+pub const LOAD_SHAPE: LoadShape = LoadShape::Cylinder;
+
 // smaller density mod means more squishing
 pub const DENSITY_MOD: f64 = 10.0;
 
 //self explanatory
 pub const SEA_LEVEL: i32 = 60;
+
+//seed for the terrain noise
+pub const WORLD_SEED: u32 = 1;
+//greyscale png to build terrain from instead of noise, None for noise terrain
+pub const HEIGHTMAP: Option<&str> = None;
+//optional colour or splat map for the heightmap's surface blocks
+pub const HEIGHTMAP_SURFACE: Option<&str> = None;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LoadShape {
+    //uses RENDER_DISTANCE in every direction
+    Sphere,
+    //uses RENDER_DISTANCE horizontally and VERTICAL_RENDER_DISTANCE vertically
+    #[default]
+    Cylinder,
+}
+
+//voxels picked per loaded chunk on every random tick
+pub const RANDOM_TICK_SPEED: u32 = 3;
+//seconds between random ticks
+pub const RANDOM_TICK_INTERVAL: f32 = 0.05;
+//seed for random ticks, the same seed and loaded chunks give the same world changes
+pub const RANDOM_TICK_SEED: u64 = 1;
+//seed for the temperature noise
+pub const CLIMATE_SEED: u32 = 2;
+
+//how block textures are handed to the chunk material
+pub const TEXTURE_MODE: TextureMode = TextureMode::Atlas;
+
+//sectors meshed as smooth terrain with surface nets instead of blocky faces
+pub const SMOOTH_SECTORS: &[[i32; 3]] = &[];
+//block types meshed as smooth terrain in every sector, the rest of the sector keeps its own mesher
+pub const SMOOTH_BLOCK_TYPES: &[VoxelType] = &[];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TextureMode {
+    //every block texture packed into one TextureAtlas, drawn with a StandardMaterial
+    #[default]
+    Atlas,
+    //one layer of a texture array per block texture, drawn with VoxelArrayMaterial. all textures have to be the same size
+    //mipmaps do not bleed between blocks and uvs can go past 1 to repeat the texture
+    Array,
+}
@@ -0,0 +1,207 @@
+use crate::database::chunk::*;
+use crate::database::edit::VoxelAccess;
+use crate::database::mesher::*;
+use crate::database::settings::CHUNK_SIZE;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+
+//smooth terrain from naive surface nets. the density field is sampled at voxel centers, one vertex is placed in every
+//cell the surface passes through and every edge crossing the surface becomes a quad between the 4 cells around it
+//only solid voxels count as inside, fluids and other see through voxels are not meshed
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceNetsMesher
+{
+    //how much the 6 neighbours pull the surface around, 0 gives the chamfered shape of the blocks
+    //below 1 the surface still keeps every solid voxel inside it
+    pub smoothing: f32,
+}
+
+impl Default for SurfaceNetsMesher
+{
+    fn default() -> Self
+    {
+        SurfaceNetsMesher { smoothing: 0.9 }
+    }
+}
+
+const NEIGHBOURS: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+
+//the 12 cell edges as pairs of corner indices, corner bits are x, y, z
+const CELL_EDGES: [(usize, usize); 12] = [(0, 4), (1, 5), (2, 6), (3, 7), (0, 2), (1, 3), (4, 6), (5, 7), (0, 1), (2, 3), (4, 5), (6, 7)];
+
+fn corner_offset(corner: usize) -> IVec3
+{
+    IVec3::new((corner >> 2 & 1) as i32, (corner >> 1 & 1) as i32, (corner & 1) as i32)
+}
+
+impl SurfaceNetsMesher
+{
+    //positive inside solid voxels. the sign always follows the voxel itself so the surface never cuts off a block,
+    //the neighbours only move where along an edge the surface crosses. None if any of the voxels is not loaded
+    //voxels the filter leaves out count as empty, whatever meshes them sits inside the gap in the surface
+    fn density(&self, neighbourhood: &ChunkNeighbourhood, filter: &VoxelFilter, position: IVec3) -> Option<f32>
+    {
+        let fill = |voxel: &Voxel| if voxel.solid && filter.contains(voxel.voxel_type) { 1.0 } else { 0.0 };
+        let center = fill(neighbourhood.voxel(position)?);
+        let mut around = 0.0;
+        for offset in NEIGHBOURS
+        {
+            around += fill(neighbourhood.voxel(position + offset)?);
+        }
+        Some(center - 0.5 + (around / 6.0 - 0.5) * self.smoothing)
+    }
+}
+
+//one sample of the density grid
+#[derive(Clone, Copy)]
+struct Sample
+{
+    density: f32,
+    voxel_type: VoxelType,
+}
+
+impl ChunkMesher for SurfaceNetsMesher
+{
+    fn mesh(&self, chunk: &Chunk, neighbourhood: &ChunkNeighbourhood, context: &MeshContext) -> ChunkMesh
+    {
+        let step = context.lod.max(1);
+        let samples = CHUNK_SIZE / step;
+        let origin = IVec3::new(chunk.pos.0, chunk.pos.1, chunk.pos.2);
+        let texture_info = &context.texture_info;
+        let mut chunk_mesh = ChunkMesh::default();
+
+        //samples -1..=samples on every axis. the chunk owns the edges starting at 0..samples, so the cells around them
+        //reach one sample into the neighbours below and the edges one sample into the neighbours above
+        let width = samples + 2;
+        let sample_index = |position: IVec3| ((position.x + 1) * width * width + (position.y + 1) * width + position.z + 1) as usize;
+        let mut grid: Vec<Option<Sample>> = Vec::with_capacity(width.pow(3) as usize);
+        for x in -1..=samples
+        {
+            for y in -1..=samples
+            {
+                for z in -1..=samples
+                {
+                    let position = IVec3::new(x, y, z) * step;
+                    grid.push(self.density(neighbourhood, &context.filter, position).map(|density|
+                    {
+                        let voxel_type = neighbourhood.voxel(position).map_or(VoxelType::Air, |voxel| voxel.voxel_type);
+                        Sample { density, voxel_type }
+                    }));
+                }
+            }
+        }
+
+        //one vertex per cell the surface passes through, at the average of where it crosses the cell edges
+        //vertices are built from whole world coordinates first so both chunks on a seam get the exact same floats
+        let cell_index = |position: IVec3| ((position.x + 1) * (width - 1) * (width - 1) + (position.y + 1) * (width - 1) + position.z + 1) as usize;
+        let mut cells: Vec<Option<(Vec3, VoxelType)>> = vec![None; (width - 1).pow(3) as usize];
+        for x in -1..samples
+        {
+            for y in -1..samples
+            {
+                for z in -1..samples
+                {
+                    let cell = IVec3::new(x, y, z);
+                    let mut corners = [Sample { density: 0.0, voxel_type: VoxelType::Air }; 8];
+                    let mut loaded = true;
+                    for (corner, sample) in corners.iter_mut().enumerate()
+                    {
+                        match grid[sample_index(cell + corner_offset(corner))]
+                        {
+                            Some(found) => *sample = found,
+                            None => loaded = false,
+                        }
+                    }
+                    if !loaded
+                    {
+                        continue;
+                    }
+                    let mut crossing_sum = Vec3::ZERO;
+                    let mut crossings = 0;
+                    for (a, b) in CELL_EDGES
+                    {
+                        let (density_a, density_b) = (corners[a].density, corners[b].density);
+                        if (density_a > 0.0) != (density_b > 0.0)
+                        {
+                            let t = density_a / (density_a - density_b);
+                            crossing_sum += corner_offset(a).as_vec3().lerp(corner_offset(b).as_vec3(), t);
+                            crossings += 1;
+                        }
+                    }
+                    if crossings == 0
+                    {
+                        continue;
+                    }
+                    let world_cell = origin + cell * step;
+                    let position = world_cell.as_vec3() + (crossing_sum / crossings as f32) * step as f32 + Vec3::splat(0.5);
+                    cells[cell_index(cell)] = Some((position, dominant_material(corners.iter().filter(|sample| sample.density > 0.0).map(|sample| sample.voxel_type))));
+                }
+            }
+        }
+
+        for x in 0..samples
+        {
+            for y in 0..samples
+            {
+                for z in 0..samples
+                {
+                    let start = IVec3::new(x, y, z);
+                    let Some(inner) = grid[sample_index(start)] else { continue };
+                    for axis in 0..3
+                    {
+                        let Some(outer) = grid[sample_index(start + IVec3::AXES[axis])] else { continue };
+                        if (inner.density > 0.0) == (outer.density > 0.0)
+                        {
+                            continue;
+                        }
+                        //the 4 cells sharing the edge, in counter clockwise order seen from the positive end of the axis
+                        let u = IVec3::AXES[(axis + 1) % 3];
+                        let v = IVec3::AXES[(axis + 2) % 3];
+                        let quad = [start - u - v, start - v, start, start - u].map(|cell| cells[cell_index(cell)]);
+                        let [Some(a), Some(b), Some(c), Some(d)] = quad else { continue };
+                        let solid = if inner.density > 0.0 { inner.voxel_type } else { outer.voxel_type };
+                        let material = dominant_material([solid, a.1, b.1, c.1, d.1].into_iter());
+                        let uv_rect = texture_info.uv_rect(material);
+                        let corners = [(a.0, [uv_rect.min.x, uv_rect.max.y]), (b.0, [uv_rect.max.x, uv_rect.max.y]), (c.0, [uv_rect.max.x, uv_rect.min.y]), (d.0, [uv_rect.min.x, uv_rect.min.y])];
+                        //faces point out of the solid side
+                        let order = if inner.density > 0.0 { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+                        for corner in order
+                        {
+                            chunk_mesh.push_vertex(material, corners[corner].0.to_array(), corners[corner].1);
+                        }
+                    }
+                }
+            }
+        }
+        chunk_mesh
+    }
+
+    //cells on the chunk border use voxels from the chunks across edges and corners as well
+    fn uses_diagonal_neighbours(&self) -> bool
+    {
+        true
+    }
+}
+
+//the most common of the materials, ties go to the one listed first so the solid voxel on a quad's edge breaks them
+fn dominant_material(materials: impl Iterator<Item = VoxelType>) -> VoxelType
+{
+    let mut counts: Vec<(VoxelType, usize)> = Vec::with_capacity(8);
+    for material in materials
+    {
+        match counts.iter_mut().find(|(counted, _)| *counted == material)
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((material, 1)),
+        }
+    }
+    let mut best = (VoxelType::Air, 0);
+    for (material, count) in counts
+    {
+        if count > best.1
+        {
+            best = (material, count);
+        }
+    }
+    best.0
+}
@@ -0,0 +1,547 @@
+use crate::database::chunk::*;
+use crate::database::edit::*;
+use crate::database::generator::*;
+use crate::database::physics::*;
+use crate::database::raycast::*;
+use crate::database::schematic::{decode_voxel, encode_voxel, invalid, VOXEL_BYTES};
+use crate::database::settings::CHUNK_SIZE;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"VSVO";
+const VERSION: u8 = 1;
+const LEAF: u8 = 0;
+const BRANCH: u8 = 1;
+//points this close to the face between two cells are on it
+const FACE_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OctreeNode
+{
+    //every voxel inside the node is the same
+    Leaf(Voxel),
+    //children are ordered by child_index, representative is what the node looks like from far away
+    Branch { children: Box<[OctreeNode; 8]>, representative: Voxel },
+}
+
+impl OctreeNode
+{
+    //collapses the children into a leaf when they are all the same leaf
+    fn branch(children: [OctreeNode; 8]) -> OctreeNode
+    {
+        if let OctreeNode::Leaf(first) = &children[0]
+        {
+            if children.iter().all(|child| matches!(child, OctreeNode::Leaf(voxel) if voxel == first))
+            {
+                return OctreeNode::Leaf(first.clone());
+            }
+        }
+        let representative = representative(children.iter().map(OctreeNode::representative));
+        OctreeNode::Branch { children: Box::new(children), representative }
+    }
+
+    pub fn representative(&self) -> &Voxel
+    {
+        match self
+        {
+            OctreeNode::Leaf(voxel) => voxel,
+            OctreeNode::Branch { representative, .. } => representative,
+        }
+    }
+
+    //number of nodes including this one
+    pub fn node_count(&self) -> usize
+    {
+        match self
+        {
+            OctreeNode::Leaf(_) => 1,
+            OctreeNode::Branch { children, .. } => 1 + children.iter().map(OctreeNode::node_count).sum::<usize>(),
+        }
+    }
+}
+
+//the most common of the voxels, air only wins when it fills more than half so thin surfaces survive sampling
+//ties go to whichever came first so the result is the same every build
+fn representative<'a>(voxels: impl Iterator<Item = &'a Voxel>) -> Voxel
+{
+    let mut counts: Vec<(&Voxel, usize)> = Vec::with_capacity(8);
+    let mut total = 0;
+    for voxel in voxels
+    {
+        total += 1;
+        match counts.iter_mut().find(|(counted, _)| *counted == voxel)
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((voxel, 1)),
+        }
+    }
+    let air = counts.iter().find(|(voxel, _)| voxel.voxel_type == VoxelType::Air).map_or(0, |(_, count)| *count);
+    if air * 2 > total
+    {
+        return Voxel::default();
+    }
+    let mut best: Option<(&Voxel, usize)> = None;
+    for (voxel, count) in counts.into_iter().filter(|(voxel, _)| voxel.voxel_type != VoxelType::Air)
+    {
+        if best.is_none_or(|(_, best_count)| count > best_count)
+        {
+            best = Some((voxel, count));
+        }
+    }
+    best.map_or_else(Voxel::default, |(voxel, _)| voxel.clone())
+}
+
+//which of the 8 children holds a position, half is the size of a child
+fn child_index(local: IVec3, half: i32) -> usize
+{
+    ((local.x >= half) as usize) << 2 | ((local.y >= half) as usize) << 1 | (local.z >= half) as usize
+}
+
+fn child_offset(index: usize, half: i32) -> IVec3
+{
+    IVec3::new((index >> 2 & 1) as i32, (index >> 1 & 1) as i32, (index & 1) as i32) * half
+}
+
+//a cube of voxels stored as an octree, uniform areas like sky and rock take up a single node
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseVoxelOctree
+{
+    //lowest corner, relative to the sector origin like chunk voxel positions
+    pub origin: IVec3,
+    //edge length in voxels, always a power of two
+    pub size: i32,
+    pub root: OctreeNode,
+}
+
+impl SparseVoxelOctree
+{
+    pub fn from_chunk(chunk: &Chunk) -> SparseVoxelOctree
+    {
+        let origin = IVec3::new(chunk.pos.0, chunk.pos.1, chunk.pos.2);
+        let root = build(IVec3::ZERO, CHUNK_SIZE, &mut |local, size| (size == 1).then(|| chunk.block_data[voxel_index(local.x, local.y, local.z)].clone()));
+        SparseVoxelOctree { origin, size: CHUNK_SIZE, root }
+    }
+
+    //builds straight from a generator, size is rounded up to a power of two
+    //regions the generator reports as uniform are never generated, single voxels are asked for directly when the
+    //generator supports it and only the rest falls back to generating whole chunks
+    pub fn from_generator(generator: &dyn WorldGenerator, origin: IVec3, size: i32) -> SparseVoxelOctree
+    {
+        let size = (size.max(1) as u32).next_power_of_two() as i32;
+        let mut chunks: HashMap<[i32; 3], Vec<Voxel>> = HashMap::new();
+        let root = build(IVec3::ZERO, size, &mut |local, region_size|
+        {
+            let position = origin + local;
+            if region_size > 1
+            {
+                return generator.uniform_region(position, region_size);
+            }
+            if let Some(voxel) = generator.voxel(position)
+            {
+                return Some(voxel);
+            }
+            let (chunk, voxel) = voxel_to_chunk(position);
+            let data = chunks.entry(chunk).or_insert_with(|| generator.generate(chunk));
+            Some(data[voxel_index(voxel[0], voxel[1], voxel[2])].clone())
+        });
+        SparseVoxelOctree { origin, size, root }
+    }
+
+    pub fn contains(&self, position: IVec3) -> bool
+    {
+        let local = position - self.origin;
+        local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(self.size)).all()
+    }
+
+    //the leaf holding a position along with its lowest corner and size
+    fn leaf(&self, position: IVec3) -> Option<(&Voxel, IVec3, i32)>
+    {
+        if !self.contains(position)
+        {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut min = self.origin;
+        let mut size = self.size;
+        while let OctreeNode::Branch { children, .. } = node
+        {
+            size /= 2;
+            let index = child_index(position - min, size);
+            min += child_offset(index, size);
+            node = &children[index];
+        }
+        match node
+        {
+            OctreeNode::Leaf(voxel) => Some((voxel, min, size)),
+            OctreeNode::Branch { .. } => None,
+        }
+    }
+
+    pub fn get(&self, position: IVec3) -> Option<&Voxel>
+    {
+        self.leaf(position).map(|(voxel, _, _)| voxel)
+    }
+
+    //the voxel seen at most depth levels below the root, depth 0 is the whole tree as one voxel
+    //each level halves the cell size so this doubles as a level of detail lookup
+    pub fn sample(&self, position: IVec3, depth: u32) -> Option<&Voxel>
+    {
+        if !self.contains(position)
+        {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut min = self.origin;
+        let mut size = self.size;
+        for _ in 0..depth
+        {
+            let OctreeNode::Branch { children, .. } = node else { break };
+            size /= 2;
+            let index = child_index(position - min, size);
+            min += child_offset(index, size);
+            node = &children[index];
+        }
+        Some(node.representative())
+    }
+
+    //same rules as raycast, but empty nodes are skipped in one step instead of voxel by voxel
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit>
+    {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO
+        {
+            return None;
+        }
+        let bounds = BoundingBox { min: self.origin.as_vec3(), max: (self.origin + self.size).as_vec3() };
+        let mut travelled = ray_box(origin, direction, &bounds)?;
+        let mut normal = IVec3::ZERO;
+        if travelled > 0.0
+        {
+            //entered from outside, the entry face is on the axis that was reached last
+            let inverse = direction.recip();
+            let near = (bounds.min - origin) * inverse;
+            let far = (bounds.max - origin) * inverse;
+            let entry = near.min(far);
+            let axis = if entry.x >= entry.y && entry.x >= entry.z { 0 } else if entry.y >= entry.z { 1 } else { 2 };
+            normal[axis] = -(direction[axis].signum() as i32);
+        }
+        let step = direction.signum();
+        while travelled <= max_distance
+        {
+            //a point on the face between two cells belongs to the one the ray moves into, floor picks the wrong one when
+            //moving backwards and rounding errors can leave it just short of the face, edges and corners sit on several faces
+            let point = origin + direction * travelled;
+            let position = IVec3::from_array(std::array::from_fn(|axis|
+            {
+                let face = point[axis].round();
+                if direction[axis] != 0.0 && (point[axis] - face).abs() <= FACE_EPSILON
+                {
+                    return face as i32 - (direction[axis] < 0.0) as i32;
+                }
+                point[axis].floor() as i32
+            }));
+            let (voxel, mut min, mut size) = self.leaf(position)?;
+            let boxes = selection_boxes(voxel, stair_shape(self, position, voxel));
+            if !boxes.is_empty()
+            {
+                let offset = position.as_vec3();
+                let hit = boxes.iter().filter_map(|bounds| ray_box(origin, direction, &bounds.offset(offset))).reduce(f32::min);
+                if let Some(distance) = hit.filter(|distance| *distance <= max_distance)
+                {
+                    return Some(RaycastHit { position, normal, distance });
+                }
+                //only this voxel was checked, step out of it rather than the whole leaf
+                min = position;
+                size = 1;
+            }
+            //leave the cell through whichever face the ray reaches first
+            let mut exit = f32::INFINITY;
+            let mut exit_axis = 0;
+            for axis in 0..3
+            {
+                if direction[axis] == 0.0
+                {
+                    continue;
+                }
+                let boundary = if step[axis] > 0.0 { (min[axis] + size) as f32 } else { min[axis] as f32 };
+                let distance = (boundary - origin[axis]) / direction[axis];
+                if distance < exit
+                {
+                    exit = distance;
+                    exit_axis = axis;
+                }
+            }
+            travelled = exit.max(travelled);
+            normal = IVec3::ZERO;
+            normal[exit_axis] = -(step[exit_axis] as i32);
+        }
+        None
+    }
+
+    //magic, version, origin, size, then every node depth first: a leaf tag and its voxel or a branch tag and its 8 children
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        for value in [self.origin.x, self.origin.y, self.origin.z, self.size]
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop()
+        {
+            match node
+            {
+                OctreeNode::Leaf(voxel) =>
+                {
+                    bytes.push(LEAF);
+                    bytes.extend_from_slice(&encode_voxel(voxel));
+                }
+                OctreeNode::Branch { children, .. } =>
+                {
+                    bytes.push(BRANCH);
+                    stack.extend(children.iter().rev());
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<SparseVoxelOctree>
+    {
+        let mut cursor = 0;
+        let mut take = |count: usize| -> std::io::Result<&[u8]>
+        {
+            let slice = bytes.get(cursor..cursor + count).ok_or_else(|| invalid("octree is truncated"))?;
+            cursor += count;
+            Ok(slice)
+        };
+        if take(4)? != MAGIC
+        {
+            return Err(invalid("not an octree"));
+        }
+        if take(1)?[0] != VERSION
+        {
+            return Err(invalid("unsupported octree version"));
+        }
+        let mut header = [0; 4];
+        for value in &mut header
+        {
+            *value = i32::from_le_bytes(take(4)?.try_into().unwrap());
+        }
+        let [x, y, z, size] = header;
+        if size <= 0 || !(size as u32).is_power_of_two()
+        {
+            return Err(invalid("octree size is not a power of two"));
+        }
+        let root = read_node(&mut take, size)?;
+        Ok(SparseVoxelOctree { origin: IVec3::new(x, y, z), size, root })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()>
+    {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<SparseVoxelOctree>
+    {
+        SparseVoxelOctree::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+impl VoxelAccess for SparseVoxelOctree
+{
+    fn voxel(&self, position: IVec3) -> Option<&Voxel>
+    {
+        self.get(position)
+    }
+}
+
+//builds the node for the cube at min, uniform returns the voxel filling a cube when it is known to be uniform
+//it is always asked for single voxels so it has to answer those
+fn build(min: IVec3, size: i32, uniform: &mut impl FnMut(IVec3, i32) -> Option<Voxel>) -> OctreeNode
+{
+    if let Some(voxel) = uniform(min, size)
+    {
+        return OctreeNode::Leaf(voxel);
+    }
+    if size == 1
+    {
+        return OctreeNode::Leaf(Voxel::default());
+    }
+    let half = size / 2;
+    let children: [OctreeNode; 8] = std::array::from_fn(|index| build(min + child_offset(index, half), half, uniform));
+    OctreeNode::branch(children)
+}
+
+fn read_node<'a>(take: &mut impl FnMut(usize) -> std::io::Result<&'a [u8]>, size: i32) -> std::io::Result<OctreeNode>
+{
+    match take(1)?[0]
+    {
+        LEAF => Ok(OctreeNode::Leaf(decode_voxel(take(VOXEL_BYTES)?)?)),
+        BRANCH if size > 1 =>
+        {
+            let mut children = Vec::with_capacity(8);
+            for _ in 0..8
+            {
+                children.push(read_node(take, size / 2)?);
+            }
+            Ok(OctreeNode::branch(children.try_into().unwrap()))
+        }
+        BRANCH => Err(invalid("octree branches below single voxels")),
+        _ => Err(invalid("unknown octree node")),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn block(voxel_type: VoxelType) -> Voxel
+    {
+        Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::Forward)
+    }
+
+    //stone up to y 15, a slab and a stair on top and a lone dirt voxel in the far corner
+    fn terrain() -> Chunk
+    {
+        let mut world = test_world([0, 0, 0], [0, 0, 0], &[
+            (IVec3::new(4, 16, 4), Voxel::new(VoxelType::Stone, VoxelVariant::Slab, VoxelRotation::Forward)),
+            (IVec3::new(6, 16, 4), Voxel::new(VoxelType::Stone, VoxelVariant::Stair, VoxelRotation::Right)),
+            (IVec3::splat(31), block(VoxelType::Dirt)),
+        ]);
+        let mut chunk = world.remove(&[0, 0, 0]).unwrap();
+        for x in 0..CHUNK_SIZE
+        {
+            for y in 0..16
+            {
+                for z in 0..CHUNK_SIZE
+                {
+                    chunk.block_data[voxel_index(x, y, z)] = block(VoxelType::Stone);
+                }
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn bytes_round_trip()
+    {
+        let octree = SparseVoxelOctree::from_chunk(&terrain());
+        let bytes = octree.to_bytes();
+        assert_eq!(SparseVoxelOctree::from_bytes(&bytes).unwrap(), octree);
+        //the uniform halves collapse into single leaves
+        assert!(octree.root.node_count() < 200);
+
+        assert!(SparseVoxelOctree::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(SparseVoxelOctree::from_bytes(&wrong_magic).is_err());
+        let mut wrong_size = bytes.clone();
+        wrong_size[17..21].copy_from_slice(&24i32.to_le_bytes());
+        assert!(SparseVoxelOctree::from_bytes(&wrong_size).is_err());
+    }
+
+    #[test]
+    fn samples_get_finer_with_depth()
+    {
+        let octree = SparseVoxelOctree::from_chunk(&terrain());
+        let corner = IVec3::splat(31);
+        //half stone is not enough air to win
+        assert_eq!(octree.sample(corner, 0), Some(&block(VoxelType::Stone)));
+        //the lone dirt voxel only shows up once its own cell is reached
+        for depth in 1..5
+        {
+            assert_eq!(octree.sample(corner, depth), Some(&Voxel::default()), "depth {depth}");
+            assert_eq!(octree.sample(IVec3::ZERO, depth), Some(&block(VoxelType::Stone)), "depth {depth}");
+        }
+        assert_eq!(octree.sample(corner, 5), Some(&block(VoxelType::Dirt)));
+        assert_eq!(octree.sample(corner, 5), octree.get(corner));
+        //deeper than the tree goes is the voxel itself
+        assert_eq!(octree.sample(IVec3::new(30, 31, 31), 9), Some(&Voxel::default()));
+        assert_eq!(octree.sample(IVec3::new(0, 32, 0), 0), None);
+    }
+
+    #[test]
+    fn generator_octrees_match_generated_chunks()
+    {
+        let noise = NoiseGenerator { seed: 7 };
+        for chunk in [[0, 1, 0], [2, 1, -1], [0, 4, 0]]
+        {
+            let pos = IVec3::from(chunk) * CHUNK_SIZE;
+            let generated = Chunk { block_data: noise.generate(chunk), pos: (pos.x, pos.y, pos.z) };
+            assert_eq!(SparseVoxelOctree::from_generator(&noise, pos, CHUNK_SIZE), SparseVoxelOctree::from_chunk(&generated));
+        }
+
+        //heightmaps answer uniform regions and single voxels without generating chunks, which has to give the same result
+        let heights = image::ImageBuffer::from_fn(64, 64, |x, z| image::Luma([((40 + (x + z) / 8) as f32 / 255.0 * u16::MAX as f32).round() as u16]));
+        let heightmap = HeightmapGenerator::new(heights);
+        let octree = SparseVoxelOctree::from_generator(&heightmap, IVec3::ZERO, 60);
+        assert_eq!(octree.size, 64);
+        for chunk_x in 0..2
+        {
+            for chunk_y in 0..2
+            {
+                for chunk_z in 0..2
+                {
+                    let data = heightmap.generate([chunk_x, chunk_y, chunk_z]);
+                    let pos = IVec3::new(chunk_x, chunk_y, chunk_z) * CHUNK_SIZE;
+                    for (index, voxel) in data.iter().enumerate()
+                    {
+                        let index = index as i32;
+                        let local = IVec3::new(index / (CHUNK_SIZE * CHUNK_SIZE), index / CHUNK_SIZE % CHUNK_SIZE, index % CHUNK_SIZE);
+                        assert_eq!(voxel_index(local.x, local.y, local.z), index as usize);
+                        assert_eq!(octree.get(pos + local), Some(voxel), "{}", pos + local);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raycasts_match_the_voxel_raycast()
+    {
+        let mut chunk = terrain();
+        chunk.block_data[voxel_index(20, 20, 20)] = block(VoxelType::Dirt);
+        let octree = SparseVoxelOctree::from_chunk(&chunk);
+        let world = HashMap::from([([0, 0, 0], chunk)]);
+        let rays = [
+            (Vec3::new(4.5, 30.5, 4.5), Vec3::NEG_Y),
+            (Vec3::new(6.2, 30.5, 4.5), Vec3::NEG_Y),
+            (Vec3::new(1.5, 17.5, 4.5), Vec3::new(1.0, -0.3, 0.0)),
+            (Vec3::new(10.5, 25.5, 10.5), Vec3::new(1.0, -0.5, 1.0)),
+            (Vec3::new(-5.0, 20.5, 20.5), Vec3::X),
+            (Vec3::new(20.5, 40.0, 20.5), Vec3::NEG_Y),
+            (Vec3::new(30.5, 30.5, 30.5), Vec3::ONE),
+            (Vec3::new(16.0, 31.0, 16.0), Vec3::new(0.3, -1.0, 0.7)),
+        ];
+        for (origin, direction) in rays
+        {
+            assert_eq!(octree.raycast(origin, direction, 64.0), raycast(&world, origin, direction, 64.0), "{origin} {direction}");
+        }
+    }
+
+    #[test]
+    fn rays_through_edges_and_corners_keep_going()
+    {
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::ZERO, block(VoxelType::Stone)), (IVec3::new(31, 31, 0), block(VoxelType::Stone))]);
+        let octree = SparseVoxelOctree::from_chunk(&world[&[0, 0, 0]]);
+        //along the edges between four cells
+        let hit = octree.raycast(Vec3::new(2.5, 2.5, 0.5), Vec3::new(-1.0, -1.0, 0.0), 8.0).unwrap();
+        assert_eq!(hit.position, IVec3::ZERO);
+        assert!((hit.distance - 1.5 * 2f32.sqrt()).abs() < 1e-4);
+        //through the corners between eight cells
+        let hit = octree.raycast(Vec3::splat(2.5), Vec3::NEG_ONE, 8.0).unwrap();
+        assert_eq!(hit.position, IVec3::ZERO);
+        //entering the tree through one of its edges
+        let hit = octree.raycast(Vec3::new(33.0, 33.0, 0.5), Vec3::new(-1.0, -1.0, 0.0), 8.0).unwrap();
+        assert_eq!(hit.position, IVec3::new(31, 31, 0));
+        //missing everything still ends
+        assert_eq!(octree.raycast(Vec3::new(2.5, 4.5, 0.5), Vec3::new(-1.0, -1.0, 0.0), 64.0), None);
+        assert_eq!(octree.raycast(Vec3::new(20.5, 20.5, 20.5), Vec3::ONE, 64.0), None);
+    }
+}
@@ -1,9 +1,15 @@
 use bevy::{prelude::*,pbr::wireframe::WireframeConfig,asset::LoadState};
 use crate::database::structs::AppState;
-use crate::database::settings::WIREFRAME;
+use crate::database::settings::{TextureMode, TEXTURE_MODE, WIREFRAME};
+use crate::database::texture_array::{build_texture_array, ATTRIBUTE_TEXTURE_ANIMATION};
+use crate::database::voxel::{block_to_tex, VoxelType};
+use bevy::utils::HashMap;
 use std::fs;
 use bevy::{asset::LoadedFolder, prelude::*};
 use bevy::app::DynEq;
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError};
 
 #[derive(Resource, Default)]
 pub struct TextureHandles
@@ -18,6 +24,52 @@ pub struct TextureAtlasBuilt
     pub texture_rects: Vec<Rect>,
     pub texture_map: Vec<usize>,
     pub texture_size: Vec2,
+    //frames of every atlas texture, animated ones are a strip of square frames with only the top one in texture_rects' uvs
+    pub animations: Vec<TextureAnimation>,
+    //the same textures as layers of a texture array, every frame of an animation gets its own layer
+    //only built for TextureMode::Array
+    pub array: Option<Handle<Image>>,
+    //array layer of the first frame of every atlas texture
+    pub array_layers: Vec<u32>,
+}
+
+//a texture made of square frames stacked top to bottom that are shown one after another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureAnimation
+{
+    pub frames: u32,
+    //seconds every frame is shown for
+    pub frame_time: f32,
+}
+
+impl Default for TextureAnimation
+{
+    fn default() -> Self
+    {
+        TextureAnimation { frames: 1, frame_time: 0.0 }
+    }
+}
+
+impl TextureAnimation
+{
+    //checks the frame layout of a texture a block flagged as animated, the frames have to be square and fill the strip
+    pub fn from_strip(name: &str, width: u32, height: u32, frame_time: f32) -> Result<TextureAnimation, String>
+    {
+        if width == 0 || !height.is_multiple_of(width)
+        {
+            return Err(format!("{} is {}x{}, animated textures need square frames stacked vertically", name, width, height));
+        }
+        if frame_time <= 0.0
+        {
+            return Err(format!("{} has a frame time of {}, it has to be above 0", name, frame_time));
+        }
+        Ok(TextureAnimation { frames: height / width, frame_time })
+    }
+
+    pub fn frame(&self, seconds: f32) -> u32
+    {
+        if self.frames <= 1 { 0 } else { (seconds / self.frame_time) as u32 % self.frames }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -26,6 +78,86 @@ pub struct TextureInfo
     pub map: Vec<usize>,
     pub rects: Vec<Rect>,
     pub size: Vec2,
+    pub mode: TextureMode,
+    //per atlas texture like TextureAtlasBuilt, empty means nothing is animated
+    pub animations: Vec<TextureAnimation>,
+    //first array layer per atlas texture, only used by TextureMode::Array
+    pub layers: Vec<u32>,
+}
+
+impl TextureInfo
+{
+    //the part of the texture a voxel type's faces map 0..1 onto, the whole layer for texture arrays
+    //animated atlas textures only map onto their first frame, VoxelAtlasMaterial moves the uvs down to the current one
+    pub fn uv_rect(&self, voxel_type: VoxelType) -> Rect
+    {
+        match self.mode
+        {
+            TextureMode::Atlas =>
+            {
+                let rect = block_to_tex(voxel_type, self.map.clone(), self.rects.clone(), self.size);
+                let frame_height = rect.height() / self.animation(voxel_type).frames as f32;
+                Rect::new(rect.min.x, rect.min.y, rect.max.x, rect.min.y + frame_height)
+            }
+            TextureMode::Array => Rect::new(0.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn layer(&self, voxel_type: VoxelType) -> u32
+    {
+        let texture = self.map[voxel_type as usize];
+        self.layers.get(texture).copied().unwrap_or(texture as u32)
+    }
+
+    pub fn animation(&self, voxel_type: VoxelType) -> TextureAnimation
+    {
+        self.map.get(voxel_type as usize).and_then(|texture| self.animations.get(*texture)).copied().unwrap_or_default()
+    }
+
+    //how far the shader moves on per frame, the next layer of a texture array or one frame height down the atlas
+    pub fn frame_step(&self, voxel_type: VoxelType) -> f32
+    {
+        match self.mode
+        {
+            TextureMode::Atlas => self.uv_rect(voxel_type).height(),
+            TextureMode::Array => 1.0,
+        }
+    }
+}
+
+//chunk material for TextureMode::Atlas, unlit like VoxelArrayMaterial. animated textures are strips in the atlas and
+//the vertex shader moves the uvs down to the current frame with the time from bevy's globals uniform
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct VoxelAtlasMaterial
+{
+    #[texture(0)]
+    #[sampler(1)]
+    pub atlas_texture: Handle<Image>,
+}
+
+impl Material for VoxelAtlasMaterial
+{
+    fn vertex_shader() -> ShaderRef
+    {
+        "shaders/voxel_atlas.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef
+    {
+        "shaders/voxel_atlas.wgsl".into()
+    }
+
+    fn specialize(_pipeline: &MaterialPipeline<Self>, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayout, _key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError>
+    {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(2),
+            ATTRIBUTE_TEXTURE_ANIMATION.at_shader_location(3),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
 }
 
 #[derive(Resource, Default)]
@@ -41,7 +173,6 @@ pub fn build_texture_atlas(
     texture_folder: Res<TextureFolder>,
     mut texture_atlas_data : ResMut<TextureAtlasBuilt>,
     mut next_state: ResMut<NextState<AppState>>,
-    mut commands: Commands,
     asset_server: Res<AssetServer>,
     loaded_folders: Res<Assets<LoadedFolder>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
@@ -56,10 +187,11 @@ pub fn build_texture_atlas(
 
     let mut texture_atlas_builder = TextureAtlasBuilder::default();
     let loaded_folder = loaded_folders.get(&texture_folder.0).unwrap();
-    let mut texture_map: Vec<usize> = vec![0; loaded_folder.handles.len()+1];
-    for (handle_iter,handle) in loaded_folder.handles.iter().enumerate() {
+    //handles that did not resolve to an image are left out of the atlas and everything indexed like it
+    let mut resolved: Vec<&UntypedHandle> = Vec::with_capacity(loaded_folder.handles.len());
+    for handle in loaded_folder.handles.iter() {
         let id = handle.id().typed_unchecked::<Image>();
-        let Some(texture) = textures.get(id.clone()) else {
+        let Some(texture) = textures.get(id) else {
             warn!(
                 "{:?} did not resolve to an `Image` asset.",
                 handle.path().unwrap()
@@ -67,32 +199,67 @@ pub fn build_texture_atlas(
             continue;
         };
         texture_atlas_builder.add_texture(id, texture);
-        //load texs into map
-        let handle_clone = handle.clone();
-        for (texture_iter, line) in fs::read_to_string("Assets/TexMem").unwrap().lines().enumerate() {
-            let expected_texture_asset_path = handle_clone.path();
-            let expected_texture_path = expected_texture_asset_path.unwrap().path();
-            let expected_texture_file_name = expected_texture_path.file_name().unwrap();
-            let expected_texture_str = expected_texture_file_name.to_string_lossy();
-            let expected_texture_name = expected_texture_str.split(".png").next().unwrap();
-            let current_texture_name = line;
-            if current_texture_name == expected_texture_name
-            {
-                texture_map[texture_iter] = handle_iter;
-            }
+        resolved.push(handle);
+    }
+    let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+    //the packer does not keep the order textures were added in, so the map, animations and array layers all follow
+    //the atlas rects from here on
+    resolved.sort_by_key(|handle| texture_atlas.get_texture_index(handle.id().typed_unchecked::<Image>()));
+
+    //load texs into map
+    let mut texture_map: Vec<usize> = vec![0; loaded_folder.handles.len()+1];
+    for (texture_iter, line) in fs::read_to_string("Assets/TexMem").unwrap().lines().enumerate() {
+        let texture = resolved.iter().position(|handle| texture_name(handle) == line);
+        if let (Some(texture), Some(entry)) = (texture, texture_map.get_mut(texture_iter))
+        {
+            *entry = texture;
         }
     }
 
-    let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+    //blocks flagged as animated in BlockMem, a texture with a broken frame layout stays a still image
+    let frame_times = fs::read_to_string("Assets/BlockMem").map(|block_mem| frame_times(&block_mem)).unwrap_or_default();
+    let animations: Vec<TextureAnimation> = resolved.iter().map(|handle|
+    {
+        let name = texture_name(handle);
+        let Some(frame_time) = frame_times.get(&name) else { return TextureAnimation::default() };
+        let size = textures.get(handle.id().typed_unchecked::<Image>()).map_or(UVec2::ZERO, |texture| texture.size());
+        TextureAnimation::from_strip(&name, size.x, size.y, *frame_time).unwrap_or_else(|error|
+        {
+            warn!("{}, it will not be animated", error);
+            TextureAnimation::default()
+        })
+    }).collect();
+
     let texture_atlas_texture = texture_atlas.texture.clone();
     let atlas_handle = texture_atlases.add(texture_atlas.clone());
     texture_atlas_data.tex = texture_atlas.texture.clone();
     texture_atlas_data.texture_rects = texture_atlas.textures.clone();
     texture_atlas_data.texture_map = texture_map;
     texture_atlas_data.texture_size = texture_atlas.size;
+    texture_atlas_data.animations = animations.clone();
 
-
-
+    //layers in the same order as the atlas so texture_map works for both
+    if TEXTURE_MODE == TextureMode::Array
+    {
+        let layers: Vec<(String, &Image, u32)> = resolved.iter().zip(&animations).filter_map(|(handle, animation)|
+        {
+            textures.get(handle.id().typed_unchecked::<Image>()).map(|texture| (texture_name(handle), texture, animation.frames))
+        }).collect();
+        match build_texture_array(&layers)
+        {
+            Ok(array) =>
+            {
+                texture_atlas_data.array = Some(textures.add(array));
+                texture_atlas_data.array_layers = animations.iter().scan(0, |layer, animation|
+                {
+                    let first = *layer;
+                    *layer += animation.frames;
+                    Some(first)
+                }).collect();
+            }
+            Err(error) => warn!("could not build the block texture array: {}, using the atlas", error),
+        }
+    }
 
     //assign texture atlas
     //texture
@@ -102,6 +269,34 @@ pub fn build_texture_atlas(
 
 
 
+//file name without the extension
+fn texture_name(handle: &UntypedHandle) -> String
+{
+    handle.path().and_then(|path| path.path().file_stem()).map_or_else(String::new, |stem| stem.to_string_lossy().to_string())
+}
+
+//texture name to frame time from BlockMem. lines are blockid:top,bottom,front,left,back,right with an optional
+//:frame_time in seconds after them that makes every texture the block uses animated
+pub fn frame_times(block_mem: &str) -> HashMap<String, f32>
+{
+    let mut frame_times = HashMap::new();
+    for line in block_mem.lines().skip(1)
+    {
+        let mut fields = line.split(':');
+        let (Some(_), Some(faces), Some(frame_time)) = (fields.next(), fields.next(), fields.next()) else { continue };
+        let Ok(frame_time) = frame_time.trim().parse::<f32>() else
+        {
+            warn!("{} in BlockMem is not a frame time", frame_time);
+            continue;
+        };
+        for face in faces.split(',')
+        {
+            frame_times.insert(face.trim().to_string(), frame_time);
+        }
+    }
+    frame_times
+}
+
 pub fn check_textures(
     mut next_state: ResMut<NextState<AppState>>,
     textures_folder: ResMut<TextureFolder>,
@@ -113,4 +308,53 @@ pub fn check_textures(
             next_state.set(AppState::Finished);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn animated_textures_are_square_frames_with_a_frame_time()
+    {
+        assert_eq!(TextureAnimation::from_strip("lava", 16, 64, 0.25), Ok(TextureAnimation { frames: 4, frame_time: 0.25 }));
+        assert_eq!(TextureAnimation::from_strip("lava", 16, 16, 0.25), Ok(TextureAnimation { frames: 1, frame_time: 0.25 }));
+        //frames that are not square or do not fill the strip
+        assert!(TextureAnimation::from_strip("lava", 16, 40, 0.25).unwrap_err().contains("lava is 16x40"));
+        assert!(TextureAnimation::from_strip("lava", 32, 16, 0.25).is_err());
+        assert!(TextureAnimation::from_strip("lava", 0, 0, 0.25).is_err());
+        //frames have to be shown for some time
+        assert!(TextureAnimation::from_strip("water", 16, 64, 0.0).unwrap_err().contains("water has a frame time of 0"));
+        assert!(TextureAnimation::from_strip("water", 16, 64, -1.0).is_err());
+    }
+
+    #[test]
+    fn frames_loop_over_time()
+    {
+        let animation = TextureAnimation { frames: 4, frame_time: 0.5 };
+        let frames: Vec<u32> = [0.0, 0.49, 0.5, 1.0, 1.99, 2.0, 5.2].iter().map(|seconds| animation.frame(*seconds)).collect();
+        assert_eq!(frames, vec![0, 0, 1, 2, 3, 0, 2]);
+        //still textures never move on
+        assert_eq!(TextureAnimation::default().frame(10.0), 0);
+        assert_eq!(TextureAnimation { frames: 1, frame_time: 0.5 }.frame(10.0), 0);
+    }
+
+    #[test]
+    fn frame_times_come_from_block_mem()
+    {
+        let block_mem = "blockid:top,bottom,front,left,back,right:frame_time\n\
+            stone:stone,stone,stone,stone,stone,stone\n\
+            lava:lava,lava,lava,lava,lava,lava:0.25\n\
+            water:water_top, water_side:0.5\n\
+            brick:brick,brick,brick,brick,brick,brick:fast";
+        let frame_times = frame_times(block_mem);
+        //the header is not a block, still blocks and frame times that are not numbers are left out
+        assert_eq!(frame_times.len(), 3);
+        assert_eq!(frame_times.get("lava"), Some(&0.25));
+        assert_eq!(frame_times.get("water_top"), Some(&0.5));
+        assert_eq!(frame_times.get("water_side"), Some(&0.5));
+        assert!(!frame_times.contains_key("stone"));
+        assert!(!frame_times.contains_key("brick"));
+        assert!(!frame_times.contains_key("top"));
+    }
+}
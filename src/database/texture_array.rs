@@ -0,0 +1,309 @@
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayout};
+use bevy::render::render_resource::{AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension, VertexFormat};
+use bevy::render::texture::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor};
+
+//texture array layer of every vertex, read by VoxelArrayMaterial
+pub const ATTRIBUTE_TEXTURE_LAYER: MeshVertexAttribute = MeshVertexAttribute::new("TextureLayer", 988_540_917, VertexFormat::Uint32);
+//frame count, seconds per frame and how far apart frames are, the shaders move on by the current frame so chunks are
+//never remeshed to animate. frames are a layer apart in the array and a frame height in uv apart in the atlas
+pub const ATTRIBUTE_TEXTURE_ANIMATION: MeshVertexAttribute = MeshVertexAttribute::new("TextureAnimation", 988_540_918, VertexFormat::Float32x3);
+
+//chunk material for TextureMode::Array, samples the layer each vertex names. animated textures step through the layers
+//after it with the time from bevy's globals uniform
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct VoxelArrayMaterial
+{
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub array_texture: Handle<Image>,
+}
+
+impl Material for VoxelArrayMaterial
+{
+    fn vertex_shader() -> ShaderRef
+    {
+        "shaders/voxel_array.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef
+    {
+        "shaders/voxel_array.wgsl".into()
+    }
+
+    fn specialize(_pipeline: &MaterialPipeline<Self>, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayout, _key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError>
+    {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(2),
+            ATTRIBUTE_TEXTURE_LAYER.at_shader_location(3),
+            ATTRIBUTE_TEXTURE_ANIMATION.at_shader_location(4),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
+
+//stacks the textures into the layers of one array image with a full mip chain, every frame of an animated texture
+//becomes a layer of its own. layers take the most common frame size and textures of any other size are resized to it,
+//every texture has to be a single rgba8 image whose height splits into its frames, the error names the first that is not
+pub fn build_texture_array(textures: &[(String, &Image, u32)]) -> Result<Image, String>
+{
+    let Some((first_name, first, _)) = textures.first() else { return Err("there are no textures to build a texture array from".to_string()) };
+    let format = first.texture_descriptor.format;
+    if !matches!(format, TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm)
+    {
+        return Err(format!("texture arrays need rgba8 textures, {} is {:?}", first_name, format));
+    }
+    let mut frame_sizes: Vec<(UVec2, usize)> = Vec::new();
+    for (name, texture, frames) in textures
+    {
+        let texture_size = texture.texture_descriptor.size;
+        if texture.texture_descriptor.format != format || texture_size.depth_or_array_layers != 1
+        {
+            return Err(format!("{} is not a single {:?} texture like the other layers", name, format));
+        }
+        if *frames == 0 || texture_size.width == 0 || texture_size.height == 0 || !texture_size.height.is_multiple_of(*frames)
+        {
+            return Err(format!("{} is {}x{} which does not split into {} frames", name, texture_size.width, texture_size.height, frames));
+        }
+        let frame_size = UVec2::new(texture_size.width, texture_size.height / frames);
+        match frame_sizes.iter_mut().find(|(size, _)| *size == frame_size)
+        {
+            Some((_, count)) => *count += 1,
+            None => frame_sizes.push((frame_size, 1)),
+        }
+    }
+    //ties go to the size that came first
+    let mut size = frame_sizes[0].0;
+    let mut most = 0;
+    for (frame_size, count) in frame_sizes
+    {
+        if count > most
+        {
+            (size, most) = (frame_size, count);
+        }
+    }
+
+    //wgpu wants the data layer by layer, every layer followed by its smaller mip levels
+    let mip_levels = size.x.max(size.y).ilog2() + 1;
+    let mut data = Vec::new();
+    let mut layers = 0;
+    for (name, texture, frames) in textures
+    {
+        let texture_size = texture.texture_descriptor.size;
+        let frame_size = UVec2::new(texture_size.width, texture_size.height / frames);
+        if frame_size != size
+        {
+            warn!("{} is {}x{}, resizing it to the {}x{} of the texture array", name, frame_size.x, frame_size.y, size.x, size.y);
+        }
+        for frame in texture.data.chunks_exact((frame_size.x * frame_size.y * 4) as usize).take(*frames as usize)
+        {
+            let mut level = if frame_size == size { frame.to_vec() } else { resize(frame, frame_size, size) };
+            let (mut width, mut height) = (size.x, size.y);
+            data.extend_from_slice(&level);
+            for _ in 1..mip_levels
+            {
+                (level, width, height) = downsample(&level, width, height);
+                data.extend_from_slice(&level);
+            }
+            layers += 1;
+        }
+    }
+
+    //Image::new only takes data for the first mip level
+    let mut image = Image { data, ..default() };
+    image.texture_descriptor.size = Extent3d { width: size.x, height: size.y, depth_or_array_layers: layers };
+    image.texture_descriptor.dimension = TextureDimension::D2;
+    image.texture_descriptor.format = format;
+    image.texture_descriptor.mip_level_count = mip_levels;
+    image.texture_view_descriptor = Some(TextureViewDescriptor { dimension: Some(TextureViewDimension::D2Array), ..default() });
+    //pixelated up close like the atlas, smooth between mip levels so distant blocks do not shimmer
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor
+    {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Nearest,
+        min_filter: ImageFilterMode::Nearest,
+        mipmap_filter: ImageFilterMode::Linear,
+        ..default()
+    });
+    Ok(image)
+}
+
+//an rgba8 image at another size, every pixel is the average of the pixels it covers or the one it sits on when growing
+fn resize(pixels: &[u8], from: UVec2, to: UVec2) -> Vec<u8>
+{
+    //the source pixels covering one target pixel along an axis, at least one
+    let span = |target: u32, from: u32, to: u32|
+    {
+        let start = (target as u64 * from as u64 / to as u64) as u32;
+        let end = ((target as u64 + 1) * from as u64 / to as u64) as u32;
+        start..end.max(start + 1)
+    };
+    let mut resized = Vec::with_capacity((to.x * to.y * 4) as usize);
+    for y in 0..to.y
+    {
+        for x in 0..to.x
+        {
+            let (columns, rows) = (span(x, from.x, to.x), span(y, from.y, to.y));
+            let count = columns.len() as u32 * rows.len() as u32;
+            for channel in 0..4
+            {
+                let mut sum = 0;
+                for source_y in rows.clone()
+                {
+                    for source_x in columns.clone()
+                    {
+                        sum += pixels[((source_y * from.x + source_x) * 4 + channel) as usize] as u32;
+                    }
+                }
+                resized.push((sum / count) as u8);
+            }
+        }
+    }
+    resized
+}
+
+//the next mip level of an rgba8 image, every pixel is the average of the 2x2 pixels above it
+fn downsample(pixels: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32)
+{
+    let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+    let mut next = Vec::with_capacity((next_width * next_height * 4) as usize);
+    for y in 0..next_height
+    {
+        for x in 0..next_width
+        {
+            for channel in 0..4
+            {
+                let mut sum = 0;
+                for (source_x, source_y) in [(x * 2, y * 2), (x * 2 + 1, y * 2), (x * 2, y * 2 + 1), (x * 2 + 1, y * 2 + 1)]
+                {
+                    let (source_x, source_y) = (source_x.min(width - 1), source_y.min(height - 1));
+                    sum += pixels[((source_y * width + source_x) * 4 + channel) as usize] as u32;
+                }
+                next.push((sum / 4) as u8);
+            }
+        }
+    }
+    (next, next_width, next_height)
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    //a texture of solid colour frames stacked top to bottom
+    fn strip(width: u32, frames: &[[u8; 4]]) -> Image
+    {
+        let data = frames.iter().flat_map(|colour| colour.repeat((width * width) as usize)).collect();
+        Image::new(Extent3d { width, height: width * frames.len() as u32, depth_or_array_layers: 1 }, TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb)
+    }
+
+    fn build(textures: &[(&str, &Image, u32)]) -> Result<Image, String>
+    {
+        let textures: Vec<(String, &Image, u32)> = textures.iter().map(|(name, image, frames)| (name.to_string(), *image, *frames)).collect();
+        build_texture_array(&textures)
+    }
+
+    //the bytes of one mip level of one layer
+    fn level(array: &Image, layer: usize, level: u32) -> &[u8]
+    {
+        let size = array.texture_descriptor.size;
+        let level_bytes: Vec<usize> = (0..array.texture_descriptor.mip_level_count).map(|level| ((size.width >> level).max(1) * (size.height >> level).max(1) * 4) as usize).collect();
+        let start = layer * level_bytes.iter().sum::<usize>() + level_bytes[..level as usize].iter().sum::<usize>();
+        &array.data[start..start + level_bytes[level as usize]]
+    }
+
+    #[test]
+    fn layers_and_mip_chain()
+    {
+        let red = strip(16, &[[255, 0, 0, 255]]);
+        let frames = strip(16, &[[0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]]);
+        let array = build(&[("red", &red, 1), ("frames", &frames, 3)]).unwrap();
+        //every frame is a layer of its own
+        assert_eq!(array.texture_descriptor.size, Extent3d { width: 16, height: 16, depth_or_array_layers: 4 });
+        //16, 8, 4, 2 and 1
+        assert_eq!(array.texture_descriptor.mip_level_count, 5);
+        assert_eq!(array.data.len(), 4 * (16 * 16 + 8 * 8 + 4 * 4 + 2 * 2 + 1) * 4);
+        for (layer, colour) in [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]].iter().enumerate()
+        {
+            for mip in 0..5
+            {
+                assert!(level(&array, layer, mip).chunks_exact(4).all(|pixel| pixel == colour), "layer {layer} level {mip}");
+            }
+        }
+        //rectangular layers run down to 1 on the long side
+        let wide = Image::new_fill(Extent3d { width: 8, height: 2, depth_or_array_layers: 1 }, TextureDimension::D2, &[1, 2, 3, 4], TextureFormat::Rgba8Unorm);
+        assert_eq!(build(&[("wide", &wide, 1)]).unwrap().texture_descriptor.mip_level_count, 4);
+    }
+
+    #[test]
+    fn odd_sizes_are_resized_to_the_most_common_one()
+    {
+        let small = strip(16, &[[10, 20, 30, 255]]);
+        let big = strip(256, &[[40, 50, 60, 255]]);
+        let thin = Image::new_fill(Extent3d { width: 1, height: 25, depth_or_array_layers: 1 }, TextureDimension::D2, &[70, 80, 90, 255], TextureFormat::Rgba8UnormSrgb);
+        let frames = strip(32, &[[1, 1, 1, 255], [2, 2, 2, 255]]);
+        let array = build(&[("big", &big, 1), ("small", &small, 1), ("thin", &thin, 1), ("small again", &small, 1), ("frames", &frames, 2)]).unwrap();
+        assert_eq!(array.texture_descriptor.size, Extent3d { width: 16, height: 16, depth_or_array_layers: 6 });
+        for (layer, colour) in [[40, 50, 60, 255], [10, 20, 30, 255], [70, 80, 90, 255], [10, 20, 30, 255], [1, 1, 1, 255], [2, 2, 2, 255]].iter().enumerate()
+        {
+            assert!(level(&array, layer, 0).chunks_exact(4).all(|pixel| pixel == colour), "layer {layer}");
+        }
+    }
+
+    #[test]
+    fn broken_textures_are_named()
+    {
+        assert!(build(&[]).is_err());
+        let float = Image::new_fill(Extent3d { width: 16, height: 16, depth_or_array_layers: 1 }, TextureDimension::D2, &[0; 16], TextureFormat::Rgba32Float);
+        assert!(build(&[("float", &float, 1)]).unwrap_err().contains("float"));
+        let srgb = strip(16, &[[0; 4]]);
+        let linear = Image::new_fill(Extent3d { width: 16, height: 16, depth_or_array_layers: 1 }, TextureDimension::D2, &[0; 4], TextureFormat::Rgba8Unorm);
+        assert!(build(&[("srgb", &srgb, 1), ("linear", &linear, 1)]).unwrap_err().contains("linear"));
+        let stack = Image::new_fill(Extent3d { width: 16, height: 16, depth_or_array_layers: 2 }, TextureDimension::D2, &[0; 4], TextureFormat::Rgba8UnormSrgb);
+        assert!(build(&[("srgb", &srgb, 1), ("stack", &stack, 1)]).unwrap_err().contains("stack"));
+        let uneven = strip(16, &[[0; 4], [0; 4]]);
+        assert!(build(&[("srgb", &srgb, 1), ("uneven", &uneven, 3)]).unwrap_err().contains("uneven"));
+    }
+
+    #[test]
+    fn downsampling_averages_2x2_blocks()
+    {
+        let pixels = [[0, 0, 0, 255], [100, 0, 0, 255], [0, 200, 0, 255], [0, 0, 40, 255]].concat();
+        assert_eq!(downsample(&pixels, 2, 2), (vec![25, 50, 10, 255], 1, 1));
+        //odd edges reuse the last pixel
+        let row = [[0, 0, 0, 0], [40, 40, 40, 40], [80, 80, 80, 80]].concat();
+        assert_eq!(downsample(&row, 3, 1), (vec![20, 20, 20, 20], 1, 1));
+        assert_eq!(downsample(&[9, 9, 9, 9], 1, 1), (vec![9, 9, 9, 9], 1, 1));
+        //growing picks the nearest pixel, shrinking averages
+        assert_eq!(resize(&[1, 2, 3, 4], UVec2::ONE, UVec2::new(2, 1)), vec![1, 2, 3, 4, 1, 2, 3, 4]);
+        assert_eq!(resize(&row, UVec2::new(3, 1), UVec2::ONE), vec![40, 40, 40, 40]);
+    }
+
+    #[test]
+    fn shipped_textures_build_an_array()
+    {
+        let mut textures = Vec::new();
+        for entry in std::fs::read_dir("Assets/textures/blocks").unwrap()
+        {
+            let path = entry.unwrap().path();
+            let bytes = std::fs::read(&path).unwrap();
+            let image = Image::from_buffer(&bytes, bevy::render::texture::ImageType::Extension("png"), bevy::render::texture::CompressedImageFormats::NONE, true, ImageSampler::Default).unwrap();
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            //lava and water are the animated strips from BlockMem
+            let frames = if name == "lava" || name == "water" { 4 } else { 1 };
+            textures.push((name, image, frames));
+        }
+        textures.sort_by(|a, b| a.0.cmp(&b.0));
+        let layers: Vec<(String, &Image, u32)> = textures.iter().map(|(name, image, frames)| (name.clone(), image, *frames)).collect();
+        let array = build_texture_array(&layers).unwrap();
+        assert_eq!((array.texture_descriptor.size.width, array.texture_descriptor.size.height), (16, 16));
+        assert_eq!(array.texture_descriptor.size.depth_or_array_layers, textures.len() as u32 + 6);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::database::schematic::Schematic;
+use crate::database::texture::TextureAtlasBuilt;
+use crate::database::voxel::*;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+//one model from a MagicaVoxel file, positions are in the file's z up coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxModel
+{
+    pub size: IVec3,
+    //position and palette index, indices go from 1 to 255
+    pub voxels: Vec<(IVec3, u8)>,
+}
+
+//models and palette of a .vox file. the scene graph (nTRN, nGRP, nSHP) is not read, every model stands on its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxFile
+{
+    pub models: Vec<VoxModel>,
+    //rgba for every palette index, index 0 is never used by voxels
+    pub palette: [[u8; 4]; 256],
+}
+
+fn invalid(message: &str) -> Error
+{
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_i32(bytes: &[u8], at: usize) -> std::io::Result<i32>
+{
+    let slice = at.checked_add(4).and_then(|end| bytes.get(at..end)).ok_or_else(|| invalid("vox file is truncated"))?;
+    Ok(i32::from_le_bytes(slice.try_into().unwrap()))
+}
+
+//a chunk size or count, which the format stores as i32 but can never be negative
+fn read_size(bytes: &[u8], at: usize) -> std::io::Result<usize>
+{
+    usize::try_from(read_i32(bytes, at)?).map_err(|_| invalid("negative size in vox file"))
+}
+
+//offsets come from sizes in the file, so adding them up can overflow on a broken one
+fn offset(parts: &[usize]) -> std::io::Result<usize>
+{
+    parts.iter().try_fold(0usize, |sum, part| sum.checked_add(*part)).ok_or_else(|| invalid("vox chunk sizes overflow"))
+}
+
+//palette MagicaVoxel uses when a file has no RGBA chunk
+//a 6 level colour cube without black, then ramps of red, green, blue and grey
+pub fn default_vox_palette() -> [[u8; 4]; 256]
+{
+    const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut palette = [[0, 0, 0, 0]; 256];
+    let mut index = 1;
+    for r in CUBE
+    {
+        for g in CUBE
+        {
+            for b in CUBE
+            {
+                if r == 0 && g == 0 && b == 0
+                {
+                    continue;
+                }
+                palette[index] = [r, g, b, 0xff];
+                index += 1;
+            }
+        }
+    }
+    for channel in 0..4
+    {
+        for value in RAMP
+        {
+            palette[index] = match channel
+            {
+                0 => [value, 0, 0, 0xff],
+                1 => [0, value, 0, 0xff],
+                2 => [0, 0, value, 0xff],
+                _ => [value, value, value, 0xff],
+            };
+            index += 1;
+        }
+    }
+    palette
+}
+
+impl VoxFile
+{
+    pub fn parse(bytes: &[u8]) -> std::io::Result<VoxFile>
+    {
+        if bytes.get(0..4) != Some(b"VOX ")
+        {
+            return Err(invalid("not a vox file"));
+        }
+        if bytes.get(8..12) != Some(b"MAIN")
+        {
+            return Err(invalid("vox file has no MAIN chunk"));
+        }
+        let mut file = VoxFile { models: Vec::new(), palette: default_vox_palette() };
+        let main_content = read_size(bytes, 12)?;
+        let main_children = read_size(bytes, 16)?;
+        let end = offset(&[20, main_content, main_children])?.min(bytes.len());
+        let mut cursor = offset(&[20, main_content])?;
+        //SIZE is always followed by the XYZI chunk of the same model
+        let mut size = None;
+        while cursor.checked_add(12).is_some_and(|header| header <= end)
+        {
+            let id = &bytes[cursor..cursor + 4];
+            let content_size = read_size(bytes, cursor + 4)?;
+            let children_size = read_size(bytes, cursor + 8)?;
+            let content = bytes.get(cursor + 12..offset(&[cursor, 12, content_size])?).ok_or_else(|| invalid("vox chunk is truncated"))?;
+            match id
+            {
+                b"SIZE" =>
+                {
+                    let model_size = IVec3::new(read_i32(content, 0)?, read_i32(content, 4)?, read_i32(content, 8)?);
+                    if model_size.cmplt(IVec3::ZERO).any()
+                    {
+                        return Err(invalid("negative model size in vox file"));
+                    }
+                    size = Some(model_size);
+                }
+                b"XYZI" =>
+                {
+                    let size = size.take().ok_or_else(|| invalid("XYZI chunk without SIZE"))?;
+                    let count = read_size(content, 0)?;
+                    let data_end = count.checked_mul(4).and_then(|length| length.checked_add(4)).ok_or_else(|| invalid("vox chunk sizes overflow"))?;
+                    let data = content.get(4..data_end).ok_or_else(|| invalid("XYZI chunk is truncated"))?;
+                    let voxels = data.chunks_exact(4).map(|voxel| (IVec3::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32), voxel[3])).collect();
+                    file.models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" =>
+                {
+                    //colour i of the chunk belongs to palette index i + 1
+                    for (index, colour) in content.chunks_exact(4).take(255).enumerate()
+                    {
+                        file.palette[index + 1] = [colour[0], colour[1], colour[2], colour[3]];
+                    }
+                }
+                _ => {}
+            }
+            cursor = offset(&[cursor, 12, content_size, children_size])?;
+        }
+        Ok(file)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<VoxFile>
+    {
+        VoxFile::parse(&std::fs::read(path)?)
+    }
+
+    //turns a model into a schematic that can be pasted. the file's z up axis becomes y and its y axis turns into -z
+    //so the model keeps its handedness. an error if there is no such model or it is too big for a schematic
+    pub fn to_schematic(&self, model: usize, materials: &VoxMaterialMap) -> std::io::Result<Schematic>
+    {
+        let model = self.models.get(model).ok_or_else(|| invalid("vox file has no such model"))?;
+        let mut schematic = Schematic::with_size(IVec3::new(model.size.x, model.size.z, model.size.y))?;
+        for (position, colour_index) in &model.voxels
+        {
+            let target = IVec3::new(position.x, position.z, model.size.y - 1 - position.y);
+            if target.cmplt(IVec3::ZERO).any() || target.cmpge(schematic.size).any()
+            {
+                continue;
+            }
+            let voxel_type = materials.voxel_type(*colour_index, self.palette[*colour_index as usize]);
+            let index = schematic.index(target);
+            schematic.voxels[index] = Voxel::new(voxel_type, VoxelVariant::Block, VoxelRotation::default());
+        }
+        Ok(schematic)
+    }
+
+    //every model as its own schematic
+    pub fn to_schematics(&self, materials: &VoxMaterialMap) -> std::io::Result<Vec<Schematic>>
+    {
+        (0..self.models.len()).map(|model| self.to_schematic(model, materials)).collect()
+    }
+}
+
+//decides which voxel type a palette colour turns into
+//palette indices in the table win, everything else goes to the voxel type with the closest colour
+#[derive(Debug, Clone, Default)]
+pub struct VoxMaterialMap
+{
+    pub table: HashMap<u8, VoxelType>,
+    //reference colour of each voxel type that can be picked, usually the average of its texture
+    pub colours: Vec<(VoxelType, Vec3)>,
+}
+
+impl VoxMaterialMap
+{
+    pub fn voxel_type(&self, index: u8, colour: [u8; 4]) -> VoxelType
+    {
+        if let Some(voxel_type) = self.table.get(&index)
+        {
+            return *voxel_type;
+        }
+        let colour = Vec3::new(colour[0] as f32, colour[1] as f32, colour[2] as f32) / 255.0;
+        self.colours.iter()
+            .min_by(|(_, a), (_, b)| a.distance_squared(colour).total_cmp(&b.distance_squared(colour)))
+            .map_or(VoxelType::Stone, |(voxel_type, _)| *voxel_type)
+    }
+
+    //reference colours from the average of every block texture in the atlas, air and fluids are left out
+    pub fn from_atlas(atlas: &TextureAtlasBuilt, images: &Assets<Image>) -> VoxMaterialMap
+    {
+        let mut map = VoxMaterialMap::default();
+        let Some(image) = images.get(&atlas.tex) else { return map };
+        let width = image.texture_descriptor.size.width as usize;
+        for voxel_type in VoxelType::ALL
+        {
+            if matches!(voxel_type, VoxelType::Air | VoxelType::Water | VoxelType::Lava)
+            {
+                continue;
+            }
+            let Some(rect) = atlas.texture_map.get(voxel_type as usize).and_then(|texture| atlas.texture_rects.get(*texture)) else { continue };
+            let mut sum = Vec3::ZERO;
+            let mut weight = 0.0;
+            for y in rect.min.y as usize..rect.max.y as usize
+            {
+                for x in rect.min.x as usize..rect.max.x as usize
+                {
+                    let Some(pixel) = image.data.get((y * width + x) * 4..(y * width + x) * 4 + 4) else { continue };
+                    //see-through pixels count less so glass is matched by its frame
+                    let alpha = pixel[3] as f32 / 255.0;
+                    sum += Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) / 255.0 * alpha;
+                    weight += alpha;
+                }
+            }
+            if weight > 0.0
+            {
+                map.colours.push((voxel_type, sum / weight));
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn fixture(name: &str) -> std::io::Result<VoxFile>
+    {
+        VoxFile::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vox").join(name))
+    }
+
+    fn error(name: &str) -> String
+    {
+        fixture(name).expect_err(name).to_string()
+    }
+
+    #[test]
+    fn reads_models_and_palette()
+    {
+        let file = fixture("two_models.vox").unwrap();
+        assert_eq!(file.models.len(), 2);
+        assert_eq!(file.models[0].size, IVec3::new(2, 3, 4));
+        assert_eq!(file.models[0].voxels, vec![(IVec3::new(0, 0, 0), 1), (IVec3::new(1, 2, 3), 2), (IVec3::new(0, 1, 0), 3)]);
+        assert_eq!(file.models[1], VoxModel { size: IVec3::ONE, voxels: vec![(IVec3::ZERO, 2)] });
+        assert_eq!(file.palette[1], [255, 0, 0, 255]);
+        assert_eq!(file.palette[2], [0, 255, 0, 255]);
+        assert_eq!(file.palette[3], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn falls_back_to_the_default_palette()
+    {
+        let file = fixture("default_palette.vox").unwrap();
+        assert_eq!(file.palette, default_vox_palette());
+        assert_eq!(file.models[0].voxels, vec![(IVec3::ZERO, 1), (IVec3::new(0, 0, 1), 255)]);
+    }
+
+    #[test]
+    fn turns_z_up_into_y_up()
+    {
+        let file = fixture("two_models.vox").unwrap();
+        let materials = VoxMaterialMap
+        {
+            table: HashMap::from([(2, VoxelType::Stone)]),
+            colours: vec![(VoxelType::RedSand, Vec3::X), (VoxelType::Grass, Vec3::Y), (VoxelType::Ice, Vec3::Z)],
+        };
+        let schematic = file.to_schematic(0, &materials).unwrap();
+        assert_eq!(schematic.size, IVec3::new(2, 4, 3));
+        let types: Vec<(IVec3, VoxelType)> = schematic.iter()
+            .filter(|(_, voxel)| voxel.voxel_type != VoxelType::Air)
+            .map(|(position, voxel)| (position, voxel.voxel_type))
+            .collect();
+        assert_eq!(types.len(), 3);
+        assert!(types.contains(&(IVec3::new(0, 0, 2), VoxelType::RedSand)));
+        assert!(types.contains(&(IVec3::new(1, 3, 0), VoxelType::Stone)));
+        assert!(types.contains(&(IVec3::new(0, 0, 1), VoxelType::Ice)));
+        assert_eq!(file.to_schematics(&materials).unwrap().len(), 2);
+        assert!(file.to_schematic(2, &materials).is_err());
+    }
+
+    #[test]
+    fn rejects_broken_files()
+    {
+        assert_eq!(error("truncated.vox"), "vox chunk is truncated");
+        assert_eq!(error("negative_model_size.vox"), "negative model size in vox file");
+        assert_eq!(error("negative_chunk_size.vox"), "negative size in vox file");
+        assert_eq!(error("negative_voxel_count.vox"), "negative size in vox file");
+        assert_eq!(error("huge_voxel_count.vox"), "XYZI chunk is truncated");
+        assert_eq!(error("huge_chunk_size.vox"), "vox chunk is truncated");
+        assert!(VoxFile::parse(b"VOX ").is_err());
+        assert!(VoxFile::parse(b"not a vox file at all").is_err());
+    }
+
+    #[test]
+    fn refuses_models_too_big_for_a_schematic()
+    {
+        let file = fixture("huge_model.vox").unwrap();
+        assert_eq!(file.to_schematic(0, &VoxMaterialMap::default()).unwrap_err().to_string(), "schematic is too big");
+    }
+}
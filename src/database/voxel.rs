@@ -1,17 +1,11 @@
-pub const FACES: [[f32; 3]; 6] = [[0.0,0.0,-1.0],[0.0,0.0,1.0],[0.0,1.0,0.0],[0.0,-1.0,0.0],[-1.0,0.0,0.0],[1.0,0.0,0.0]];
-pub const BLOCK_VERTS: [[f32; 3]; 8] = [[0.0,0.0,0.0],[1.0,0.0,0.0],[1.0,1.0,0.0],[0.0,1.0,0.0],[0.0,0.0,1.0],[1.0,0.0,1.0],[0.0,1.0,1.0],[1.0,1.0,1.0]];
-pub const SLAB_VERTS: [[f32; 3]; 8] = [[0.0,0.0,0.0],[1.0,0.0,0.0],[1.0,0.5,0.0],[0.0,0.5,0.0],[0.0,0.0,1.0],[1.0,0.0,1.0],[0.0,0.5,1.0],[1.0,0.5,1.0]];
-pub const STAIR_VERTS: [[f32; 3]; 12] = [[0.0,0.0,0.0],[1.0,0.0,0.0],[0.0,0.5,0.0],[1.0,0.5,0.0],[0.0,0.5,0.5],[1.0,0.5,0.5],[0.0,1.0,0.5],[1.0,1.0,0.5],[0.0,0.0,1.0],[1.0,0.0,1.0],[0.0,1.0,1.0],[1.0,1.0,1.0]];
-pub const RECTANGLE_TRIS: [[usize; 6]; 6] = [[0,3,1,1,3,2],[5,7,4,4,7,6],[3,6,2,2,6,7],[1,5,0,0,5,4],[4,6,0,0,6,3],[1,2,5,5,2,7]];
-pub const STAIR_TRIS: [&'static [usize]; 6] = [/*front face*/&[/*bottom*/0,2,1,1,2,3,/*top*/4,6,5,5,6,7],/*back face*/&[9,11,8,8,11,10],/*top face*/&[/*top*/6,10,7,7,10,11,/*bottom*/2,4,3,3,4,5],/*bottom face*/&[8,0,9,9,0,1],/*left face*/&[8,10,0,0,4,2,4,10,6],/*right face*/&[1,11,9,1,3,5,5,7,11]];
-pub const STAIR_UVS: [&'static [Vec2]; 6] = [/*front face*/&[/*bottom*/vec2(1.0,1.0),vec2(1.0,0.5),vec2(0.0,1.0),vec2(0.0,1.0),vec2(1.0,0.5),vec2(0.0,0.5),/*top*/vec2(1.0,0.5),vec2(1.0,0.0),vec2(0.0,0.5),vec2(0.0,0.5),vec2(1.0,0.0),vec2(0.0,0.0)],/*back face*/&[vec2(0.0,1.0),vec2(0.0,0.0),vec2(1.0,1.0),vec2(1.0,1.0),vec2(0.0,0.0),vec2(1.0,0.0)],/*top face*/&[/*top*/vec2(1.0,0.5),vec2(1.0,0.0),vec2(0.0,0.5),vec2(0.0,0.5),vec2(1.0,0.0),vec2(0.0,0.0),/*bottom*/vec2(1.0,1.0),vec2(1.0,0.5),vec2(0.0,1.0),vec2(0.0,1.0),vec2(1.0,0.5),vec2(0.0,0.5)],/*bottom face*/&[vec2(1.0,0.0),vec2(1.0,1.0),vec2(0.0,0.0),vec2(0.0,0.0),vec2(1.0,1.0),vec2(0.0,1.0)],/*left face*/&[vec2(0.0,1.0),vec2(0.0,0.0),vec2(1.0,1.0),vec2(1.0,1.0),vec2(0.5,0.5),vec2(1.0,0.5),vec2(0.5,0.5),vec2(0.0,0.0),vec2(0.5,0.0)],/*right face*/&[vec2(1.0,1.0),vec2(0.0,0.0),vec2(0.0,1.0),vec2(1.0,1.0),vec2(1.0,0.5),vec2(0.5,0.5),vec2(0.5,0.5),vec2(0.5,0.0),vec2(0.0,0.0)]];
-pub const SLAB_UVS: [[Vec2; 6]; 6] = [/*front face*/[vec2(1.0,1.0),vec2(1.0,0.5),vec2(0.0,1.0),vec2(0.0,1.0),vec2(1.0,0.5),vec2(0.0,0.5)],/*back face*/[vec2(0.0,1.0),vec2(0.0,0.5),vec2(1.0,1.0),vec2(1.0,1.0),vec2(0.0,0.5),vec2(1.0,0.5)],/*top face*/[vec2(1.0,1.0),vec2(1.0,0.0),vec2(0.0,1.0),vec2(0.0,1.0),vec2(1.0,0.0),vec2(0.0,0.0)],/*bottom face*/[vec2(0.0,1.0),vec2(0.0,0.0),vec2(1.0,1.0),vec2(1.0,1.0),vec2(0.0,0.0),vec2(1.0,0.0)],/*left face*/[vec2(0.0,1.0),vec2(0.0,0.5),vec2(1.0,1.0),vec2(1.0,1.0),vec2(0.0,0.5),vec2(1.0,0.5)],/*right face*/[vec2(1.0,1.0),vec2(1.0,0.5),vec2(0.0,1.0),vec2(0.0,1.0),vec2(1.0,0.5),vec2(0.0,0.5)]];
 pub const VOXEL_ROTATIONS: [Rotation; 4] = [/*Forward*/Rotation{switch:false,values:[1.0,1.0]},/*Backward*/Rotation{switch:false,values:[-1.0,-1.0]},/*Left*/Rotation{switch:true,values:[1.0,-1.0]},/*Right*/Rotation{switch:true,values:[-1.0,1.0]}];
-use bevy::math::vec2;
+use crate::database::edit::VoxelAccess;
 use bevy::prelude::*;
+use serde::Deserialize;
 use noise::{NoiseFn, Perlin, Seedable, Fbm, MultiFractal};
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum VoxelType {
     #[default]
     Air,
@@ -49,14 +43,59 @@ pub enum VoxelType {
     Cat,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+impl VoxelType
+{
+    //every voxel type in declaration order, so voxel_type as usize indexes into it
+    pub const ALL: [VoxelType; 33] = [
+        VoxelType::Air, VoxelType::Stone, VoxelType::Dirt, VoxelType::Sand, VoxelType::Brick, VoxelType::Grass, VoxelType::Lava,
+        VoxelType::Water, VoxelType::Salt, VoxelType::Ash, VoxelType::RedSand, VoxelType::Coral, VoxelType::Sulfur, VoxelType::JungleGrass,
+        VoxelType::SavannahGrass, VoxelType::SwampGrass, VoxelType::Ice, VoxelType::SnowBlock, VoxelType::Snow, VoxelType::Pine,
+        VoxelType::Forest, VoxelType::Glass, VoxelType::Sexy, VoxelType::Rainbow, VoxelType::StoneBrick, VoxelType::Arrow,
+        VoxelType::Netherack, VoxelType::Arsenic, VoxelType::Actinium, VoxelType::Antimony, VoxelType::Aluminum, VoxelType::Copper,
+        VoxelType::Cat,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum VoxelVariant {
     #[default]
     Block,
     Slab,
     Stair,
+    //two crossed quads, for plants
+    Cross,
+    //post with rails to neighbouring fences and solid blocks
+    Fence,
+    //thin wall joining neighbouring panes and solid blocks
+    Pane,
+    Pillar,
+    //half a voxel standing against the side it faces
+    VerticalSlab,
 }
 
+impl VoxelVariant
+{
+    pub const ALL: [VoxelVariant; 8] = [VoxelVariant::Block, VoxelVariant::Slab, VoxelVariant::Stair, VoxelVariant::Cross, VoxelVariant::Fence, VoxelVariant::Pane, VoxelVariant::Pillar, VoxelVariant::VerticalSlab];
+
+    //name of the variant's block model, used unless a model registers itself for the voxel type
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            VoxelVariant::Block => "block",
+            VoxelVariant::Slab => "slab",
+            VoxelVariant::Stair => "stair",
+            VoxelVariant::Cross => "cross",
+            VoxelVariant::Fence => "fence",
+            VoxelVariant::Pane => "pane",
+            VoxelVariant::Pillar => "pillar",
+            VoxelVariant::VerticalSlab => "vertical_slab",
+        }
+    }
+}
+
+//the direction a voxel's model +z side is turned to, stairs rise towards it and vertical slabs stand against it
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub enum VoxelRotation {
     #[default]
@@ -64,6 +103,153 @@ pub enum VoxelRotation {
     Backward,
     Left,
     Right,
+    Up,
+    Down,
+}
+
+impl VoxelRotation
+{
+    pub const ALL: [VoxelRotation; 6] = [VoxelRotation::Forward, VoxelRotation::Backward, VoxelRotation::Left, VoxelRotation::Right, VoxelRotation::Up, VoxelRotation::Down];
+
+    //the facing pointing along an axis direction, None for anything else
+    pub fn from_direction(direction: IVec3) -> Option<VoxelRotation>
+    {
+        VoxelRotation::ALL.into_iter().find(|rotation| rotation.direction() == direction)
+    }
+
+    pub fn direction(self) -> IVec3
+    {
+        self.rotate_direction(IVec3::Z)
+    }
+
+    pub fn is_horizontal(self) -> bool
+    {
+        !matches!(self, VoxelRotation::Up | VoxelRotation::Down)
+    }
+
+    //the same facing after a quarter turn around the y axis, forward(+z) goes to right(-x). up and down stay
+    pub fn rotate_y(self) -> VoxelRotation
+    {
+        match self
+        {
+            VoxelRotation::Forward => VoxelRotation::Right,
+            VoxelRotation::Right => VoxelRotation::Backward,
+            VoxelRotation::Backward => VoxelRotation::Left,
+            VoxelRotation::Left => VoxelRotation::Forward,
+            other => other,
+        }
+    }
+
+    //turns a point in the voxel's local 0..1 space from the forward facing to this one
+    //up turns +z to +y and +y to -z, down turns +z to -y and +y to +z
+    pub fn rotate_point(self, point: Vec3) -> Vec3
+    {
+        match self
+        {
+            VoxelRotation::Up => Vec3::new(point.x, point.z, 1.0 - point.y),
+            VoxelRotation::Down => Vec3::new(point.x, 1.0 - point.z, point.y),
+            _ =>
+            {
+                let rotation = &VOXEL_ROTATIONS[self as usize];
+                let (x, z) = if rotation.switch { (point.z, point.x) } else { (point.x, point.z) };
+                Vec3::new((x - 0.5) * rotation.values[0] + 0.5, point.y, (z - 0.5) * rotation.values[1] + 0.5)
+            }
+        }
+    }
+
+    pub fn rotate_direction(self, direction: IVec3) -> IVec3
+    {
+        match self
+        {
+            VoxelRotation::Up => IVec3::new(direction.x, direction.z, -direction.y),
+            VoxelRotation::Down => IVec3::new(direction.x, -direction.z, direction.y),
+            _ =>
+            {
+                let rotation = &VOXEL_ROTATIONS[self as usize];
+                let (x, z) = if rotation.switch { (direction.z, direction.x) } else { (direction.x, direction.z) };
+                IVec3::new(x * rotation.values[0] as i32, direction.y, z * rotation.values[1] as i32)
+            }
+        }
+    }
+
+    //the same facing mirrored along the x axis
+    pub fn mirror_x(self) -> VoxelRotation
+    {
+        match self
+        {
+            VoxelRotation::Left => VoxelRotation::Right,
+            VoxelRotation::Right => VoxelRotation::Left,
+            other => other,
+        }
+    }
+}
+
+//which half of the voxel slabs and stairs sit in, top halves are the model mirrored upside down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum VoxelHalf {
+    #[default]
+    Bottom,
+    Top,
+}
+
+//the corner a stair makes with the stairs around it, never stored, always worked out from the neighbours like minecraft does
+//left is the side the facing turns to counter clockwise seen from above, +x for a forward stair
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StairShape {
+    #[default]
+    Straight,
+    InnerLeft,
+    InnerRight,
+    OuterLeft,
+    OuterRight,
+}
+
+impl StairShape
+{
+    //added to the name of a stair's block model, right corners use the left model turned a quarter
+    pub fn model_suffix(self) -> &'static str
+    {
+        match self
+        {
+            StairShape::Straight => "",
+            StairShape::InnerLeft | StairShape::InnerRight => "_inner",
+            StairShape::OuterLeft | StairShape::OuterRight => "_outer",
+        }
+    }
+
+    pub fn is_right(self) -> bool
+    {
+        matches!(self, StairShape::InnerRight | StairShape::OuterRight)
+    }
+}
+
+//how a voxel's model is placed inside the voxel, the half is applied before the rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelOrientation
+{
+    pub rotation: VoxelRotation,
+    pub half: VoxelHalf,
+}
+
+impl VoxelOrientation
+{
+    pub fn point(self, point: Vec3) -> Vec3
+    {
+        let point = if self.half == VoxelHalf::Top { Vec3::new(point.x, 1.0 - point.y, point.z) } else { point };
+        self.rotation.rotate_point(point)
+    }
+
+    pub fn direction(self, direction: IVec3) -> IVec3
+    {
+        let direction = if self.half == VoxelHalf::Top { IVec3::new(direction.x, -direction.y, direction.z) } else { direction };
+        self.rotation.rotate_direction(direction)
+    }
+
+    //top halves are a mirror image so triangles have to be wound the other way round
+    pub fn is_mirrored(self) -> bool
+    {
+        self.half == VoxelHalf::Top
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -72,13 +258,119 @@ pub struct Rotation {
     pub values: [f32; 2]
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FlowDirection {
+    #[default]
+    Still,
+    Down,
+    PosX,
+    NegX,
+    PosZ,
+    NegZ,
+}
+
+impl FlowDirection
+{
+    pub const ALL: [FlowDirection; 6] = [FlowDirection::Still, FlowDirection::Down, FlowDirection::PosX, FlowDirection::NegX, FlowDirection::PosZ, FlowDirection::NegZ];
+}
+
+//state of water and lava inside a voxel, unused for every other voxel type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FluidState
+{
+    //0..=7, 7 is a full voxel
+    pub level: u8,
+    //source voxels never drain
+    pub source: bool,
+    pub flow: FlowDirection,
+}
+
+impl FluidState
+{
+    pub const NONE: FluidState = FluidState { level: 0, source: false, flow: FlowDirection::Still };
+    pub const SOURCE: FluidState = FluidState { level: 7, source: true, flow: FlowDirection::Still };
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Voxel
 {
     pub voxel_type: VoxelType,
     pub solid: bool,
     pub voxel_variant: VoxelVariant,
     pub voxel_rotation: VoxelRotation,
+    pub fluid: FluidState,
+    pub voxel_half: VoxelHalf,
+}
+
+impl Voxel
+{
+    //solid marks voxels that completely hide the faces next to them, only full blocks that are not see-through
+    pub fn new(voxel_type: VoxelType, voxel_variant: VoxelVariant, voxel_rotation: VoxelRotation) -> Voxel
+    {
+        Voxel
+        {
+            voxel_type,
+            solid: voxel_type != VoxelType::Air && voxel_type != VoxelType::Glass && voxel_variant == VoxelVariant::Block,
+            voxel_variant,
+            voxel_rotation,
+            fluid: if matches!(voxel_type, VoxelType::Water | VoxelType::Lava) { FluidState::SOURCE } else { FluidState::NONE },
+            voxel_half: VoxelHalf::Bottom,
+        }
+    }
+
+    pub fn with_half(mut self, voxel_half: VoxelHalf) -> Voxel
+    {
+        self.voxel_half = voxel_half;
+        self
+    }
+
+    //where the model for the given stair shape goes, anything but a stair only ever uses the straight shape
+    pub fn orientation(&self, shape: StairShape) -> VoxelOrientation
+    {
+        let rotation = if shape.is_right() { self.voxel_rotation.rotate_y() } else { self.voxel_rotation };
+        VoxelOrientation { rotation, half: self.voxel_half }
+    }
+
+    pub fn is_fluid(&self) -> bool
+    {
+        matches!(self.voxel_type, VoxelType::Water | VoxelType::Lava)
+    }
+}
+
+fn is_stair_like(voxel: &Voxel, other: &Voxel) -> bool
+{
+    other.voxel_variant == VoxelVariant::Stair && other.voxel_half == voxel.voxel_half && other.voxel_rotation.is_horizontal()
+}
+
+//minecraft's stair shape rule. a stair behind this one facing across it makes an outer corner, one in front of it an
+//inner corner, unless the stair on that side already continues this one in a straight line
+pub fn stair_shape(world: &impl VoxelAccess, position: IVec3, voxel: &Voxel) -> StairShape
+{
+    if voxel.voxel_variant != VoxelVariant::Stair || !voxel.voxel_rotation.is_horizontal()
+    {
+        return StairShape::Straight;
+    }
+    let facing = voxel.voxel_rotation;
+    let left = facing.rotate_y().rotate_y().rotate_y();
+    //a stair on the given side that faces the same way, the corner would cut it off
+    let continues = |direction: IVec3| world.voxel(position + direction).is_some_and(|other| is_stair_like(voxel, other) && other.voxel_rotation == facing);
+    if let Some(behind) = world.voxel(position + facing.direction()).filter(|other| is_stair_like(voxel, other))
+    {
+        let turn = behind.voxel_rotation;
+        if turn.direction().dot(facing.direction()) == 0 && !continues(-turn.direction())
+        {
+            return if turn == left { StairShape::OuterLeft } else { StairShape::OuterRight };
+        }
+    }
+    if let Some(front) = world.voxel(position - facing.direction()).filter(|other| is_stair_like(voxel, other))
+    {
+        let turn = front.voxel_rotation;
+        if turn.direction().dot(facing.direction()) == 0 && !continues(turn.direction())
+        {
+            return if turn == left { StairShape::InnerLeft } else { StairShape::InnerRight };
+        }
+    }
+    StairShape::Straight
 }
 
 pub fn block_to_tex
@@ -91,3 +383,177 @@ pub fn block_to_tex
 {
     return Rect::new(tex_rects[tex_map[block_type as usize]].min.x/tex_size.x,tex_rects[tex_map[block_type as usize]].min.y/tex_size.y,tex_rects[tex_map[block_type as usize]].max.x/tex_size.x,tex_rects[tex_map[block_type as usize]].max.y/tex_size.y);
 }
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::database::edit::test_world;
+
+    //sides of the stair in the middle, back is where it rises towards
+    #[derive(Debug, Clone, Copy)]
+    enum Side
+    {
+        Back,
+        Front,
+        Left,
+        Right,
+    }
+
+    //which way a neighbour faces compared to the stair in the middle
+    #[derive(Debug, Clone, Copy)]
+    enum Turn
+    {
+        Same,
+        Opposite,
+        Left,
+        Right,
+        Up,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Neighbour
+    {
+        side: Side,
+        turn: Turn,
+        variant: VoxelVariant,
+        other_half: bool,
+    }
+
+    fn stair(side: Side, turn: Turn) -> Neighbour
+    {
+        Neighbour { side, turn, variant: VoxelVariant::Stair, other_half: false }
+    }
+
+    impl Neighbour
+    {
+        fn other_half(self) -> Neighbour
+        {
+            Neighbour { other_half: true, ..self }
+        }
+
+        fn block(self) -> Neighbour
+        {
+            Neighbour { variant: VoxelVariant::Block, ..self }
+        }
+    }
+
+    fn left_of(facing: VoxelRotation) -> VoxelRotation
+    {
+        facing.rotate_y().rotate_y().rotate_y()
+    }
+
+    //the shape of a stair at 10,10,10 with the given facing and half among the neighbours
+    fn shape(facing: VoxelRotation, half: VoxelHalf, neighbours: &[Neighbour]) -> StairShape
+    {
+        let center = IVec3::splat(10);
+        let other_half = if half == VoxelHalf::Top { VoxelHalf::Bottom } else { VoxelHalf::Top };
+        let mut voxels = Vec::new();
+        for neighbour in neighbours
+        {
+            let offset = match neighbour.side
+            {
+                Side::Back => facing.direction(),
+                Side::Front => -facing.direction(),
+                Side::Left => left_of(facing).direction(),
+                Side::Right => -left_of(facing).direction(),
+            };
+            let rotation = match neighbour.turn
+            {
+                Turn::Same => facing,
+                Turn::Opposite => facing.rotate_y().rotate_y(),
+                Turn::Left => left_of(facing),
+                Turn::Right => facing.rotate_y(),
+                Turn::Up => VoxelRotation::Up,
+            };
+            let voxel = Voxel::new(VoxelType::Stone, neighbour.variant, rotation).with_half(if neighbour.other_half { other_half } else { half });
+            voxels.push((center + offset, voxel));
+        }
+        let voxel = Voxel::new(VoxelType::Stone, VoxelVariant::Stair, facing).with_half(half);
+        voxels.push((center, voxel.clone()));
+        let world = test_world([0, 0, 0], [0, 0, 0], &voxels);
+        stair_shape(&world, center, &voxel)
+    }
+
+    //minecraft's getStairsShape: the stair behind facing across makes an outer corner unless the stair on the side it
+    //turns away from continues this one, then the stair in front facing across makes an inner corner the same way
+    #[test]
+    fn stair_shapes_follow_minecraft()
+    {
+        use Side::*;
+        let cases: Vec<(Vec<Neighbour>, StairShape)> = vec![
+            (vec![], StairShape::Straight),
+            //perpendicular neighbours behind and in front make the corners
+            (vec![stair(Back, Turn::Left)], StairShape::OuterLeft),
+            (vec![stair(Back, Turn::Right)], StairShape::OuterRight),
+            (vec![stair(Front, Turn::Left)], StairShape::InnerLeft),
+            (vec![stair(Front, Turn::Right)], StairShape::InnerRight),
+            //parallel ones never do
+            (vec![stair(Back, Turn::Same)], StairShape::Straight),
+            (vec![stair(Back, Turn::Opposite)], StairShape::Straight),
+            (vec![stair(Front, Turn::Same)], StairShape::Straight),
+            (vec![stair(Front, Turn::Opposite)], StairShape::Straight),
+            (vec![stair(Back, Turn::Same), stair(Front, Turn::Left)], StairShape::InnerLeft),
+            //neither do the stairs beside it
+            (vec![stair(Left, Turn::Left)], StairShape::Straight),
+            (vec![stair(Right, Turn::Same)], StairShape::Straight),
+            //a stair continuing this one on the side the corner would cut off keeps it straight
+            (vec![stair(Back, Turn::Left), stair(Right, Turn::Same)], StairShape::Straight),
+            (vec![stair(Back, Turn::Left), stair(Left, Turn::Same)], StairShape::OuterLeft),
+            (vec![stair(Back, Turn::Right), stair(Left, Turn::Same)], StairShape::Straight),
+            (vec![stair(Front, Turn::Left), stair(Left, Turn::Same)], StairShape::Straight),
+            (vec![stair(Front, Turn::Left), stair(Right, Turn::Same)], StairShape::InnerLeft),
+            (vec![stair(Front, Turn::Right), stair(Right, Turn::Same)], StairShape::Straight),
+            (vec![stair(Back, Turn::Left), stair(Right, Turn::Opposite)], StairShape::OuterLeft),
+            //outer corners are checked first
+            (vec![stair(Back, Turn::Left), stair(Front, Turn::Right)], StairShape::OuterLeft),
+            //only stairs in the same half take part
+            (vec![stair(Back, Turn::Left).other_half()], StairShape::Straight),
+            (vec![stair(Front, Turn::Right).other_half()], StairShape::Straight),
+            (vec![stair(Back, Turn::Left), stair(Right, Turn::Same).other_half()], StairShape::OuterLeft),
+            (vec![stair(Back, Turn::Left).block()], StairShape::Straight),
+            (vec![stair(Back, Turn::Up)], StairShape::Straight),
+        ];
+        for facing in [VoxelRotation::Forward, VoxelRotation::Backward, VoxelRotation::Left, VoxelRotation::Right]
+        {
+            for half in [VoxelHalf::Bottom, VoxelHalf::Top]
+            {
+                for (neighbours, expected) in &cases
+                {
+                    assert_eq!(shape(facing, half, neighbours), *expected, "{facing:?} {half:?} {neighbours:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn left_is_counter_clockwise_from_above()
+    {
+        //minecraft turns south (+z) counter clockwise to east (+x)
+        assert_eq!(left_of(VoxelRotation::Forward).direction(), IVec3::X);
+        for facing in [VoxelRotation::Forward, VoxelRotation::Backward, VoxelRotation::Left, VoxelRotation::Right]
+        {
+            assert_eq!(left_of(facing).direction(), IVec3::Y.cross(facing.direction()));
+        }
+    }
+
+    #[test]
+    fn upright_stairs_stay_straight()
+    {
+        for facing in [VoxelRotation::Up, VoxelRotation::Down]
+        {
+            let voxel = Voxel::new(VoxelType::Stone, VoxelVariant::Stair, facing);
+            let neighbours: Vec<(IVec3, Voxel)> = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z, IVec3::Y, IVec3::NEG_Y].iter()
+                .map(|offset| (IVec3::splat(10) + *offset, Voxel::new(VoxelType::Stone, VoxelVariant::Stair, VoxelRotation::Left)))
+                .chain([(IVec3::splat(10), voxel.clone())])
+                .collect();
+            let world = test_world([0, 0, 0], [0, 0, 0], &neighbours);
+            assert_eq!(stair_shape(&world, IVec3::splat(10), &voxel), StairShape::Straight);
+        }
+        //anything but a stair has no corners
+        let slab = Voxel::new(VoxelType::Stone, VoxelVariant::Slab, VoxelRotation::Forward);
+        let world = test_world([0, 0, 0], [0, 0, 0], &[(IVec3::new(10, 10, 11), Voxel::new(VoxelType::Stone, VoxelVariant::Stair, VoxelRotation::Left))]);
+        assert_eq!(stair_shape(&world, IVec3::splat(10), &slab), StairShape::Straight);
+    }
+}
@@ -3,6 +3,20 @@ use crate::database::structs::AppState;
 use crate::database::voxel::*;
 use crate::database::chunk::*;
 use crate::database::sector::*;
+use crate::database::events::*;
+use crate::database::edit::*;
+use crate::database::physics::*;
+use crate::database::interaction::*;
+use crate::database::fluid::*;
+use crate::database::scheduler::*;
+use crate::database::gravity::*;
+use crate::database::bulk::*;
+use crate::database::history::*;
+use crate::database::generator::*;
+use crate::database::lod::*;
+use crate::database::mesher::*;
+use crate::database::model::*;
+use crate::database::texture_array::VoxelArrayMaterial;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 mod database;
 use std::fs;
@@ -23,12 +37,35 @@ use bevy::window::{WindowMode};
 use bevy_flycam::prelude::*;
 
 fn main() {
+    //headless tools like exporting regions run instead of the game
+    if database::cli::run_from_args()
+    {
+        return;
+    }
     App::new()
         .init_resource::<TextureHandles>()
         .init_resource::<TextureAtlasBuilt>()
         .init_resource::<Universe>()
         .init_resource::<CurrentlyLoaded>()
         .init_resource::<ChunkEntitys>()
+        .init_resource::<LoaderChunks>()
+        .init_resource::<DirtyChunks>()
+        .init_resource::<FluidSimulation>()
+        .init_resource::<BlockUpdates>()
+        .init_resource::<RandomTicks>()
+        .init_resource::<EditHistory>()
+        .insert_resource(configured_generator())
+        .init_resource::<LodSettings>()
+        .insert_resource(configured_meshers())
+        .insert_resource(configured_block_models())
+        .add_event::<ChunkGenerated>()
+        .add_event::<ChunkMeshed>()
+        .add_event::<ChunkUnloaded>()
+        .add_event::<VoxelChanged>()
+        .add_event::<SetVoxel>()
+        .add_event::<BulkEdit>()
+        .add_event::<BulkEditApplied>()
+        .add_event::<HistoryCommand>()
         .add_state::<AppState>()
         .add_plugins((
             DefaultPlugins.set(ImagePlugin::default_nearest()).set(WindowPlugin {
@@ -41,15 +78,24 @@ fn main() {
             }),
             WireframePlugin,
             TemporalAntiAliasPlugin,
+            MaterialPlugin::<VoxelArrayMaterial>::default(),
+            MaterialPlugin::<VoxelAtlasMaterial>::default(),
         ))
         .add_plugins(WorldInspectorPlugin::new())
         .add_plugins(PlayerPlugin)
+        .add_plugins(InteractionPlugin)
         .add_systems(OnEnter(AppState::Setup), load_textures)
         .add_systems(Update, check_textures.run_if(in_state(AppState::Setup)))
         .add_systems(OnEnter(AppState::Finished), build_texture_atlas)
         .add_systems(OnEnter(AppState::Generating), generate_planet)
+        .add_systems(Update, (attach_flycam_loaders, attach_flycam_interactors))
         .add_systems(Update, load_chunks.run_if(in_state(AppState::Generating)))
         .add_systems(Update, unload_chunks.run_if(in_state(AppState::Generating)).run_if(on_timer(Duration::from_secs(1))))
-        .add_systems(Update, chunk_handler.run_if(in_state(AppState::Generating)))
+        .add_systems(Update, update_chunk_lods.after(load_chunks).before(chunk_handler).run_if(in_state(AppState::Generating)))
+        .add_systems(Update, chunk_handler.after(apply_history_commands).run_if(in_state(AppState::Generating)))
+        .add_systems(Update, (apply_voxel_edits, apply_bulk_edits, record_edit_history, apply_history_commands, (collect_block_updates, track_modified_chunks), (schedule_fluid_updates, start_falling_blocks)).chain().run_if(in_state(AppState::Generating)))
+        .add_systems(Update, (simulate_fluids, update_falling_blocks, random_tick).before(apply_voxel_edits).run_if(in_state(AppState::Generating)))
+        .add_systems(Update, (keyboard_character_input, character_controller).chain().run_if(in_state(AppState::Generating)))
+        .add_systems(Update, keyboard_history_input.before(apply_history_commands).run_if(in_state(AppState::Generating)))
         .run();
 }